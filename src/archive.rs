@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// yt-dlp style download archive: one `bilibili <bvid>_<cid>` line per finished download.
+#[derive(Debug)]
+pub struct DownloadArchive {
+    path: PathBuf,
+    entries: HashSet<String>,
+}
+
+impl DownloadArchive {
+    /// Loads the archive at `path`; a missing file is treated as empty.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = HashSet::new();
+        if path.exists() {
+            let file = File::open(&path).context("open download archive")?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                let t = line.trim();
+                if !t.is_empty() { entries.insert(t.to_string()); }
+            }
+        }
+        Ok(Self { path, entries })
+    }
+
+    pub fn key(bvid: &str, cid: u64) -> String {
+        format!("bilibili {}_{}", bvid, cid)
+    }
+
+    pub fn contains(&self, bvid: &str, cid: u64) -> bool {
+        self.entries.contains(&Self::key(bvid, cid))
+    }
+
+    /// Appends an entry and flushes it right away so an interrupted run keeps its progress.
+    pub fn record(&mut self, bvid: &str, cid: u64) -> Result<()> {
+        let key = Self::key(bvid, cid);
+        if self.entries.contains(&key) { return Ok(()); }
+        if let Some(parent) = self.path.parent() && !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context("open download archive for append")?;
+        writeln!(f, "{}", key)?;
+        f.flush()?;
+        self.entries.insert(key);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+}
@@ -0,0 +1,192 @@
+use crate::bilibili::{av_to_bv, BiliClient, PlayUrlResp, VideoPage};
+use crate::util::parse_page_spec;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// Bangumi / PGC identifiers found in `/bangumi/play/ep..`, `ss..` and `/bangumi/media/md..` links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PgcId {
+    Episode(u64),
+    Season(u64),
+    Media(u64),
+}
+
+/// Season and episode details attached to a bangumi [`VideoPage`].
+#[derive(Debug, Clone, Serialize)]
+pub struct EpisodeInfo {
+    pub ep_id: u64,
+    pub season_id: u64,
+    pub season_title: String,
+    /// 1-based position within the season's main episodes
+    pub episode_number: u32,
+    /// Episode title, e.g. "1 Long Title"
+    pub episode_title: String,
+}
+
+pub fn extract_pgc_id(input: &str) -> Option<PgcId> {
+    let re = Regex::new(r"(?i)(?:^|/)(ep|ss|md)(\d+)(?:$|[/?#])").ok()?;
+    let caps = re.captures(input)?;
+    let id = caps[2].parse::<u64>().ok()?;
+    match caps[1].to_ascii_lowercase().as_str() {
+        "ep" => Some(PgcId::Episode(id)),
+        "ss" => Some(PgcId::Season(id)),
+        "md" => Some(PgcId::Media(id)),
+        _ => None,
+    }
+}
+
+impl BiliClient {
+    /// Resolves a bangumi link to its episodes. An `ep` link selects that episode;
+    /// `ss`/`md` links select the whole season. `page_spec` picks episodes by position.
+    pub async fn resolve_bangumi(&self, id: PgcId, page_spec: Option<&str>, all_pages: bool) -> Result<Vec<VideoPage>> {
+        let season = match id {
+            PgcId::Episode(ep) => self.get_pgc_season(&[("ep_id", ep.to_string())]).await?,
+            PgcId::Season(ss) => self.get_pgc_season(&[("season_id", ss.to_string())]).await?,
+            PgcId::Media(md) => {
+                let ss = self.get_pgc_media_season(md).await?;
+                self.get_pgc_season(&[("season_id", ss.to_string())]).await?
+            }
+        };
+        let total = season.episodes.len() as u32;
+        if total == 0 {
+            return Err(anyhow!("season {} has no episodes", season.season_id));
+        }
+        let selected: Vec<u32> = if all_pages {
+            (1..=total).collect()
+        } else if let Some(spec) = page_spec {
+            parse_page_spec(spec, total)?
+        } else if let PgcId::Episode(ep) = id {
+            let pos = season
+                .episodes
+                .iter()
+                .position(|e| e.id == ep)
+                .ok_or_else(|| anyhow!("episode ep{} not found in season {}", ep, season.season_id))?;
+            vec![pos as u32 + 1]
+        } else {
+            (1..=total).collect()
+        };
+
+        let season_title = if season.season_title.is_empty() { season.title.clone() } else { season.season_title.clone() };
+        Ok(selected
+            .into_iter()
+            .filter_map(|n| season.episodes.get((n - 1) as usize).map(|e| (n, e)))
+            .map(|(n, e)| {
+                let episode_title = if e.long_title.is_empty() { e.title.clone() } else { format!("{} {}", e.title, e.long_title) };
+                VideoPage {
+                    bvid: if e.bvid.is_empty() { av_to_bv(e.aid) } else { e.bvid.clone() },
+                    aid: e.aid,
+                    cid: e.cid,
+                    page: n,
+                    title: season_title.clone(),
+                    page_title: episode_title.clone(),
+                    page_count: total,
+                    duration: e.duration / 1000,
+                    episode: Some(EpisodeInfo {
+                        ep_id: e.id,
+                        season_id: season.season_id,
+                        season_title: season_title.clone(),
+                        episode_number: n,
+                        episode_title,
+                    }),
+                }
+            })
+            .collect())
+    }
+
+    /// PGC counterpart of `get_playurl` (`pgc/player/web/playurl`), keyed by episode id.
+    pub async fn get_pgc_playurl(&self, ep_id: u64, cid: u64, quality: Option<u32>, fnval: u32) -> Result<PlayUrlResp> {
+        let mut params = vec![
+            ("ep_id", ep_id.to_string()),
+            ("cid", cid.to_string()),
+            ("fnval", fnval.to_string()),
+            ("fnver", "0".to_string()),
+            ("fourk", "1".to_string()),
+        ];
+        if let Some(qn) = quality {
+            params.push(("qn", qn.to_string()));
+        }
+        let url = Url::parse_with_params("https://api.bilibili.com/pgc/player/web/playurl", &params)?;
+        let parsed: PlayUrlResp = self.get_json_retry(url).await?;
+        if parsed.code != 0 {
+            return Err(anyhow!(
+                "pgc playurl api error code {}: {}",
+                parsed.code,
+                parsed.message.clone().unwrap_or_default()
+            ));
+        }
+        Ok(parsed)
+    }
+
+    async fn get_pgc_season(&self, query: &[(&str, String)]) -> Result<PgcSeason> {
+        let url = Url::parse_with_params("https://api.bilibili.com/pgc/view/web/season", query)?;
+        let resp: PgcResp<PgcSeason> = self.get_json_retry(url).await?;
+        resp.into_result("season")
+    }
+
+    async fn get_pgc_media_season(&self, media_id: u64) -> Result<u64> {
+        let url = Url::parse_with_params(
+            "https://api.bilibili.com/pgc/review/user",
+            &[("media_id", media_id.to_string())],
+        )?;
+        let resp: PgcResp<PgcMediaResult> = self.get_json_retry(url).await?;
+        Ok(resp.into_result("media")?.media.season_id)
+    }
+}
+
+// ==== Types ====
+
+#[derive(Debug, Deserialize)]
+struct PgcResp<T> {
+    code: i32,
+    message: Option<String>,
+    result: Option<T>,
+}
+
+impl<T> PgcResp<T> {
+    fn into_result(self, what: &str) -> Result<T> {
+        if self.code != 0 {
+            return Err(anyhow!("pgc {} api error code {}: {}", what, self.code, self.message.unwrap_or_default()));
+        }
+        self.result.ok_or_else(|| anyhow!("pgc {} result missing", what))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PgcSeason {
+    season_id: u64,
+    #[serde(default)]
+    season_title: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    episodes: Vec<PgcEpisode>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PgcEpisode {
+    /// Episode id (the number in `ep..` links)
+    id: u64,
+    aid: u64,
+    #[serde(default)]
+    bvid: String,
+    cid: u64,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    long_title: String,
+    /// Duration in milliseconds
+    #[serde(default)]
+    duration: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PgcMediaResult {
+    media: PgcMedia,
+}
+
+#[derive(Debug, Deserialize)]
+struct PgcMedia {
+    season_id: u64,
+}
@@ -1,11 +1,15 @@
+use crate::bangumi::{extract_pgc_id, EpisodeInfo};
+use crate::subtitles::SubtitleTrack;
+use crate::util::parse_page_spec;
 use crate::wbi::WbiSigner;
 use anyhow::{anyhow, Context, Result};
 use regex::Regex;
 use reqwest::header::{HeaderMap, HeaderValue, REFERER, USER_AGENT};
 use reqwest::{Client, Proxy, Url};
 use reqwest_cookie_store::{CookieStore, CookieStoreMutex, RawCookie};
-use std::sync::Arc;
-use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use tokio::time::{sleep, Duration};
@@ -15,8 +19,12 @@ pub struct BiliClient {
     http: Client,
     cookie_header: Option<String>,
     jar: Option<Arc<CookieStoreMutex>>,
+    // nav keys are shared by every signed request; refreshed after WBI_KEY_TTL
+    wbi: Arc<Mutex<Option<(Instant, WbiSigner)>>>,
 }
 
+const WBI_KEY_TTL: Duration = Duration::from_secs(30 * 60);
+
 impl BiliClient {
     pub fn new(user_agent: String, referer: String, cookies: Option<String>, proxy: Option<String>) -> Result<Self> {
         let mut headers = HeaderMap::new();
@@ -54,16 +62,14 @@ impl BiliClient {
             // Also load cookies into a shared cookie jar
             let store = CookieStore::default();
             let jar_arc = Arc::new(CookieStoreMutex::new(store));
-            if let Ok(cnt) = load_netscape_into_jar(&jar_arc, &path) {
-                if cnt > 0 {
-                    builder = builder.cookie_provider(jar_arc.clone());
-                    jar = Some(jar_arc);
-                }
+            if let Ok(cnt) = load_netscape_into_jar(&jar_arc, &path) && cnt > 0 {
+                builder = builder.cookie_provider(jar_arc.clone());
+                jar = Some(jar_arc);
             }
         }
 
         let http = builder.build()?;
-        Ok(Self { http, cookie_header, jar })
+        Ok(Self { http, cookie_header, jar, wbi: Arc::new(Mutex::new(None)) })
     }
 
     pub fn new_with_jar(user_agent: String, referer: String, proxy: Option<String>, jar: Option<Arc<CookieStoreMutex>>, cookie_header: Option<String>) -> Result<Self> {
@@ -91,23 +97,17 @@ impl BiliClient {
         if let Some(p) = proxy { builder = builder.proxy(Proxy::all(&p)?); }
         if let Some(ref j) = jar { builder = builder.cookie_provider(j.clone()); }
         let http = builder.build()?;
-        Ok(Self { http, cookie_header, jar })
+        Ok(Self { http, cookie_header, jar, wbi: Arc::new(Mutex::new(None)) })
     }
 
     pub async fn resolve_bvid_and_cid(&self, input: &str, page: u32) -> Result<(String, u64)> {
-        let (bvid, page_from_url) = self
-            .parse_bvid_and_page(input)
-            .await
-            .context("parse input")?;
+        let target = self.expand_short_link(input).await.context("parse input")?;
+        let (bvid, page_from_url) = parse_bvid_and_page(&target).context("parse input")?;
+        let page_from_url = extract_page_param(input).or(page_from_url);
         // if URL had ?p=, use it unless user passed -p (we can't detect explicit flag; use heuristic: if page==1 and URL has p>0, use it)
         let page = if page == 1 { page_from_url.unwrap_or(1) } else { page };
         // fetch view for cids
-        let url = Url::parse_with_params(
-            "https://api.bilibili.com/x/web-interface/view",
-            &[("bvid", bvid.clone())],
-        )?;
-        let view: ViewResp = self.get_json_retry(url).await?;
-        let data = view.data.ok_or_else(|| anyhow!("view data missing"))?;
+        let data = self.get_view(&bvid).await?;
         let idx = (page.saturating_sub(1)) as usize;
         let page_item = data
             .pages
@@ -116,35 +116,75 @@ impl BiliClient {
         Ok((bvid, page_item.cid))
     }
 
-    async fn parse_bvid_and_page(&self, input: &str) -> Result<(String, Option<u32>)> {
-        if let Some(bv) = extract_bvid(input) {
-            let p = extract_page_param(input);
-            return Ok((bv, p));
-        }
-        // Try as URL: follow redirects (for b23.tv) and then extract
-        if let Ok(mut url) = Url::parse(input) {
-            // Quick page param before request
-            let p = url
-                .query_pairs()
-                .find(|(k, _)| k == "p")
-                .and_then(|(_, v)| v.parse::<u32>().ok());
-            let resp = self.http.get(url.clone()).send().await?;
-            url = resp.url().clone();
-            if let Some(bv) = extract_bvid(url.as_str()) {
-                return Ok((bv, p));
-            }
+    /// Resolves an input to the pages selected by `page_spec` (e.g. `1-5,8,10-`).
+    /// Without a spec, the `?p=` of the URL (or page 1) is used; `all_pages` selects every page.
+    pub async fn resolve_pages(&self, input: &str, page_spec: Option<&str>, all_pages: bool) -> Result<Vec<VideoPage>> {
+        let target = self.expand_short_link(input).await.context("parse input")?;
+        self.resolve_expanded_pages(&target, page_spec, all_pages).await
+    }
+
+    /// [`BiliClient::resolve_pages`] for a target whose short link was already expanded.
+    pub(crate) async fn resolve_expanded_pages(&self, target: &str, page_spec: Option<&str>, all_pages: bool) -> Result<Vec<VideoPage>> {
+        if let Some(id) = extract_pgc_id(target) {
+            return self.resolve_bangumi(id, page_spec, all_pages).await;
         }
-        Err(anyhow!("BV id not found in input"))
+        let (bvid, page_from_url) = parse_bvid_and_page(target).context("parse input")?;
+        let data = self.get_view(&bvid).await?;
+        pages_from_view(&bvid, &data, page_from_url, page_spec, all_pages)
     }
 
-    pub async fn get_title(&self, bvid: &str) -> Result<String> {
+    pub(crate) async fn get_view(&self, bvid: &str) -> Result<VideoInfo> {
         let url = Url::parse_with_params(
             "https://api.bilibili.com/x/web-interface/view",
             &[("bvid", bvid.to_string())],
         )?;
         let view: ViewResp = self.get_json_retry(url).await?;
-        let data = view.data.ok_or_else(|| anyhow!("view data missing"))?;
-        Ok(data.title)
+        view.data.ok_or_else(|| anyhow!("view data missing"))
+    }
+
+    /// Full video metadata, including tags (best-effort, fetched separately).
+    pub async fn get_video_info(&self, bvid: &str) -> Result<VideoInfo> {
+        let mut info = self.get_view(bvid).await?;
+        let url = Url::parse_with_params(
+            "https://api.bilibili.com/x/tag/archive/tags",
+            &[("bvid", bvid.to_string())],
+        )?;
+        if let Ok(tags) = self.get_json_retry::<TagsResp>(url).await {
+            info.tags = tags.data.unwrap_or_default().into_iter().map(|t| t.tag_name).collect();
+        }
+        Ok(info)
+    }
+
+    /// Per-page player info from the WBI-signed `x/player/wbi/v2` endpoint: subtitle tracks and chapters.
+    pub async fn get_player_info(&self, aid: u64, cid: u64) -> Result<PlayerInfo> {
+        let params = vec![
+            ("aid".to_string(), aid.to_string()),
+            ("cid".to_string(), cid.to_string()),
+        ];
+        let url = self.wbi_signed_url("https://api.bilibili.com/x/player/wbi/v2", params).await?;
+        let resp: PlayerResp = self.get_json_retry(url).await?;
+        if resp.code != 0 {
+            return Err(anyhow!("player api error code {}: {}", resp.code, resp.message.unwrap_or_default()));
+        }
+        Ok(resp.data.unwrap_or_default())
+    }
+
+    /// Follows redirects (for b23.tv) when the input has no recognizable id, returning the final URL.
+    pub(crate) async fn expand_short_link(&self, input: &str) -> Result<String> {
+        if extract_bvid(input).is_some() || extract_aid(input).is_some() || extract_pgc_id(input).is_some() {
+            return Ok(input.to_string());
+        }
+        match Url::parse(input) {
+            Ok(url) => {
+                let resp = self.http.get(url).send().await?;
+                Ok(resp.url().to_string())
+            }
+            Err(_) => Ok(input.to_string()),
+        }
+    }
+
+    pub async fn get_title(&self, bvid: &str) -> Result<String> {
+        Ok(self.get_view(bvid).await?.title)
     }
 
     pub async fn get_playurl(
@@ -155,7 +195,6 @@ impl BiliClient {
         fnval: u32,
     )
     -> Result<PlayUrlResp> {
-        let signer = WbiSigner::fetch(&self.http).await?;
         let mut params = vec![
             ("bvid".to_string(), bvid.to_string()),
             ("cid".to_string(), cid.to_string()),
@@ -166,15 +205,7 @@ impl BiliClient {
         if let Some(qn) = quality {
             params.push(("qn".into(), qn.to_string()));
         }
-        let (params, _wts, w_rid) = signer.sign(params);
-        let mut url = Url::parse("https://api.bilibili.com/x/player/wbi/playurl")?;
-        {
-            let mut qp = url.query_pairs_mut();
-            for (k, v) in &params {
-                qp.append_pair(k, v);
-            }
-            qp.append_pair("w_rid", &w_rid);
-        }
+        let url = self.wbi_signed_url("https://api.bilibili.com/x/player/wbi/playurl", params).await?;
         let parsed: PlayUrlResp = self.get_json_retry(url).await?;
         if parsed.code != 0 {
             return Err(anyhow!(
@@ -186,7 +217,50 @@ impl BiliClient {
         Ok(parsed)
     }
 
-    async fn get_json_retry<T: serde::de::DeserializeOwned>(&self, url: Url) -> Result<T> {
+    /// Fetches the playurl for a resolved page, using the PGC endpoint for bangumi episodes.
+    pub async fn get_playurl_for(&self, page: &VideoPage, quality: Option<u32>, fnval: u32) -> Result<PlayUrlResp> {
+        match &page.episode {
+            Some(ep) => self.get_pgc_playurl(ep.ep_id, page.cid, quality, fnval).await,
+            None => self.get_playurl(&page.bvid, page.cid, quality, fnval).await,
+        }
+    }
+
+    async fn wbi_signer(&self) -> Result<WbiSigner> {
+        if let Ok(guard) = self.wbi.lock()
+            && let Some((at, signer)) = guard.as_ref()
+            && at.elapsed() < WBI_KEY_TTL
+        {
+            return Ok(signer.clone());
+        }
+        let signer = WbiSigner::fetch(&self.http).await?;
+        if let Ok(mut guard) = self.wbi.lock() {
+            *guard = Some((Instant::now(), signer.clone()));
+        }
+        Ok(signer)
+    }
+
+    /// Builds `base?params` with WBI `wts`/`w_rid` signature appended.
+    pub(crate) async fn wbi_signed_url(&self, base: &str, params: Vec<(String, String)>) -> Result<Url> {
+        let signer = self.wbi_signer().await?;
+        let (params, _wts, w_rid) = signer.sign(params);
+        let mut url = Url::parse(base)?;
+        {
+            let mut qp = url.query_pairs_mut();
+            for (k, v) in &params {
+                qp.append_pair(k, v);
+            }
+            qp.append_pair("w_rid", &w_rid);
+        }
+        Ok(url)
+    }
+
+    pub(crate) async fn get_json_retry<T: serde::de::DeserializeOwned>(&self, url: Url) -> Result<T> {
+        let body = self.get_bytes_retry(url).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// GET with small retries, returning the raw (decompressed) body.
+    pub(crate) async fn get_bytes_retry(&self, url: Url) -> Result<bytes::Bytes> {
         let mut last_err = None;
         for (i, delay_ms) in [0u64, 500, 1500].into_iter().enumerate() {
             if delay_ms > 0 { sleep(Duration::from_millis(delay_ms)).await; }
@@ -195,7 +269,7 @@ impl BiliClient {
                 Ok(resp) => {
                     let status = resp.status();
                     if status.is_success() {
-                        return Ok(resp.json::<T>().await?);
+                        return Ok(resp.bytes().await?);
                     }
                     last_err = Some(anyhow!("http status {}", status));
                 }
@@ -266,7 +340,7 @@ pub fn select_streams(
     let mut asel = None;
     if want_audio {
         let mut audios = dash.audio.clone().unwrap_or_default();
-        audios.sort_by(|a, b| a.id.cmp(&b.id));
+        audios.sort_by_key(|a| a.id);
         audios.reverse();
         asel = audios.first().cloned();
     }
@@ -288,8 +362,12 @@ pub fn select_streams_with_format(dash: &Dash, fmt: &str) -> (Option<DashVideo>,
             }
             _ => (true, true),
         };
-        let vfilter = parse_filter(parts.get(0).copied());
-        let afilter = if parts.len() > 1 { parse_filter(parts.get(1).copied()) } else { Default::default() };
+        let vfilter = parse_filter(parts.first().copied());
+        let afilter = match parts.as_slice() {
+            [_, a] => parse_filter(Some(a)),
+            [single] if want_audio && !want_video => parse_filter(Some(single)),
+            _ => Default::default(),
+        };
 
         let vsel = if want_video { pick_video(dash, &vfilter) } else { None };
         let asel = if want_audio { pick_audio(dash, &afilter) } else { None };
@@ -306,9 +384,22 @@ pub fn select_streams_with_format(dash: &Dash, fmt: &str) -> (Option<DashVideo>,
 struct Filter {
     max_height: Option<i32>,
     min_height: Option<i32>,
+    dynamic_range: Option<String>,
+    /// (operator, value) such as (">", 30.0)
+    fps: Option<(String, f64)>,
     vcodec_eq: Option<String>,
     vcodec_prefix: Option<String>,
     acodec_eq: Option<String>,
+    acodec_prefix: Option<String>,
+    dolby: bool,
+    flac: bool,
+}
+
+impl Filter {
+    /// Dolby and Hi-Res tracks are only considered when an audio constraint asks for them.
+    fn wants_extra_audio(&self) -> bool {
+        self.acodec_eq.is_some() || self.acodec_prefix.is_some() || self.dolby || self.flac
+    }
 }
 
 fn parse_filter(token: Option<&str>) -> Filter {
@@ -325,8 +416,21 @@ fn parse_filter(token: Option<&str>) -> Filter {
             if let Some(v2) = v.strip_prefix("=") { f.vcodec_prefix = Some(v2.to_string()); }
         } else if let Some(v) = expr.strip_prefix("vcodec=") {
             f.vcodec_eq = Some(v.to_string());
+        } else if let Some(v) = expr.strip_prefix("dynamic_range=") {
+            f.dynamic_range = Some(v.to_string());
+        } else if let Some(v) = expr.strip_prefix("fps") {
+            let op_len = v.find(|c: char| !matches!(c, '<' | '>' | '=')).unwrap_or(v.len());
+            if let (op @ ("<" | "<=" | ">" | ">=" | "="), Ok(n)) = (&v[..op_len], v[op_len..].parse::<f64>()) {
+                f.fps = Some((op.to_string(), n));
+            }
+        } else if let Some(v) = expr.strip_prefix("acodec^=") {
+            f.acodec_prefix = Some(v.to_string());
         } else if let Some(v) = expr.strip_prefix("acodec=") {
             f.acodec_eq = Some(v.to_string());
+        } else if expr.eq_ignore_ascii_case("dolby") {
+            f.dolby = true;
+        } else if expr.eq_ignore_ascii_case("flac") || expr.eq_ignore_ascii_case("hires") {
+            f.flac = true;
         }
     }
     // quick codec hints directly in token (e.g., "+av01")
@@ -342,23 +446,43 @@ fn parse_filter(token: Option<&str>) -> Filter {
 fn pick_video(dash: &Dash, f: &Filter) -> Option<DashVideo> {
     let mut vids = dash.video.clone();
     vids.retain(|v| {
-        if let Some(h) = f.max_height { if v.height.map(|x| x > h).unwrap_or(false) { return false; } }
-        if let Some(h) = f.min_height { if v.height.map(|x| x < h).unwrap_or(false) { return false; } }
-        if let Some(ref eq) = f.vcodec_eq { if v.codecs != *eq { return false; } }
-        if let Some(ref pf) = f.vcodec_prefix { if !v.codecs.to_ascii_lowercase().starts_with(&pf.to_ascii_lowercase()) { return false; } }
+        if let Some(h) = f.max_height && v.height.map(|x| x > h).unwrap_or(false) { return false; }
+        if let Some(h) = f.min_height && v.height.map(|x| x < h).unwrap_or(false) { return false; }
+        if let Some(ref eq) = f.vcodec_eq && v.codecs != *eq { return false; }
+        if let Some(ref pf) = f.vcodec_prefix && !v.codecs.to_ascii_lowercase().starts_with(&pf.to_ascii_lowercase()) { return false; }
+        if let Some(ref dr) = f.dynamic_range && !dynamic_range_matches(v.dynamic_range(), dr) { return false; }
+        if let Some((ref op, n)) = f.fps {
+            let Some(fps) = v.fps() else { return false; };
+            let ok = match op.as_str() {
+                "<" => fps < n,
+                "<=" => fps <= n + 0.01,
+                ">" => fps > n + 0.01,
+                ">=" => fps >= n,
+                _ => (fps - n).abs() < 0.1,
+            };
+            if !ok { return false; }
+        }
         true
     });
     vids.sort_by(|a,b| a.height.cmp(&b.height).then(a.id.cmp(&b.id)));
     vids.pop()
 }
 
+/// `HDR` matches any high dynamic range; other values compare case-insensitively.
+fn dynamic_range_matches(actual: &str, wanted: &str) -> bool {
+    actual.eq_ignore_ascii_case(wanted) || (wanted.eq_ignore_ascii_case("hdr") && actual != "SDR")
+}
+
 fn pick_audio(dash: &Dash, f: &Filter) -> Option<DashAudio> {
-    let mut auds = dash.audio.clone().unwrap_or_default();
+    let mut auds = if f.wants_extra_audio() { dash.all_audio() } else { dash.audio.clone().unwrap_or_default() };
     auds.retain(|a| {
-        if let Some(ref eq) = f.acodec_eq { if a.codecs != *eq { return false; } }
+        if let Some(ref eq) = f.acodec_eq && !a.codecs.eq_ignore_ascii_case(eq) { return false; }
+        if let Some(ref pf) = f.acodec_prefix && !a.codecs.to_ascii_lowercase().starts_with(&pf.to_ascii_lowercase()) { return false; }
+        if f.dolby && !a.is_dolby() { return false; }
+        if f.flac && !a.is_flac() { return false; }
         true
     });
-    auds.sort_by(|a,b| a.id.cmp(&b.id));
+    auds.sort_by_key(|a| a.id);
     auds.pop()
 }
 
@@ -375,6 +499,86 @@ pub fn extract_bvid(input: &str) -> Option<String> {
     None
 }
 
+/// Extracts a legacy av number from `av170001`, `.../video/av170001` or an `aid=` query parameter.
+pub fn extract_aid(input: &str) -> Option<u64> {
+    let re_av = Regex::new(r"(?i)(?:^|[^0-9a-z])av(\d+)").ok()?;
+    if let Some(c) = re_av.captures(input) {
+        return c[1].parse().ok();
+    }
+    let url = Url::parse(input).ok()?;
+    url.query_pairs()
+        .find(|(k, _)| k == "aid")
+        .and_then(|(_, v)| v.parse().ok())
+}
+
+const BV_XOR_CODE: u64 = 23442827791579;
+const BV_MASK_CODE: u64 = 2251799813685247;
+const BV_MAX_AID: u64 = 1 << 51;
+const BV_ALPHABET: &[u8; 58] = b"FcwAPNKTMug3GV5Lj7EJnHpWsx4tb8haYeviqBz6rkCy12mUSDQX9RdoZf";
+// position in the 9-char BV tail of each base-58 digit, least significant first
+const BV_ENCODE_MAP: [usize; 9] = [8, 7, 0, 5, 1, 3, 2, 4, 6];
+
+/// Converts an av number to its BV id (e.g. 170001 -> BV17x411w7KC).
+pub fn av_to_bv(aid: u64) -> String {
+    let mut tail = [0u8; 9];
+    let mut tmp = (BV_MAX_AID | aid) ^ BV_XOR_CODE;
+    for &pos in BV_ENCODE_MAP.iter() {
+        tail[pos] = BV_ALPHABET[(tmp % 58) as usize];
+        tmp /= 58;
+    }
+    format!("BV1{}", String::from_utf8_lossy(&tail))
+}
+
+/// Converts a BV id back to its av number. Returns None for malformed ids.
+pub fn bv_to_av(bvid: &str) -> Option<u64> {
+    let b = bvid.as_bytes();
+    if b.len() != 12 || !b[..3].eq_ignore_ascii_case(b"BV1") { return None; }
+    let tail = &b[3..];
+    let mut tmp: u64 = 0;
+    for &pos in BV_ENCODE_MAP.iter().rev() {
+        let idx = BV_ALPHABET.iter().position(|&c| c == tail[pos])?;
+        tmp = tmp * 58 + idx as u64;
+    }
+    Some((tmp & BV_MASK_CODE) ^ BV_XOR_CODE)
+}
+
+/// The pages of `data` selected by `page_spec`, `all_pages` or the URL's `?p=` (page 1 by default).
+pub(crate) fn pages_from_view(bvid: &str, data: &VideoInfo, page_from_url: Option<u32>, page_spec: Option<&str>, all_pages: bool) -> Result<Vec<VideoPage>> {
+    let total = data.pages.len() as u32;
+    let selected = if all_pages {
+        (1..=total).collect()
+    } else if let Some(spec) = page_spec {
+        parse_page_spec(spec, total)?
+    } else {
+        let page = page_from_url.unwrap_or(1);
+        if page == 0 || page > total { return Err(anyhow!("page {} not found", page)); }
+        vec![page]
+    };
+    Ok(selected
+        .into_iter()
+        .filter_map(|p| data.pages.get((p - 1) as usize).map(|item| (p, item)))
+        .map(|(p, item)| VideoPage {
+            bvid: bvid.to_string(),
+            aid: if data.aid > 0 { data.aid } else { bv_to_av(bvid).unwrap_or(0) },
+            cid: item.cid,
+            page: p,
+            title: data.title.clone(),
+            page_title: item.part.clone(),
+            page_count: total,
+            duration: item.duration,
+            episode: None,
+        })
+        .collect())
+}
+
+/// BV id and `?p=` of an input whose short link was already expanded.
+pub(crate) fn parse_bvid_and_page(target: &str) -> Result<(String, Option<u32>)> {
+    let bvid = extract_bvid(target)
+        .or_else(|| extract_aid(target).map(av_to_bv))
+        .ok_or_else(|| anyhow!("BV id not found in input"))?;
+    Ok((bvid, extract_page_param(target)))
+}
+
 pub fn extract_page_param(input: &str) -> Option<u32> {
     if let Ok(url) = Url::parse(input) {
         return url
@@ -431,7 +635,7 @@ fn load_netscape_into_jar(jar: &Arc<CookieStoreMutex>, path: &str) -> Result<usi
             rc.set_domain(domain.to_string());
             if secure { rc.set_secure(true); }
             if let Ok(mut guard) = jar.lock() {
-                let _ = guard.store_response_cookies(std::iter::once(rc), &url);
+                guard.store_response_cookies(std::iter::once(rc), &url);
                 count += 1;
             }
         }
@@ -445,21 +649,147 @@ fn load_netscape_into_jar(jar: &Arc<CookieStoreMutex>, path: &str) -> Result<usi
 pub struct PlayUrlResp {
     pub code: i32,
     pub message: Option<String>,
+    // the PGC endpoint returns the same payload under `result`
+    #[serde(alias = "result")]
     pub data: Option<PlayUrlData>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PlayUrlData {
     pub dash: Option<Dash>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub accept_quality: Vec<i32>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub accept_description: Vec<String>,
+    /// Legacy progressive segments, returned instead of `dash` for some videos
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub durl: Vec<Durl>,
+    /// Container of the `durl` segments, e.g. "flv", "flv720", "mp4"
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Quality id of the `durl` segments
+    #[serde(default)]
+    pub quality: Option<i32>,
+}
+
+/// One FLV or MP4 segment of a legacy stream.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Durl {
+    #[serde(default)]
+    pub order: u32,
+    /// Duration in milliseconds
+    #[serde(default)]
+    pub length: u64,
+    #[serde(default)]
+    pub size: u64,
+    pub url: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub backup_url: Vec<String>,
+}
+
+impl Durl {
+    /// `url` followed by its mirrors.
+    pub fn urls(&self) -> Vec<String> {
+        std::iter::once(&self.url).chain(&self.backup_url).cloned().collect()
+    }
+}
+
+impl PlayUrlData {
+    /// File extension of the `durl` segments.
+    pub fn durl_ext(&self) -> &'static str {
+        match self.format.as_deref() {
+            Some(f) if f.starts_with("mp4") => "mp4",
+            Some(_) => "flv",
+            None => match self.durl.first() {
+                Some(d) if d.url.split('?').next().is_some_and(|p| p.ends_with(".mp4")) => "mp4",
+                _ => "flv",
+            },
+        }
+    }
+
+    /// Human label for a quality id, from `accept_description` or the known ids.
+    pub fn quality_label(&self, id: i32) -> String {
+        self.accept_quality
+            .iter()
+            .position(|q| *q == id)
+            .and_then(|i| self.accept_description.get(i))
+            .cloned()
+            .unwrap_or_else(|| quality_name(id).to_string())
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// English name of a playurl quality id (`qn`).
+pub fn quality_name(id: i32) -> &'static str {
+    match id {
+        6 => "240P",
+        16 => "360P",
+        32 => "480P",
+        64 => "720P",
+        74 => "720P60",
+        80 => "1080P",
+        100 => "Smart Repair",
+        112 => "1080P+",
+        116 => "1080P60",
+        120 => "4K",
+        125 => "HDR",
+        126 => "Dolby Vision",
+        127 => "8K",
+        _ => "",
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Dash {
     pub video: Vec<DashVideo>,
     pub audio: Option<Vec<DashAudio>>,
+    /// Dolby Atmos (E-AC-3) tracks
+    #[serde(default)]
+    pub dolby: Option<DashDolby>,
+    /// Hi-Res lossless track
+    #[serde(default)]
+    pub flac: Option<DashFlac>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DashDolby {
+    /// 1: Dolby audio, 2: Dolby Atmos
+    #[serde(rename = "type", default)]
+    pub kind: u8,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub audio: Vec<DashAudio>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DashFlac {
+    #[serde(default)]
+    pub display: bool,
+    pub audio: Option<DashAudio>,
+}
+
+impl Dash {
+    /// Mirrors of the video or audio stream with this id and codecs (any codecs when empty).
+    pub fn stream_urls(&self, id: i32, codecs: &str) -> Option<Vec<String>> {
+        let matches = |sid: i32, c: &str| sid == id && (codecs.is_empty() || c == codecs);
+        if let Some(v) = self.video.iter().find(|v| matches(v.id, &v.codecs)) {
+            return Some(v.urls());
+        }
+        self.all_audio().iter().find(|a| matches(a.id, &a.codecs)).map(DashAudio::urls)
+    }
+
+    /// Regular AAC tracks followed by the Dolby and Hi-Res FLAC tracks.
+    pub fn all_audio(&self) -> Vec<DashAudio> {
+        let mut out = self.audio.clone().unwrap_or_default();
+        if let Some(d) = &self.dolby {
+            out.extend(d.audio.iter().cloned());
+        }
+        if let Some(a) = self.flac.as_ref().and_then(|f| f.audio.clone()) {
+            out.push(a);
+        }
+        out
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct DashVideo {
     pub id: i32,
     #[serde(rename = "baseUrl")]
@@ -467,29 +797,271 @@ pub struct DashVideo {
     pub codecs: String,
     pub height: Option<i32>,
     pub bandwidth: Option<u64>,
+    #[serde(default)]
+    pub width: Option<i32>,
+    /// e.g. "29.970"
+    #[serde(default, rename = "frameRate", alias = "frame_rate")]
+    pub frame_rate: Option<String>,
+    /// Sample aspect ratio, e.g. "1:1"
+    #[serde(default)]
+    pub sar: Option<String>,
+    /// 7 AVC, 12 HEVC, 13 AV1
+    #[serde(default)]
+    pub codecid: Option<i32>,
+    /// CDN mirrors of `base_url`
+    #[serde(default, rename = "backupUrl", deserialize_with = "null_as_default")]
+    pub backup_url: Vec<String>,
+}
+
+impl DashVideo {
+    /// `base_url` followed by its mirrors.
+    pub fn urls(&self) -> Vec<String> {
+        std::iter::once(&self.base_url).chain(&self.backup_url).cloned().collect()
+    }
+
+    pub fn fps(&self) -> Option<f64> {
+        self.frame_rate.as_deref().and_then(|f| match f.split_once('/') {
+            Some((n, d)) => Some(n.parse::<f64>().ok()? / d.parse::<f64>().ok()?),
+            None => f.parse().ok(),
+        })
+    }
+
+    /// "DV" for Dolby Vision (quality 126 or a dvh1/dvhe stream), "HDR10" for quality 125, else "SDR".
+    pub fn dynamic_range(&self) -> &'static str {
+        if self.id == 126 || self.codecs.starts_with("dvh") {
+            "DV"
+        } else if self.id == 125 {
+            "HDR10"
+        } else {
+            "SDR"
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct DashAudio {
     pub id: i32,
     #[serde(rename = "baseUrl")]
     pub base_url: String,
     pub codecs: String,
     pub bandwidth: Option<u64>,
+    /// CDN mirrors of `base_url`
+    #[serde(default, rename = "backupUrl", deserialize_with = "null_as_default")]
+    pub backup_url: Vec<String>,
+}
+
+impl DashAudio {
+    /// `base_url` followed by its mirrors.
+    pub fn urls(&self) -> Vec<String> {
+        std::iter::once(&self.base_url).chain(&self.backup_url).cloned().collect()
+    }
+
+    pub fn is_flac(&self) -> bool {
+        self.codecs.eq_ignore_ascii_case("flac") || self.id == 30251
+    }
+
+    pub fn is_dolby(&self) -> bool {
+        self.codecs.starts_with("ec-3") || self.codecs.starts_with("ac-3") || self.id == 30250
+    }
+
+    /// Label for -F: "Hi-Res FLAC", "Dolby Atmos" or empty for regular AAC.
+    pub fn label(&self) -> &'static str {
+        if self.is_flac() { "Hi-Res FLAC" } else if self.is_dolby() { "Dolby Atmos" } else { "" }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct ViewResp {
-    data: Option<ViewData>,
+    data: Option<VideoInfo>,
+}
+
+/// Video metadata from `x/web-interface/view`, written out by `--write-info-json`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VideoInfo {
+    #[serde(default)]
+    pub bvid: String,
+    #[serde(default)]
+    pub aid: u64,
+    pub title: String,
+    #[serde(default)]
+    pub desc: String,
+    /// Cover image URL
+    #[serde(default)]
+    pub pic: String,
+    /// Category name
+    #[serde(default)]
+    pub tname: String,
+    /// 1 = original, 2 = repost
+    #[serde(default)]
+    pub copyright: i32,
+    /// Publish time (unix seconds)
+    #[serde(default)]
+    pub pubdate: i64,
+    /// Upload time (unix seconds)
+    #[serde(default)]
+    pub ctime: i64,
+    /// Total duration in seconds
+    #[serde(default)]
+    pub duration: u64,
+    #[serde(default)]
+    pub dynamic: String,
+    #[serde(default)]
+    pub owner: VideoOwner,
+    #[serde(default)]
+    pub stat: VideoStat,
+    pub pages: Vec<ViewPage>,
+    /// Collection (合集) the video belongs to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ugc_season: Option<UgcSeason>,
+    /// Not part of the view response; filled from `x/tag/archive/tags` by `get_video_info`
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VideoOwner {
+    #[serde(default)]
+    pub mid: u64,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub face: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VideoStat {
+    #[serde(default)]
+    pub view: u64,
+    #[serde(default)]
+    pub danmaku: u64,
+    #[serde(default)]
+    pub reply: u64,
+    #[serde(default)]
+    pub favorite: u64,
+    #[serde(default)]
+    pub coin: u64,
+    #[serde(default)]
+    pub share: u64,
+    #[serde(default)]
+    pub like: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ViewPage {
+    pub cid: u64,
+    #[serde(default)]
+    pub page: u32,
+    #[serde(default)]
+    pub part: String,
+    /// Duration in seconds
+    #[serde(default)]
+    pub duration: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UgcSeason {
+    pub id: u64,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub mid: u64,
+    #[serde(default)]
+    pub sections: Vec<UgcSection>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UgcSection {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub episodes: Vec<UgcEpisode>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UgcEpisode {
+    pub aid: u64,
+    pub bvid: String,
+    pub cid: u64,
+    #[serde(default)]
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TagsResp {
+    data: Option<Vec<VideoTag>>,
 }
 
 #[derive(Debug, Deserialize)]
-struct ViewData {
-    title: String,
-    pages: Vec<ViewPage>,
+struct VideoTag {
+    tag_name: String,
 }
 
 #[derive(Debug, Deserialize)]
-struct ViewPage {
-    cid: u64,
+struct PlayerResp {
+    code: i32,
+    message: Option<String>,
+    data: Option<PlayerInfo>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PlayerInfo {
+    #[serde(default)]
+    subtitle: Option<PlayerSubtitles>,
+    /// Chapter markers (高能进度条 / view points)
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub view_points: Vec<ViewPoint>,
+}
+
+impl PlayerInfo {
+    pub fn subtitles(&self) -> &[SubtitleTrack] {
+        self.subtitle.as_ref().map(|s| s.subtitles.as_slice()).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct PlayerSubtitles {
+    #[serde(default)]
+    subtitles: Vec<SubtitleTrack>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ViewPoint {
+    /// 2 = chapter
+    #[serde(default, rename = "type")]
+    pub kind: i32,
+    /// Start in seconds
+    pub from: u64,
+    /// End in seconds
+    pub to: u64,
+    #[serde(default)]
+    pub content: String,
+}
+
+fn null_as_default<'de, D, T>(d: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(d)?.unwrap_or_default())
+}
+
+/// One page (part) of a video, resolved to the cid that is downloaded.
+#[derive(Debug, Clone)]
+pub struct VideoPage {
+    pub bvid: String,
+    /// Legacy av number
+    pub aid: u64,
+    pub cid: u64,
+    /// 1-based page number
+    pub page: u32,
+    /// Video title
+    pub title: String,
+    /// Title of this page (`part` in the view response)
+    pub page_title: String,
+    /// Number of pages of the video
+    pub page_count: u32,
+    /// Duration of this page in seconds (0 if unknown)
+    pub duration: u64,
+    /// Bangumi (PGC) episode info; None for regular uploads
+    pub episode: Option<EpisodeInfo>,
 }
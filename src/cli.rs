@@ -4,12 +4,61 @@ use clap::{ArgAction, Parser};
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about)]
 pub struct Args {
-    /// BV id or a full Bilibili URL
-    pub input: String,
+    /// BV ids, av numbers (av170001) or full Bilibili URLs
+    #[arg(required_unless_present = "batch_file")]
+    pub inputs: Vec<String>,
 
-    /// Page number (1-based) for multi-part videos
-    #[arg(short, long, default_value_t = 1)]
-    pub page: u32,
+    /// File with one URL/BV id per line ('-' for stdin, '#' starts a comment line)
+    #[arg(short = 'a', long = "batch-file")]
+    pub batch_file: Option<String>,
+
+    /// Pages (1-based) for multi-part videos: a number, list or ranges (e.g. 1-5,8,10-)
+    #[arg(short, long)]
+    pub page: Option<String>,
+
+    /// Download every page of a multi-part video
+    #[arg(long = "all-pages", action = ArgAction::SetTrue, conflicts_with = "page")]
+    pub all_pages: bool,
+
+    /// If the video is part of a collection (合集), download the whole collection
+    #[arg(long = "whole-collection", action = ArgAction::SetTrue)]
+    pub whole_collection: bool,
+
+    /// Order of an uploader's videos for space.bilibili.com inputs (newest|oldest)
+    #[arg(long = "space-order", default_value = "newest", value_parser = ["newest", "oldest"])]
+    pub space_order: String,
+
+    /// Download at most N videos from an uploader space
+    #[arg(long = "space-limit")]
+    pub space_limit: Option<usize>,
+
+    /// Playlist items to download, 1-based (e.g. 1-10,15). Applies to pages, collections, favorites and spaces
+    #[arg(short = 'I', long = "playlist-items", conflicts_with_all = ["playlist_start", "playlist_end"])]
+    pub playlist_items: Option<String>,
+
+    /// Playlist item to start at (1-based)
+    #[arg(long = "playlist-start")]
+    pub playlist_start: Option<usize>,
+
+    /// Playlist item to end at (1-based, inclusive)
+    #[arg(long = "playlist-end")]
+    pub playlist_end: Option<usize>,
+
+    /// Download playlist items in reverse order
+    #[arg(long = "playlist-reverse", action = ArgAction::SetTrue)]
+    pub playlist_reverse: bool,
+
+    /// Stop after downloading N files
+    #[arg(long = "max-downloads")]
+    pub max_downloads: Option<usize>,
+
+    /// Record finished downloads in FILE and skip anything already listed there
+    #[arg(long = "download-archive")]
+    pub download_archive: Option<String>,
+
+    /// Stop an input's queue at the first item found in the download archive
+    #[arg(long = "break-on-existing", action = ArgAction::SetTrue, requires = "download_archive")]
+    pub break_on_existing: bool,
 
     /// Desired quality id (e.g., 80=1080p, 64=720p). If absent, pick best.
     #[arg(short = 'q', long)]
@@ -60,6 +109,18 @@ pub struct Args {
     #[arg(long = "merge-output-format")] 
     pub merge_output_format: Option<String>,
 
+    /// Keep only the audio and convert it to --audio-format
+    #[arg(short = 'x', long = "extract-audio", action = ArgAction::SetTrue)]
+    pub extract_audio: bool,
+
+    /// Audio format for -x: best keeps the codec (m4a for AAC/E-AC-3, flac for FLAC)
+    #[arg(long = "audio-format", default_value = "best", value_parser = ["best", "m4a", "mp3", "flac", "opus"])]
+    pub audio_format: String,
+
+    /// Audio quality when transcoding: 0 (best) to 10 (worst) VBR, or a bitrate such as 128K
+    #[arg(long = "audio-quality", default_value = "5")]
+    pub audio_quality: String,
+
     /// Cookies file in Netscape format
     #[arg(long = "cookies")]
     pub cookies: Option<String>,
@@ -76,6 +137,28 @@ pub struct Args {
     #[arg(long = "continue", action = ArgAction::SetTrue)]
     pub resume: bool,
 
+    /// CDN hosts to try first, in order, e.g. upos-sz-mirrorcos.bilivideo.com (comma-separated).
+    /// PCDN/MCDN mirrors are always tried last
+    #[arg(long = "cdn-host", value_delimiter = ',')]
+    pub cdn_host: Vec<String>,
+
+    /// Number of connections per track, each fetching a byte range (default: 1)
+    #[arg(short = 'N', long = "concurrent-fragments", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=64))]
+    pub concurrent_fragments: u16,
+
+    /// Maximum download rate in bytes per second, shared by all connections (e.g. 500K, 2M)
+    #[arg(short = 'r', long = "limit-rate")]
+    pub limit_rate: Option<String>,
+
+    /// Rates by local time of day, overriding --limit-rate inside each window;
+    /// 0 is full speed. E.g. "01:00-07:00=0,18:00-23:00=512K"
+    #[arg(long = "limit-schedule")]
+    pub limit_schedule: Option<String>,
+
+    /// Muxer: ffmpeg, native (pure Rust, mp4/mkv) or auto (ffmpeg if installed, else native)
+    #[arg(long = "muxer", default_value = "auto", value_parser = ["auto", "ffmpeg", "native"])]
+    pub muxer: String,
+
     /// Delete .m4s parts after successful mux (default: on). Use --no-cleanup to keep.
     #[arg(long, default_value_t = true)]
     pub cleanup: bool,
@@ -84,6 +167,62 @@ pub struct Args {
     #[arg(long = "no-cleanup", action = ArgAction::SetTrue)]
     pub no_cleanup: bool,
 
+    /// Write video metadata and the selected formats to <output>.info.json
+    #[arg(long = "write-info-json", action = ArgAction::SetTrue)]
+    pub write_info_json: bool,
+
+    /// Write subtitles (CC and AI) as <output>.<lang>.<ext>
+    #[arg(long = "write-subs", action = ArgAction::SetTrue)]
+    pub write_subs: bool,
+
+    /// Subtitle languages to write, comma separated (e.g. zh-Hans,en,ai-zh); default all
+    #[arg(long = "sub-langs", value_delimiter = ',')]
+    pub sub_langs: Vec<String>,
+
+    /// List available subtitles and exit
+    #[arg(long = "list-subs", action = ArgAction::SetTrue)]
+    pub list_subs: bool,
+
+    /// Convert subtitles from Bilibili JSON to srt|vtt|ass
+    #[arg(long = "convert-subs", value_parser = ["srt", "vtt", "ass"])]
+    pub convert_subs: Option<String>,
+
+    /// Embed subtitles into the output (mov_text in mp4, SRT/ASS in mkv)
+    #[arg(long = "embed-subs", action = ArgAction::SetTrue)]
+    pub embed_subs: bool,
+
+    /// Embed the cover image into the output
+    #[arg(long = "embed-thumbnail", action = ArgAction::SetTrue)]
+    pub embed_thumbnail: bool,
+
+    /// Embed title, uploader, date and description into the output
+    #[arg(long = "embed-metadata", action = ArgAction::SetTrue)]
+    pub embed_metadata: bool,
+
+    /// Embed chapter markers from the video's view points
+    #[arg(long = "embed-chapters", action = ArgAction::SetTrue)]
+    pub embed_chapters: bool,
+
+    /// Write danmaku (bullet comments) as <output>.danmaku.xml and a rendered <output>.danmaku.ass
+    #[arg(long = "write-danmaku", action = ArgAction::SetTrue)]
+    pub write_danmaku: bool,
+
+    /// Embed the rendered danmaku ASS as a subtitle track (mkv output)
+    #[arg(long = "embed-danmaku", action = ArgAction::SetTrue)]
+    pub embed_danmaku: bool,
+
+    /// Danmaku ASS font size for normal-size comments (1080p canvas)
+    #[arg(long = "danmaku-font-size", default_value_t = 48)]
+    pub danmaku_font_size: u32,
+
+    /// Danmaku ASS opacity, 0.0-1.0
+    #[arg(long = "danmaku-opacity", default_value_t = 0.8)]
+    pub danmaku_opacity: f64,
+
+    /// Fraction of the screen height danmaku may cover, 0.0-1.0; comments that don't fit are dropped
+    #[arg(long = "danmaku-density", default_value_t = 1.0)]
+    pub danmaku_density: f64,
+
     /// Save cookies (Netscape format) after run
     #[arg(long = "save-cookies")]
     pub save_cookies: Option<String>,
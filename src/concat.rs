@@ -0,0 +1,463 @@
+//! Legacy `durl` streams: some videos are served as one or more progressive MP4 or FLV
+//! segments instead of DASH. [`concat_segments`] joins the segments into one progressive MP4
+//! (`ftyp`, `moov`, `mdat`) without ffmpeg.
+//!
+//! Each segment is demuxed into [`SegmentTrack`]s (see [`read_mp4_segment`] and
+//! `flv::read_flv_segment`); the samples are shifted so every segment starts where the previous
+//! one ended, and [`write_progressive_mp4`] writes the result with the `moov` up front.
+
+use crate::flv::read_flv_segment;
+use crate::remux::{
+    edit_media_time, find_box, is_v1, parse_boxes, read_payload, read_u32, read_u64, scan_top_level, write_box, Mp4Box, ReadSeek,
+};
+use anyhow::{anyhow, Context, Result};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+/// One sample of a demuxed segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentSample {
+    /// Index of the input the data lives in
+    pub input: usize,
+    pub offset: u64,
+    pub size: u32,
+    /// Decode time in the track timescale
+    pub dts: u64,
+    /// Presentation minus decode time
+    pub cts_offset: i32,
+    pub duration: u32,
+    pub keyframe: bool,
+}
+
+/// A video or audio track of a segment, ready to be written as an MP4 `trak`.
+#[derive(Debug, Clone)]
+pub struct SegmentTrack {
+    /// `vide` or `soun`
+    pub handler: [u8; 4],
+    pub timescale: u32,
+    pub width: u16,
+    pub height: u16,
+    /// Payload of the `stsd` box (sample descriptions)
+    pub stsd: Vec<u8>,
+    /// Edit list start in the track timescale (AAC priming, A/V offset), 0 if none
+    pub media_time: i64,
+    pub samples: Vec<SegmentSample>,
+}
+
+impl SegmentTrack {
+    fn start_ms(&self) -> Option<u64> {
+        self.samples.first().map(|s| s.dts * 1000 / self.timescale.max(1) as u64)
+    }
+
+    fn end_ms(&self) -> u64 {
+        self.samples
+            .last()
+            .map(|s| (s.dts + s.duration as u64) * 1000 / self.timescale.max(1) as u64)
+            .unwrap_or(0)
+    }
+}
+
+// ==== Progressive MP4 input ====
+
+/// Demuxes the video and audio tracks of a progressive (non-fragmented) MP4.
+pub fn read_mp4_segment<R: Read + Seek>(r: &mut R) -> Result<Vec<SegmentTrack>> {
+    let top = scan_top_level(r)?;
+    let moov = top.iter().find(|b| &b.kind == b"moov").ok_or_else(|| anyhow!("no moov box"))?;
+    let moov = parse_boxes(&read_payload(r, moov)?)?;
+    let mut tracks = Vec::new();
+    for trak in moov.iter().filter(|b| &b.kind == b"trak") {
+        if let Some(t) = read_mp4_trak(trak)? {
+            tracks.push(t);
+        }
+    }
+    Ok(tracks)
+}
+
+fn child(parent: &Mp4Box, kind: &[u8; 4]) -> Result<Mp4Box> {
+    find_box(&parent.children()?, kind)
+        .cloned()
+        .ok_or_else(|| anyhow!("no {} box", String::from_utf8_lossy(kind)))
+}
+
+/// Entries of a table box (`stts`, `stsc`, ...): the count after the version/flags and
+/// `width` bytes per entry.
+fn table(b: &Mp4Box, skip: usize, width: usize) -> Result<(usize, &[u8])> {
+    let count = read_u32(&b.payload, 4 + skip)? as usize;
+    let start = 8 + skip;
+    let data = b
+        .payload
+        .get(start..start + count * width)
+        .ok_or_else(|| anyhow!("truncated {} box", String::from_utf8_lossy(&b.kind)))?;
+    Ok((count, data))
+}
+
+fn read_mp4_trak(trak: &Mp4Box) -> Result<Option<SegmentTrack>> {
+    let mdia = child(trak, b"mdia")?;
+    let hdlr = child(&mdia, b"hdlr")?;
+    let handler: [u8; 4] = hdlr.payload.get(8..12).ok_or_else(|| anyhow!("truncated hdlr"))?.try_into().unwrap();
+    if &handler != b"vide" && &handler != b"soun" {
+        return Ok(None);
+    }
+    let mdhd = child(&mdia, b"mdhd")?;
+    let timescale = read_u32(&mdhd.payload, if is_v1(&mdhd.payload) { 20 } else { 12 })?;
+    let tkhd = child(trak, b"tkhd")?;
+    let dims = tkhd.payload.len().saturating_sub(8);
+    let (width, height) = ((read_u32(&tkhd.payload, dims)? >> 16) as u16, (read_u32(&tkhd.payload, dims + 4)? >> 16) as u16);
+    let stbl = child(&child(&mdia, b"minf")?, b"stbl")?;
+    let boxes = stbl.children()?;
+    let get = |kind: &[u8; 4]| find_box(&boxes, kind);
+    let stsd = get(b"stsd").ok_or_else(|| anyhow!("no stsd box"))?.payload.clone();
+
+    // Sizes
+    let stsz = get(b"stsz").ok_or_else(|| anyhow!("no stsz box"))?;
+    let fixed = read_u32(&stsz.payload, 4)?;
+    let sizes: Vec<u32> = if fixed != 0 {
+        vec![fixed; read_u32(&stsz.payload, 8)? as usize]
+    } else {
+        let (count, entries) = table(stsz, 4, 4)?;
+        (0..count).map(|i| read_u32(entries, i * 4)).collect::<Result<_>>()?
+    };
+
+    // Chunk offsets and samples per chunk
+    let chunk_offsets: Vec<u64> = match (get(b"stco"), get(b"co64")) {
+        (Some(b), _) => {
+            let (n, d) = table(b, 0, 4)?;
+            (0..n).map(|i| read_u32(d, i * 4).map(u64::from)).collect::<Result<_>>()?
+        }
+        (None, Some(b)) => {
+            let (n, d) = table(b, 0, 8)?;
+            (0..n).map(|i| read_u64(d, i * 8)).collect::<Result<_>>()?
+        }
+        _ => return Err(anyhow!("no chunk offsets")),
+    };
+    let stsc = get(b"stsc").ok_or_else(|| anyhow!("no stsc box"))?;
+    let (n, d) = table(stsc, 0, 12)?;
+    let runs: Vec<(u32, u32)> = (0..n).map(|i| Ok((read_u32(d, i * 12)?, read_u32(d, i * 12 + 4)?))).collect::<Result<_>>()?;
+    let mut offsets = Vec::with_capacity(sizes.len());
+    for (i, chunk) in chunk_offsets.iter().enumerate() {
+        let chunk_no = i as u32 + 1;
+        let per_chunk = runs.iter().rev().find(|(first, _)| *first <= chunk_no).map(|r| r.1).unwrap_or(0);
+        let mut pos = *chunk;
+        for _ in 0..per_chunk {
+            let Some(size) = sizes.get(offsets.len()) else { break };
+            offsets.push(pos);
+            pos += *size as u64;
+        }
+    }
+
+    // Timing
+    let mut durations = Vec::with_capacity(sizes.len());
+    if let Some(stts) = get(b"stts") {
+        let (n, d) = table(stts, 0, 8)?;
+        for i in 0..n {
+            let (count, delta) = (read_u32(d, i * 8)?, read_u32(d, i * 8 + 4)?);
+            durations.extend(std::iter::repeat_n(delta, count as usize));
+        }
+    }
+    let mut cts = Vec::new();
+    if let Some(ctts) = get(b"ctts") {
+        let (n, d) = table(ctts, 0, 8)?;
+        for i in 0..n {
+            let (count, off) = (read_u32(d, i * 8)?, read_u32(d, i * 8 + 4)? as i32);
+            cts.extend(std::iter::repeat_n(off, count as usize));
+        }
+    }
+    let sync: Option<Vec<u32>> = match get(b"stss") {
+        Some(stss) => {
+            let (n, d) = table(stss, 0, 4)?;
+            Some((0..n).map(|i| read_u32(d, i * 4)).collect::<Result<_>>()?)
+        }
+        None => None,
+    };
+
+    let mut samples = Vec::with_capacity(offsets.len());
+    let mut dts = 0u64;
+    for (i, (offset, size)) in offsets.iter().zip(&sizes).enumerate() {
+        let duration = durations.get(i).copied().unwrap_or(0);
+        samples.push(SegmentSample {
+            input: 0,
+            offset: *offset,
+            size: *size,
+            dts,
+            cts_offset: cts.get(i).copied().unwrap_or(0),
+            duration,
+            keyframe: sync.as_ref().is_none_or(|s| s.binary_search(&(i as u32 + 1)).is_ok()),
+        });
+        dts += duration as u64;
+    }
+    let media_time = edit_media_time(trak)?;
+    Ok(Some(SegmentTrack { handler, timescale, width, height, stsd, media_time, samples }))
+}
+
+// ==== Progressive MP4 output ====
+
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+fn u32s(vals: &[u32]) -> Vec<u8> {
+    vals.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+fn full_box(out: &mut Vec<u8>, kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) {
+    let mut payload = vec![version];
+    payload.extend_from_slice(&flags.to_be_bytes()[1..]);
+    payload.extend_from_slice(body);
+    write_box(out, kind, &payload);
+}
+
+/// Run-length encodes `values` as (count, value) pairs.
+fn runs<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> Vec<(u32, T)> {
+    let mut out: Vec<(u32, T)> = Vec::new();
+    for v in values {
+        match out.last_mut() {
+            Some((n, last)) if *last == v => *n += 1,
+            _ => out.push((1, v)),
+        }
+    }
+    out
+}
+
+/// A run of consecutive samples of one track in the `mdat`.
+struct Chunk {
+    track: usize,
+    first: usize,
+    count: usize,
+    /// Offset relative to the start of the `mdat` payload
+    offset: u64,
+}
+
+fn build_trak(track: &SegmentTrack, id: u32, chunks: &[&Chunk], base: u64, co64: bool) -> Vec<u8> {
+    let duration: u64 = track.samples.iter().map(|s| s.duration as u64).sum();
+    let movie_duration = duration * 1000 / track.timescale.max(1) as u64;
+    let video = &track.handler == b"vide";
+
+    let mut tkhd = u32s(&[0, 0, id, 0, movie_duration as u32, 0, 0]);
+    tkhd.extend_from_slice(&[0, 0, 0, 0]);
+    tkhd.extend_from_slice(&(if video { 0u16 } else { 0x0100 }).to_be_bytes());
+    tkhd.extend_from_slice(&[0, 0]);
+    tkhd.extend(u32s(&MATRIX));
+    tkhd.extend(u32s(&[(track.width as u32) << 16, (track.height as u32) << 16]));
+
+    let mut trak = Vec::new();
+    full_box(&mut trak, b"tkhd", 0, 3, &tkhd);
+    // Keep the input's edit; without one, skip the decoder delay of B-frame streams
+    let media_time = match track.media_time {
+        0 => track.samples.first().map_or(0, |s| s.cts_offset.max(0) as i64),
+        t => t,
+    };
+    if media_time > 0 {
+        let edit_duration = duration.saturating_sub(media_time as u64) * 1000 / track.timescale.max(1) as u64;
+        let mut elst = Vec::new();
+        full_box(&mut elst, b"elst", 0, 0, &u32s(&[1, edit_duration as u32, media_time as u32, 0x0001_0000]));
+        write_box(&mut trak, b"edts", &elst);
+    }
+
+    let mut mdia = Vec::new();
+    let mut mdhd = u32s(&[0, 0, track.timescale, duration.min(u32::MAX as u64) as u32]);
+    mdhd.extend_from_slice(&[0x55, 0xc4, 0, 0]);
+    full_box(&mut mdia, b"mdhd", 0, 0, &mdhd);
+    let name: &[u8] = if video { b"VideoHandler\0" } else { b"SoundHandler\0" };
+    full_box(&mut mdia, b"hdlr", 0, 0, &[&[0u8; 4][..], &track.handler, &[0; 12], name].concat());
+
+    let mut minf = Vec::new();
+    if video {
+        full_box(&mut minf, b"vmhd", 0, 1, &[0; 8]);
+    } else {
+        full_box(&mut minf, b"smhd", 0, 0, &[0; 4]);
+    }
+    let mut dref = Vec::new();
+    full_box(&mut dref, b"url ", 0, 1, &[]);
+    let mut dinf = Vec::new();
+    full_box(&mut dinf, b"dref", 0, 0, &[u32s(&[1]), dref].concat());
+    write_box(&mut minf, b"dinf", &dinf);
+
+    let samples = &track.samples;
+    let mut stbl = Vec::new();
+    write_box(&mut stbl, b"stsd", &track.stsd);
+    let stts = runs(samples.iter().map(|s| s.duration));
+    let mut body = u32s(&[stts.len() as u32]);
+    for (n, d) in &stts { body.extend(u32s(&[*n, *d])); }
+    full_box(&mut stbl, b"stts", 0, 0, &body);
+    if samples.iter().any(|s| s.cts_offset != 0) {
+        let ctts = runs(samples.iter().map(|s| s.cts_offset));
+        let mut body = u32s(&[ctts.len() as u32]);
+        for (n, c) in &ctts { body.extend(u32s(&[*n, *c as u32])); }
+        full_box(&mut stbl, b"ctts", 1, 0, &body);
+    }
+    if video && samples.iter().any(|s| !s.keyframe) {
+        let sync: Vec<u32> = samples.iter().enumerate().filter(|(_, s)| s.keyframe).map(|(i, _)| i as u32 + 1).collect();
+        full_box(&mut stbl, b"stss", 0, 0, &[u32s(&[sync.len() as u32]), u32s(&sync)].concat());
+    }
+    let stsc = runs(chunks.iter().map(|c| c.count as u32));
+    let mut body = u32s(&[stsc.len() as u32]);
+    let mut chunk_no = 1;
+    for (n, per_chunk) in &stsc {
+        body.extend(u32s(&[chunk_no, *per_chunk, 1]));
+        chunk_no += n;
+    }
+    full_box(&mut stbl, b"stsc", 0, 0, &body);
+    let sizes: Vec<u32> = samples.iter().map(|s| s.size).collect();
+    full_box(&mut stbl, b"stsz", 0, 0, &[u32s(&[0, sizes.len() as u32]), u32s(&sizes)].concat());
+    let mut body = u32s(&[chunks.len() as u32]);
+    for c in chunks {
+        let pos = base + c.offset;
+        if co64 { body.extend_from_slice(&pos.to_be_bytes()) } else { body.extend_from_slice(&(pos as u32).to_be_bytes()) }
+    }
+    full_box(&mut stbl, if co64 { b"co64" } else { b"stco" }, 0, 0, &body);
+    write_box(&mut minf, b"stbl", &stbl);
+    write_box(&mut mdia, b"minf", &minf);
+    write_box(&mut trak, b"mdia", &mdia);
+    let mut out = Vec::new();
+    write_box(&mut out, b"trak", &trak);
+    out
+}
+
+fn build_moov(tracks: &[SegmentTrack], chunks: &[Chunk], base: u64, co64: bool, udta: &[u8]) -> Vec<u8> {
+    let duration = tracks.iter().map(SegmentTrack::end_ms).max().unwrap_or(0);
+    let mut mvhd = u32s(&[0, 0, 1000, duration as u32, 0x0001_0000]);
+    mvhd.extend_from_slice(&[1, 0]);
+    mvhd.extend_from_slice(&[0; 10]);
+    mvhd.extend(u32s(&MATRIX));
+    mvhd.extend_from_slice(&[0; 24]);
+    mvhd.extend(u32s(&[tracks.len() as u32 + 1]));
+    let mut moov = Vec::new();
+    full_box(&mut moov, b"mvhd", 0, 0, &mvhd);
+    for (i, t) in tracks.iter().enumerate() {
+        let own: Vec<&Chunk> = chunks.iter().filter(|c| c.track == i).collect();
+        moov.extend(build_trak(t, i as u32 + 1, &own, base, co64));
+    }
+    moov.extend_from_slice(udta);
+    let mut out = Vec::new();
+    write_box(&mut out, b"moov", &moov);
+    out
+}
+
+/// Writes `tracks` as a progressive MP4 with the `moov` before the `mdat`, reading the sample
+/// data from `inputs`. Samples are grouped into chunks of about half a second.
+pub fn write_progressive_mp4<W: Write>(tracks: &[SegmentTrack], inputs: &mut [&mut dyn ReadSeek], out: &mut W) -> Result<()> {
+    write_progressive_mp4_with_udta(tracks, &[], inputs, out)
+}
+
+/// [`write_progressive_mp4`] with a complete `udta` box (e.g. iTunes tags) added to the `moov`.
+pub fn write_progressive_mp4_with_udta<W: Write>(
+    tracks: &[SegmentTrack],
+    udta: &[u8],
+    inputs: &mut [&mut dyn ReadSeek],
+    out: &mut W,
+) -> Result<()> {
+    // Interleave half-second buckets of each track
+    let mut order: Vec<(u64, usize, usize)> = Vec::new();
+    for (t, track) in tracks.iter().enumerate() {
+        for (i, s) in track.samples.iter().enumerate() {
+            order.push((s.dts * 2 / track.timescale.max(1) as u64, t, i));
+        }
+    }
+    order.sort();
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut data_len = 0u64;
+    let mut last_bucket = None;
+    for &(bucket, t, i) in &order {
+        match chunks.last_mut() {
+            Some(c) if c.track == t && last_bucket == Some(bucket) && c.first + c.count == i => c.count += 1,
+            _ => chunks.push(Chunk { track: t, first: i, count: 1, offset: data_len }),
+        }
+        last_bucket = Some(bucket);
+        data_len += tracks[t].samples[i].size as u64;
+    }
+
+    let mut ftyp = Vec::new();
+    write_box(&mut ftyp, b"ftyp", &[b"isom".as_slice(), &0x200u32.to_be_bytes(), b"isomiso2avc1mp41"].concat());
+    let large = data_len + 8 > u32::MAX as u64;
+    let mdat_header = if large { 16 } else { 8 };
+    let probe = build_moov(tracks, &chunks, 0, large, udta);
+    let base = (ftyp.len() + probe.len()) as u64 + mdat_header;
+    let moov = build_moov(tracks, &chunks, base, large, udta);
+    out.write_all(&ftyp)?;
+    out.write_all(&moov)?;
+    if large {
+        out.write_all(&1u32.to_be_bytes())?;
+        out.write_all(b"mdat")?;
+        out.write_all(&(data_len + 16).to_be_bytes())?;
+    } else {
+        out.write_all(&(data_len as u32 + 8).to_be_bytes())?;
+        out.write_all(b"mdat")?;
+    }
+    let mut buf = Vec::new();
+    for c in &chunks {
+        for s in &tracks[c.track].samples[c.first..c.first + c.count] {
+            let r = &mut inputs[s.input];
+            r.seek(SeekFrom::Start(s.offset))?;
+            buf.resize(s.size as usize, 0);
+            r.read_exact(&mut buf).context("read sample data")?;
+            out.write_all(&buf)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// ==== Concatenation ====
+
+/// Demuxes an FLV or progressive MP4 segment, told apart by the `FLV` signature.
+pub fn read_segment<R: Read + Seek>(r: &mut R) -> Result<Vec<SegmentTrack>> {
+    let mut magic = [0u8; 3];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut magic).context("read segment header")?;
+    r.seek(SeekFrom::Start(0))?;
+    if &magic == b"FLV" { read_flv_segment(r) } else { read_mp4_segment(r) }
+}
+
+/// Joins FLV or MP4 segments into one MP4. Tracks are matched by handler; the first segment
+/// decides the tracks and their sample descriptions.
+pub fn concat_segments<W: Write>(inputs: &mut [&mut dyn ReadSeek], out: &mut W) -> Result<()> {
+    let mut merged: Vec<SegmentTrack> = Vec::new();
+    for (n, input) in inputs.iter_mut().enumerate() {
+        let tracks = read_segment(input).with_context(|| format!("segment {}", n + 1))?;
+        if n == 0 {
+            merged = tracks.iter().map(|t| SegmentTrack { samples: Vec::new(), ..t.clone() }).collect();
+        }
+        // Keep the segment's own A/V offsets and start it where the longest track ended
+        let seg_start = tracks.iter().filter_map(SegmentTrack::start_ms).min().unwrap_or(0);
+        let target = merged.iter().map(SegmentTrack::end_ms).max().unwrap_or(0);
+        for t in tracks {
+            let Some(m) = merged.iter_mut().find(|m| m.handler == t.handler) else { continue };
+            let ts = m.timescale.max(1) as u64;
+            let rescale = |v: u64| v * ts / t.timescale.max(1) as u64;
+            let shift = (target * ts / 1000) as i64 - (seg_start * ts / 1000) as i64;
+            m.samples.extend(t.samples.iter().map(|s| SegmentSample {
+                input: n,
+                dts: (rescale(s.dts) as i64 + shift).max(0) as u64,
+                duration: rescale(s.duration as u64) as u32,
+                cts_offset: rescale(s.cts_offset.max(0) as u64) as i32,
+                ..s.clone()
+            }));
+        }
+    }
+    // Close gaps so each sample lasts until the next one
+    for t in &mut merged {
+        for i in 1..t.samples.len() {
+            let next = t.samples[i].dts;
+            let prev = &mut t.samples[i - 1];
+            if next > prev.dts {
+                prev.duration = (next - prev.dts) as u32;
+            }
+        }
+    }
+    if merged.iter().all(|t| t.samples.is_empty()) {
+        return Err(anyhow!("no audio or video samples in the segments"));
+    }
+    write_progressive_mp4(&merged, inputs, out)
+}
+
+/// Joins the downloaded `durl` segment files into `out_path`.
+pub async fn concat_files(paths: &[String], out_path: &str) -> Result<()> {
+    let (paths, out_path) = (paths.to_vec(), out_path.to_string());
+    tokio::task::spawn_blocking(move || {
+        let mut files = paths
+            .iter()
+            .map(|p| std::fs::File::open(p).map(BufReader::new).with_context(|| format!("open {}", p)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut inputs: Vec<&mut dyn ReadSeek> = files.iter_mut().map(|f| f as &mut dyn ReadSeek).collect();
+        let mut out = BufWriter::new(std::fs::File::create(out_path).context("create output")?);
+        concat_segments(&mut inputs, &mut out)
+    })
+    .await?
+}
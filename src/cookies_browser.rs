@@ -1,14 +1,26 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Result};
+use reqwest_cookie_store::CookieStoreMutex;
+use std::sync::Arc;
+#[cfg(target_os = "windows")]
+use anyhow::Context;
+#[cfg(target_os = "windows")]
 use aes_gcm::{Aes256Gcm, Key, Nonce};
+#[cfg(target_os = "windows")]
 use aes_gcm::aead::{Aead, KeyInit};
+#[cfg(target_os = "windows")]
 use base64::Engine;
+#[cfg(target_os = "windows")]
 use dirs_next::data_local_dir;
+#[cfg(target_os = "windows")]
 use reqwest::Url;
-use reqwest_cookie_store::{CookieStore, CookieStoreMutex, RawCookie};
+#[cfg(target_os = "windows")]
+use reqwest_cookie_store::{CookieStore, RawCookie};
+#[cfg(target_os = "windows")]
 use rusqlite::{Connection, OpenFlags};
+#[cfg(target_os = "windows")]
 use std::fs;
+#[cfg(target_os = "windows")]
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 #[cfg(target_os = "windows")]
 use windows::Win32::Security::Cryptography::{CryptUnprotectData, CRYPT_INTEGER_BLOB};
 
@@ -71,6 +83,7 @@ pub fn load_from_browser(_spec: &str) -> Result<(Arc<CookieStoreMutex>, Option<S
     Err(anyhow!("--cookies-from-browser currently supported only on Windows"))
 }
 
+#[cfg(target_os = "windows")]
 fn parse_spec(spec: &str) -> (String, String) {
     let mut parts = spec.splitn(2, ':');
     let browser = parts.next().unwrap_or("").to_ascii_lowercase();
@@ -78,16 +91,19 @@ fn parse_spec(spec: &str) -> (String, String) {
     (browser, profile)
 }
 
+#[cfg(target_os = "windows")]
 fn profile_root(product: &str) -> Result<PathBuf> {
     let base = data_local_dir().ok_or_else(|| anyhow!("no local app data directory"))?;
     Ok(base.join(product))
 }
 
+#[cfg(target_os = "windows")]
 fn profile_path(product: &str, profile: String, tail: &str) -> Result<PathBuf> {
     let root = profile_root(product)?;
     Ok(root.join(profile).join(tail))
 }
 
+#[cfg(target_os = "windows")]
 fn copy_db_temp(src: &Path) -> Result<PathBuf> {
     let mut tmp = std::env::temp_dir();
     tmp.push(format!("bili_cookies_{}.sqlite", std::process::id()));
@@ -120,6 +136,7 @@ fn dpapi_unprotect(data: &[u8]) -> Result<Vec<u8>> {
     }
 }
 
+#[cfg(target_os = "windows")]
 fn decrypt_cookie_value(enc: &[u8], key: &[u8]) -> Result<Vec<u8>> {
     if enc.len() > 3 && (&enc[0..3] == b"v10" || &enc[0..3] == b"v11") {
         let nonce = &enc[3..15];
@@ -0,0 +1,372 @@
+use crate::bilibili::BiliClient;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use reqwest::Url;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+/// Length of one `seg.so` segment in seconds.
+const SEGMENT_SECS: u64 = 360;
+
+/// One bullet comment, as found in the XML `p` attribute or a `seg.so` element.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Danmaku {
+    pub id: u64,
+    /// Appearance time in seconds
+    pub time: f64,
+    /// 1-3 scrolling, 4 bottom, 5 top, 6 reverse, 7 positioned, 8 code, 9 BAS
+    pub mode: u32,
+    /// 25 is the normal size; 18 small, 36 large
+    pub font_size: u32,
+    /// 0xRRGGBB
+    pub color: u32,
+    /// Send time (unix seconds)
+    pub ctime: i64,
+    pub pool: u32,
+    pub mid_hash: String,
+    pub weight: u32,
+    pub content: String,
+}
+
+/// The comments of one video.
+#[derive(Debug, Clone, Default)]
+pub struct DanmakuList {
+    pub items: Vec<Danmaku>,
+    /// The `list.so` response as served, when the XML endpoint was used
+    pub xml: Option<String>,
+}
+
+impl BiliClient {
+    /// Fetches every comment of `cid` from the segmented protobuf endpoint (one segment per
+    /// 6 minutes of `duration`), falling back to the XML endpoint if that fails.
+    pub async fn get_danmaku(&self, cid: u64, duration: u64) -> Result<DanmakuList> {
+        match self.get_danmaku_segments(cid, duration).await {
+            Ok(items) => Ok(DanmakuList { items, xml: None }),
+            Err(e) => {
+                eprintln!("Warning: danmaku seg.so failed ({e}); falling back to XML");
+                let url = Url::parse_with_params("https://api.bilibili.com/x/v1/dm/list.so", &[("oid", cid.to_string())])?;
+                let body = self.get_bytes_retry(url).await?;
+                let xml = String::from_utf8_lossy(&body).into_owned();
+                Ok(DanmakuList { items: parse_xml(&xml), xml: Some(xml) })
+            }
+        }
+    }
+
+    async fn get_danmaku_segments(&self, cid: u64, duration: u64) -> Result<Vec<Danmaku>> {
+        let known = duration.div_ceil(SEGMENT_SECS).max(1);
+        let mut items = Vec::new();
+        let mut index = 1;
+        loop {
+            let params = vec![
+                ("type".to_string(), "1".to_string()),
+                ("oid".to_string(), cid.to_string()),
+                ("segment_index".to_string(), index.to_string()),
+            ];
+            let url = self.wbi_signed_url("https://api.bilibili.com/x/v2/dm/wbi/web/seg.so", params).await?;
+            let body = self.get_bytes_retry(url).await?;
+            if body.first() == Some(&b'{') {
+                return Err(anyhow!("seg.so error: {}", String::from_utf8_lossy(&body)));
+            }
+            let seg = parse_seg(&body)?;
+            // Without a known duration keep going until the first empty segment.
+            let stop = if duration > 0 { index >= known } else { seg.is_empty() };
+            items.extend(seg);
+            if stop { break; }
+            index += 1;
+        }
+        Ok(normalize(items))
+    }
+}
+
+/// Sorts by time and drops duplicate ids.
+fn normalize(mut items: Vec<Danmaku>) -> Vec<Danmaku> {
+    let mut seen = HashSet::new();
+    items.retain(|d| d.id == 0 || seen.insert(d.id));
+    items.sort_by(|a, b| a.time.total_cmp(&b.time));
+    items
+}
+
+// ==== XML ====
+
+/// Parses the `<d p="...">text</d>` entries of a danmaku XML document.
+pub fn parse_xml(xml: &str) -> Vec<Danmaku> {
+    let re = Regex::new(r#"(?s)<d p="([^"]*)">(.*?)</d>"#).unwrap();
+    let items = re
+        .captures_iter(xml)
+        .filter_map(|caps| {
+            let p: Vec<&str> = caps[1].split(',').collect();
+            if p.len() < 8 { return None; }
+            Some(Danmaku {
+                time: p[0].parse().ok()?,
+                mode: p[1].parse().ok()?,
+                font_size: p[2].parse().unwrap_or(25),
+                color: p[3].parse().unwrap_or(0xFFFFFF),
+                ctime: p[4].parse().unwrap_or(0),
+                pool: p[5].parse().unwrap_or(0),
+                mid_hash: p[6].to_string(),
+                id: p[7].parse().unwrap_or(0),
+                weight: p.get(8).and_then(|w| w.parse().ok()).unwrap_or(0),
+                content: xml_unescape(&caps[2]),
+            })
+        })
+        .collect();
+    normalize(items)
+}
+
+/// Renders comments in the layout of the `list.so` XML endpoint; attributes without a
+/// [`Danmaku`] field are not reproduced.
+pub fn to_xml(cid: u64, items: &[Danmaku]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<i>\n");
+    let _ = writeln!(out, "  <chatserver>chat.bilibili.com</chatserver>\n  <chatid>{}</chatid>\n  <maxlimit>{}</maxlimit>", cid, items.len());
+    for d in items {
+        let _ = writeln!(
+            out,
+            "  <d p=\"{:.5},{},{},{},{},{},{},{},{}\">{}</d>",
+            d.time, d.mode, d.font_size, d.color, d.ctime, d.pool, xml_escape(&d.mid_hash), d.id, d.weight, xml_escape(&d.content)
+        );
+    }
+    out.push_str("</i>\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn xml_unescape(s: &str) -> String {
+    let re = Regex::new(r"&(#x[0-9a-fA-F]+|#[0-9]+|lt|gt|amp|quot|apos);").unwrap();
+    re.replace_all(s, |caps: &regex::Captures| {
+        let ent = &caps[1];
+        let ch = match ent {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if ent.starts_with("#x") => u32::from_str_radix(&ent[2..], 16).ok().and_then(char::from_u32),
+            _ => ent[1..].parse().ok().and_then(char::from_u32),
+        };
+        ch.map(String::from).unwrap_or_else(|| caps[0].to_string())
+    })
+    .into_owned()
+}
+
+// ==== Protobuf (seg.so) ====
+
+/// Decodes a `DmSegMobileReply` message: field 1 holds repeated `DanmakuElem`s.
+pub fn parse_seg(buf: &[u8]) -> Result<Vec<Danmaku>> {
+    let mut items = Vec::new();
+    let mut r = ProtoReader { buf, pos: 0 };
+    while let Some((field, value)) = r.next_field()? {
+        if let (1, ProtoValue::Bytes(elem)) = (field, value) {
+            items.push(parse_elem(elem)?);
+        }
+    }
+    Ok(items)
+}
+
+fn parse_elem(buf: &[u8]) -> Result<Danmaku> {
+    let mut d = Danmaku { id: 0, time: 0.0, mode: 1, font_size: 25, color: 0xFFFFFF, ctime: 0, pool: 0, mid_hash: String::new(), weight: 0, content: String::new() };
+    let mut r = ProtoReader { buf, pos: 0 };
+    while let Some((field, value)) = r.next_field()? {
+        match (field, value) {
+            (1, ProtoValue::Varint(v)) => d.id = v,
+            (2, ProtoValue::Varint(v)) => d.time = v as f64 / 1000.0,
+            (3, ProtoValue::Varint(v)) => d.mode = v as u32,
+            (4, ProtoValue::Varint(v)) => d.font_size = v as u32,
+            (5, ProtoValue::Varint(v)) => d.color = v as u32,
+            (6, ProtoValue::Bytes(b)) => d.mid_hash = String::from_utf8_lossy(b).into_owned(),
+            (7, ProtoValue::Bytes(b)) => d.content = String::from_utf8_lossy(b).into_owned(),
+            (8, ProtoValue::Varint(v)) => d.ctime = v as i64,
+            (9, ProtoValue::Varint(v)) => d.weight = v as u32,
+            (11, ProtoValue::Varint(v)) => d.pool = v as u32,
+            _ => {}
+        }
+    }
+    Ok(d)
+}
+
+enum ProtoValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+struct ProtoReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self.buf.get(self.pos).ok_or_else(|| anyhow!("truncated varint"))?;
+            self.pos += 1;
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 { return Ok(v); }
+        }
+        Err(anyhow!("varint too long"))
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.buf.len()).ok_or_else(|| anyhow!("truncated field"))?;
+        let out = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u64, ProtoValue<'a>)>> {
+        if self.pos >= self.buf.len() { return Ok(None); }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => ProtoValue::Varint(self.varint()?),
+            1 => { self.take(8)?; ProtoValue::Fixed }
+            2 => {
+                let len = self.varint()? as usize;
+                ProtoValue::Bytes(self.take(len)?)
+            }
+            5 => { self.take(4)?; ProtoValue::Fixed }
+            w => return Err(anyhow!("unsupported wire type {}", w)),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+// ==== ASS ====
+
+/// Layout options for [`to_ass`].
+#[derive(Debug, Clone)]
+pub struct AssOptions {
+    pub width: u32,
+    pub height: u32,
+    pub font_name: String,
+    /// Font size used for normal (25) comments; small and large ones are scaled from it
+    pub font_size: u32,
+    /// 0.0 (invisible) to 1.0 (opaque)
+    pub opacity: f64,
+    /// Fraction of the screen height that may hold comments; comments that find no free lane are dropped
+    pub density: f64,
+    /// Seconds a scrolling comment takes to cross the screen
+    pub scroll_duration: f64,
+    /// Seconds a top/bottom comment stays on screen
+    pub fixed_duration: f64,
+}
+
+impl Default for AssOptions {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            font_name: "Microsoft YaHei".to_string(),
+            font_size: 48,
+            opacity: 0.8,
+            density: 1.0,
+            scroll_duration: 8.0,
+            fixed_duration: 4.0,
+        }
+    }
+}
+
+/// A comment occupying a lane: start time, width and scroll speed (px/s).
+#[derive(Clone, Copy)]
+struct LaneUse {
+    start: f64,
+    width: f64,
+    speed: f64,
+}
+
+/// Renders comments as an ASS subtitle: scrolling comments move right to left, top and bottom
+/// comments are centered. Positioned, code and BAS comments (modes 7-9) are skipped.
+pub fn to_ass(items: &[Danmaku], opts: &AssOptions) -> String {
+    let w = opts.width as f64;
+    let h = opts.height as f64;
+    let line_h = opts.font_size.max(1) as f64;
+    let lanes = ((h * opts.density.clamp(0.0, 1.0)) / line_h).floor().max(1.0) as usize;
+    let alpha = ((1.0 - opts.opacity.clamp(0.0, 1.0)) * 255.0).round() as u8;
+
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "[Script Info]\nScriptType: v4.00+\nPlayResX: {w}\nPlayResY: {h}\nWrapStyle: 2\nScaledBorderAndShadow: yes\n\n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Danmaku,{font},{size},&H{a:02X}FFFFFF,&H{a:02X}FFFFFF,&H{a:02X}000000,&H{a:02X}000000,0,0,0,0,100,100,0,0,1,1,0,7,0,0,0,1\n\n\
+         [Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        w = opts.width,
+        h = opts.height,
+        font = opts.font_name,
+        size = opts.font_size,
+        a = alpha,
+    );
+
+    let mut scroll: Vec<Option<LaneUse>> = vec![None; lanes];
+    let mut top: Vec<f64> = vec![f64::NEG_INFINITY; lanes];
+    let mut bottom: Vec<f64> = vec![f64::NEG_INFINITY; lanes];
+
+    for d in items {
+        let size = (opts.font_size as f64 * d.font_size.max(1) as f64 / 25.0).round();
+        let width = text_width(&d.content, size);
+        let mut tags = String::new();
+        if d.font_size != 25 { let _ = write!(tags, "\\fs{}", size); }
+        if d.color & 0xFFFFFF != 0xFFFFFF {
+            let (r, g, b) = ((d.color >> 16) & 0xFF, (d.color >> 8) & 0xFF, d.color & 0xFF);
+            let _ = write!(tags, "\\c&H{:02X}{:02X}{:02X}&", b, g, r);
+        }
+        let (end, placement) = match d.mode {
+            1..=3 | 6 => {
+                let speed = (w + width) / opts.scroll_duration;
+                let Some(lane) = scroll.iter().position(|l| l.is_none_or(|p| scroll_lane_free(p, d.time, speed, w, opts.scroll_duration)))
+                else { continue };
+                scroll[lane] = Some(LaneUse { start: d.time, width, speed });
+                let y = lane as f64 * line_h;
+                let (from, to) = if d.mode == 6 { (-width, w) } else { (w, -width) };
+                (d.time + opts.scroll_duration, format!("\\move({:.0},{:.0},{:.0},{:.0})", from, y, to, y))
+            }
+            4 | 5 => {
+                let lanes = if d.mode == 5 { &mut top } else { &mut bottom };
+                let Some(lane) = lanes.iter().position(|&until| until <= d.time) else { continue };
+                lanes[lane] = d.time + opts.fixed_duration;
+                let pos = if d.mode == 5 {
+                    format!("\\an8\\pos({:.0},{:.0})", w / 2.0, lane as f64 * line_h)
+                } else {
+                    format!("\\an2\\pos({:.0},{:.0})", w / 2.0, h - lane as f64 * line_h)
+                };
+                (d.time + opts.fixed_duration, pos)
+            }
+            _ => continue,
+        };
+        let _ = writeln!(
+            out,
+            "Dialogue: 0,{},{},Danmaku,,0,0,0,,{{{}{}}}{}",
+            ass_time(d.time),
+            ass_time(end),
+            placement,
+            tags,
+            ass_escape(&d.content)
+        );
+    }
+    out
+}
+
+/// A scrolling lane is free once the previous comment has fully entered the screen and the new,
+/// possibly faster, comment cannot catch up with it before it leaves.
+fn scroll_lane_free(prev: LaneUse, t: f64, speed: f64, screen_w: f64, duration: f64) -> bool {
+    let entered = t >= prev.start + prev.width / prev.speed;
+    let no_overtake = t + screen_w / speed >= prev.start + duration;
+    entered && no_overtake
+}
+
+/// Rough rendered width: full-width characters take `size`, ASCII half of it.
+fn text_width(s: &str, size: f64) -> f64 {
+    s.chars().map(|c| if c.is_ascii() { size / 2.0 } else { size }).sum()
+}
+
+pub(crate) fn ass_time(t: f64) -> String {
+    let cs = (t.max(0.0) * 100.0).round() as u64;
+    format!("{}:{:02}:{:02}.{:02}", cs / 360_000, cs / 6000 % 60, cs / 100 % 60, cs % 100)
+}
+
+pub(crate) fn ass_escape(s: &str) -> String {
+    s.replace('{', "\\{").replace('}', "\\}").replace("\r\n", "\\N").replace(['\n', '\r'], "\\N")
+}
@@ -1,14 +1,113 @@
+use crate::segmented::{self, ByteRanges};
 use anyhow::{anyhow, Context, Result};
-use indicatif::{ProgressBar, ProgressStyle};
+use futures_util::future::BoxFuture;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::header::{HeaderMap, HeaderValue, REFERER, USER_AGENT};
 use reqwest::Client;
 use reqwest_cookie_store::CookieStoreMutex;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::path::Path;
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use std::process::Command;
 
+/// HTTP settings shared by every media download.
+#[derive(Clone, Default)]
+pub struct DownloadOptions {
+    pub user_agent: String,
+    pub referer: String,
+    pub cookie: Option<String>,
+    pub jar: Option<Arc<CookieStoreMutex>>,
+    /// Continue a `.part` whose saved state matches `source`
+    pub resume: bool,
+    /// Parallel range requests per file (`-N`); 0 or 1 streams over one connection
+    pub concurrent_fragments: usize,
+    /// Display the progress bar joins; None draws it on its own
+    pub progress: Option<MultiProgress>,
+    /// Shown before the progress bar, e.g. "video"
+    pub label: String,
+    /// What is downloaded; a saved state for anything else is not resumed
+    pub source: StreamSource,
+    /// New mirrors for `source` once its signed URLs expire
+    pub refresh: Option<Refresher>,
+    /// Bandwidth limit shared with the other downloads
+    pub limiter: Option<Arc<RateLimiter>>,
+}
+
+/// Re-resolves the mirrors of a stream, e.g. by calling playurl again.
+pub type Refresher = Arc<dyn Fn() -> BoxFuture<'static, Result<Vec<String>>> + Send + Sync>;
+
+/// How often one download may fetch new URLs before giving up
+const MAX_REFRESHES: u32 = 3;
+
+/// A non-success HTTP status from a media server.
+#[derive(Debug, thiserror::Error)]
+#[error("download status {0}")]
+pub struct HttpStatus(pub reqwest::StatusCode);
+
+/// One file of [`download_tracks`].
+#[derive(Clone)]
+pub struct TrackJob {
+    /// Mirrors, best first
+    pub urls: Vec<String>,
+    pub path: String,
+    pub label: String,
+    pub source: StreamSource,
+    pub refresh: Option<Refresher>,
+}
+
+/// The stream a file is downloaded from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamSource {
+    pub bvid: String,
+    pub cid: u64,
+    /// DASH stream id, or the quality of a legacy segment
+    pub stream_id: i32,
+    #[serde(default)]
+    pub codecs: String,
+}
+
+/// Sidecar `<file>.part.json` of an unfinished download, checked before `--continue`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DownloadState {
+    pub source: StreamSource,
+    /// Expected size in bytes; 0 when the server didn't say
+    pub size: u64,
+    /// Completed ranges of a segmented (`-N`) download. A single-stream `.part` is
+    /// written in order and complete up to its length.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ranges: Option<ByteRanges>,
+}
+
+/// The file a download is written to until it is complete.
+pub fn part_path(out_path: &str) -> String {
+    format!("{}.part", out_path)
+}
+
+pub fn state_path(part: &str) -> String {
+    format!("{}.json", part)
+}
+
+pub fn load_state(part: &str) -> Option<DownloadState> {
+    serde_json::from_slice(&std::fs::read(state_path(part)).ok()?).ok()
+}
+
+/// Replaces the state file atomically, so a crash leaves the old or the new state.
+pub fn save_state(part: &str, state: &DownloadState) -> Result<()> {
+    let path = state_path(part);
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, serde_json::to_vec(state)?).context("write download state")?;
+    std::fs::rename(&tmp, &path).context("write download state")?;
+    Ok(())
+}
+
+fn remove_partial(part: &str) {
+    let _ = std::fs::remove_file(part);
+    let _ = std::fs::remove_file(state_path(part));
+}
+
 pub async fn download_with_progress(
     url: &str,
     out_path: &str,
@@ -18,72 +117,540 @@ pub async fn download_with_progress(
     jar: Option<Arc<CookieStoreMutex>>,
     resume: bool,
 ) -> Result<()> {
+    let opts = DownloadOptions {
+        user_agent: user_agent.to_string(),
+        referer: referer.to_string(),
+        cookie: cookie.map(str::to_string),
+        jar,
+        resume,
+        ..Default::default()
+    };
+    download_mirrors(&[url.to_string()], out_path, &opts).await
+}
+
+/// Downloads all `jobs` at once under one multi-bar display. The first failure cancels the
+/// others, and all files are removed unless `opts.resume` keeps them for `--continue`.
+pub async fn download_tracks(jobs: &[TrackJob], opts: &DownloadOptions) -> Result<()> {
+    let multi = MultiProgress::new();
+    let downloads = jobs.iter().map(|job| {
+        let opts = DownloadOptions {
+            progress: Some(multi.clone()),
+            label: job.label.clone(),
+            source: job.source.clone(),
+            refresh: job.refresh.clone(),
+            ..opts.clone()
+        };
+        async move { download_mirrors(&job.urls, &job.path, &opts).await.with_context(|| format!("{} track", job.label)) }
+    });
+    let result = futures_util::future::try_join_all(downloads).await;
+    if result.is_err() && !opts.resume {
+        for job in jobs {
+            remove_partial(&part_path(&job.path));
+        }
+    }
+    result.map(|_| ())
+}
+
+/// Downloads `out_path` from the first working URL of `urls`. When a mirror fails or
+/// stalls, the next one continues from the bytes already written. Data goes to
+/// `<out_path>.part`, renamed into place when complete; with `opts.resume` a `.part` whose
+/// state matches `opts.source` and the server's size is continued.
+///
+/// Once the signed URLs expire (403/410, or past their `deadline`), `opts.refresh` supplies
+/// new ones and the download continues from the current offset.
+pub async fn download_mirrors(urls: &[String], out_path: &str, opts: &DownloadOptions) -> Result<()> {
+    let Some(refresh) = &opts.refresh else {
+        return download_once(urls, out_path, opts).await;
+    };
+    let mut urls = urls.to_vec();
+    if !urls.is_empty() && urls.iter().all(|u| url_expired(u, unix_now())) {
+        urls = refresh().await.context("refresh expired URLs")?;
+    }
+    let mut opts = opts.clone();
+    let mut refreshes = 0;
+    loop {
+        match download_once(&urls, out_path, &opts).await {
+            Err(e) if refreshes < MAX_REFRESHES && is_expired(&e, &urls) => {
+                refreshes += 1;
+                let msg = format!("URLs expired ({e:#}); fetching new ones");
+                match &opts.progress {
+                    Some(multi) => { let _ = multi.println(msg); }
+                    None => eprintln!("{}", msg),
+                }
+                urls = refresh().await.context("refresh expired URLs")?;
+                // Continue the .part written so far
+                opts.resume = true;
+            }
+            r => return r,
+        }
+    }
+}
+
+/// Whether a failed download is worth retrying with fresh URLs.
+fn is_expired(e: &anyhow::Error, urls: &[String]) -> bool {
+    let status = e.chain().find_map(|c| c.downcast_ref::<HttpStatus>()).map(|s| s.0.as_u16());
+    matches!(status, Some(403 | 410)) || urls.iter().all(|u| url_expired(u, unix_now()))
+}
+
+/// Whether the `deadline` (Unix seconds) in a signed CDN URL has passed.
+pub fn url_expired(url: &str, now: u64) -> bool {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.query_pairs().find(|(k, _)| k == "deadline").and_then(|(_, v)| v.parse::<u64>().ok()))
+        .is_some_and(|deadline| deadline <= now)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Whether `out_path` is a finished download: no partial file or state is left next to it
+/// and its size matches what the server reports.
+async fn is_complete(client: &Client, urls: &[String], out_path: &str, part: &str) -> bool {
+    let Ok(meta) = std::fs::metadata(out_path) else { return false };
+    if Path::new(part).exists() || Path::new(&state_path(part)).exists() {
+        return false;
+    }
+    matches!(segmented::probe(client, urls).await, Ok(Some((_, total))) if total == meta.len())
+}
+
+async fn download_once(urls: &[String], out_path: &str, opts: &DownloadOptions) -> Result<()> {
+    if urls.is_empty() {
+        return Err(anyhow!("no download URL"));
+    }
+    let client = build_client(opts)?;
+
+    let path = Path::new(out_path);
+    if let Some(parent) = path.parent() { tokio::fs::create_dir_all(parent).await.ok(); }
+    let part = part_path(out_path);
+    if opts.resume && is_complete(&client, urls, out_path, &part).await {
+        return Ok(());
+    }
+    let saved = if opts.resume { load_state(&part).filter(|s| s.source == opts.source) } else { None };
+    if saved.is_none() {
+        remove_partial(&part);
+    }
+    let segmented_state = saved.as_ref().is_some_and(|s| s.ranges.is_some());
+    let mut state = if segmented_state { None } else { saved };
+    if (opts.concurrent_fragments > 1 || segmented_state)
+        && segmented::download_segmented(&client, urls, &part, opts).await?
+    {
+        return finish(&part, out_path).await;
+    }
+
+    let mut pb = None;
+    let mut last_err = None;
+    for (i, url) in urls.iter().enumerate() {
+        match fetch_to_file(&client, url, &part, &mut state, &mut pb, opts).await {
+            Ok(()) => {
+                if let Some(pb) = pb { pb.finish_with_message("done"); }
+                return finish(&part, out_path).await;
+            }
+            Err(e) => {
+                if i + 1 < urls.len() {
+                    let msg = format!("Warning: {} failed ({e:#}); switching to mirror {}/{}", url_host(url), i + 2, urls.len());
+                    match &opts.progress {
+                        Some(multi) => { let _ = multi.println(msg); }
+                        None => eprintln!("{}", msg),
+                    }
+                }
+                last_err = Some(e);
+            }
+        }
+    }
+    if let Some(pb) = pb { pb.abandon(); }
+    Err(last_err.unwrap())
+}
+
+/// Checks the size of a finished `.part` and renames it to `out_path`.
+async fn finish(part: &str, out_path: &str) -> Result<()> {
+    if let Some(state) = load_state(part) {
+        let len = tokio::fs::metadata(part).await?.len();
+        if state.size > 0 && len != state.size {
+            return Err(anyhow!("downloaded {} bytes, expected {}", len, state.size));
+        }
+    }
+    tokio::fs::rename(part, out_path).await.context("rename finished download")?;
+    let _ = tokio::fs::remove_file(state_path(part)).await;
+    Ok(())
+}
+
+/// The HTTP client for media requests: browser headers, cookies and stall timeouts.
+pub(crate) fn build_client(opts: &DownloadOptions) -> Result<Client> {
     let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str(user_agent).unwrap());
-    headers.insert(REFERER, HeaderValue::from_str(referer).unwrap());
-    if let Some(c) = cookie {
+    headers.insert(USER_AGENT, HeaderValue::from_str(&opts.user_agent).unwrap());
+    headers.insert(REFERER, HeaderValue::from_str(&opts.referer).unwrap());
+    if let Some(c) = &opts.cookie {
         use reqwest::header::COOKIE;
         headers.insert(COOKIE, HeaderValue::from_str(c).unwrap());
     }
     let mut builder = Client::builder()
         .default_headers(headers)
-        .http1_only();
-    if let Some(j) = jar { builder = builder.cookie_provider(j); }
-    let client = builder.build()?;
+        .http1_only()
+        .connect_timeout(Duration::from_secs(10))
+        // A mirror that sends nothing for this long is treated as throttled
+        .read_timeout(Duration::from_secs(30));
+    if let Some(j) = &opts.jar { builder = builder.cookie_provider(j.clone()); }
+    Ok(builder.build()?)
+}
 
+/// A byte progress bar, labelled and added to the shared display of `opts` if any.
+pub(crate) fn progress_bar(total: u64, opts: &DownloadOptions) -> ProgressBar {
+    let template = if opts.label.is_empty() {
+        "{bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}) ETA {eta}"
+    } else {
+        "{prefix:>5} {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}) ETA {eta}"
+    };
+    let bar = ProgressBar::new(total);
+    bar.set_style(ProgressStyle::with_template(template).unwrap().progress_chars("##-"));
+    bar.set_prefix(opts.label.clone());
+    match &opts.progress {
+        Some(multi) => multi.add(bar),
+        None => bar,
+    }
+}
+
+/// One attempt at `url`. With a `state` from this or an earlier run, the `.part` is continued
+/// from its length; it restarts when the server ignores Range or the size changed.
+async fn fetch_to_file(
+    client: &Client,
+    url: &str,
+    part: &str,
+    state: &mut Option<DownloadState>,
+    pb: &mut Option<ProgressBar>,
+    opts: &DownloadOptions,
+) -> Result<()> {
     use reqwest::header::{RANGE, CONTENT_RANGE};
-    let path = Path::new(out_path);
-    let mut existing: u64 = 0;
-    if resume {
-        if let Ok(meta) = tokio::fs::metadata(path).await { existing = meta.len(); }
-    }
-    let req = if existing > 0 { client.get(url).header(RANGE, format!("bytes={}-", existing)) } else { client.get(url) };
-    let resp = req.send().await?;
-    let status = resp.status();
-    if !(status.is_success() || status.as_u16() == 206) {
-        return Err(anyhow!("download status {}", status));
-    }
-    let total = match (status.as_u16(), resp.headers().get(CONTENT_RANGE)) {
-        (206, Some(cr)) => {
-            // format: bytes START-END/TOTAL
-            let s = cr.to_str().unwrap_or("");
-            s.rsplit('/').next().and_then(|t| t.parse::<u64>().ok()).unwrap_or(0)
-        }
-        _ => resp.content_length().unwrap_or(0),
+    let mut existing = match state {
+        Some(_) => tokio::fs::metadata(part).await.map(|m| m.len()).unwrap_or(0),
+        None => 0,
     };
-    let pb = if total > 0 {
-        let pb = ProgressBar::new(total);
-        pb.set_style(
-            ProgressStyle::with_template(
-                "{bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}) ETA {eta}",
-            )
-            .unwrap()
-            .progress_chars("##-"),
-        );
-        Some(pb)
-    } else {
-        None
+    let expected = state.as_ref().map_or(0, |s| s.size);
+    if expected > 0 && existing >= expected {
+        return Ok(());
+    }
+    let (resp, total) = loop {
+        let req = if existing > 0 { client.get(url).header(RANGE, format!("bytes={}-", existing)) } else { client.get(url) };
+        let resp = req.send().await?;
+        let status = resp.status();
+        if existing > 0 && status.as_u16() == 416 {
+            // Nothing left past the end of a file of unknown size
+            if expected == 0 {
+                return Ok(());
+            }
+            // The part doesn't match the file it was recorded for
+            existing = 0;
+            continue;
+        }
+        if !(status.is_success() || status.as_u16() == 206) {
+            return Err(HttpStatus(status).into());
+        }
+        let total = match (status.as_u16(), resp.headers().get(CONTENT_RANGE)) {
+            (206, Some(cr)) => {
+                // format: bytes START-END/TOTAL
+                let s = cr.to_str().unwrap_or("");
+                s.rsplit('/').next().and_then(|t| t.parse::<u64>().ok()).unwrap_or(0)
+            }
+            _ => resp.content_length().unwrap_or(0),
+        };
+        if existing > 0 && status.as_u16() != 206 {
+            // Range ignored: this response is the whole file
+            existing = 0;
+        } else if existing > 0 && expected > 0 && total != expected {
+            // A different file than the one partly downloaded
+            existing = 0;
+            continue;
+        }
+        break (resp, total);
     };
 
-    if let Some(parent) = path.parent() { tokio::fs::create_dir_all(parent).await.ok(); }
-    let mut file = if existing > 0 && status.as_u16() == 206 {
-        let f = tokio::fs::OpenOptions::new().append(true).open(path).await?;
-        if let Some(ref pb) = pb { pb.set_position(existing); }
-        f
+    let mut file = if existing > 0 {
+        tokio::fs::OpenOptions::new().append(true).open(part).await?
     } else {
-        File::create(path).await?
+        File::create(part).await?
     };
+    let fresh = DownloadState { source: opts.source.clone(), size: total, ranges: None };
+    if state.as_ref() != Some(&fresh) {
+        save_state(part, &fresh)?;
+        *state = Some(fresh);
+    }
+    if total > 0 && pb.is_none() {
+        *pb = Some(progress_bar(total, opts));
+    }
+    if let Some(pb) = pb.as_ref() {
+        pb.set_length(total.max(existing));
+        pb.set_position(existing);
+    }
     let mut stream = resp.bytes_stream();
     while let Some(chunk) = stream.next().await.transpose().context("read chunk")? {
+        if let Some(limiter) = &opts.limiter {
+            limiter.acquire(chunk.len() as u64).await;
+        }
         file.write_all(&chunk).await?;
-        if let Some(ref pb) = pb { pb.inc(chunk.len() as u64); }
+        if let Some(pb) = pb.as_ref() { pb.inc(chunk.len() as u64); }
     }
     file.flush().await?;
-    if let Some(pb) = pb { pb.finish_with_message("done"); }
     Ok(())
 }
 
+pub(crate) fn url_host(url: &str) -> String {
+    url::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string)).unwrap_or_else(|| url.to_string())
+}
+
+/// Whether `url` is served by a PCDN/MCDN edge (user-run peers, often slow), not an upos host.
+pub fn is_pcdn(url: &str) -> bool {
+    let Ok(u) = url::Url::parse(url) else { return false };
+    let host = u.host_str().unwrap_or("");
+    host.ends_with(".mcdn.bilivideo.cn")
+        || host.ends_with(".szbdyd.com")
+        || matches!(u.host(), Some(url::Host::Ipv4(_) | url::Host::Ipv6(_)))
+        || u.port().is_some()
+}
+
+/// Orders the mirrors of one stream for `download_mirrors`. Each `--cdn-host` is tried first,
+/// in order, by moving the first upos URL (path `/upgcxcode/...`) onto that host; then come the
+/// URLs from the API with PCDN/MCDN edges last. Duplicates are dropped.
+pub fn rank_mirrors(urls: &[String], cdn_hosts: &[String]) -> Vec<String> {
+    let mut ranked: Vec<String> = Vec::new();
+    let upos = urls.iter().filter_map(|u| url::Url::parse(u).ok()).find(|u| u.path().starts_with("/upgcxcode/"));
+    for host in cdn_hosts {
+        let host = host.trim().trim_start_matches("https://").trim_start_matches("http://").trim_end_matches('/');
+        if host.is_empty() {
+            continue;
+        }
+        // A host whose URL the API already returned is used as-is
+        if let Some(u) = urls.iter().find(|u| url_host(u) == host) {
+            ranked.push(u.clone());
+        } else if let Some(mut u) = upos.clone()
+            && u.set_host(Some(host)).is_ok()
+        {
+            let _ = u.set_port(None);
+            let _ = u.set_scheme("https");
+            ranked.push(u.to_string());
+        }
+    }
+    let (pcdn, direct): (Vec<&String>, Vec<&String>) = urls.iter().partition(|u| is_pcdn(u));
+    for u in direct.into_iter().chain(pcdn) {
+        if !ranked.contains(u) {
+            ranked.push(u.clone());
+        }
+    }
+    ranked
+}
+
+/// `--limit-rate`: bytes per second with an optional K/M/G suffix (1024-based), e.g. "2M".
+pub fn parse_rate(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c.to_ascii_uppercase()),
+        _ => (s, 'B'),
+    };
+    let scale = match unit {
+        'B' => 1.0,
+        'K' => 1024.0,
+        'M' => 1024.0 * 1024.0,
+        'G' => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(anyhow!("invalid rate: {}", s)),
+    };
+    match num.trim().parse::<f64>() {
+        Ok(n) if n >= 0.0 && n.is_finite() => Ok((n * scale) as u64),
+        _ => Err(anyhow!("invalid rate: {}", s)),
+    }
+}
+
+/// A time-of-day window of a [`RateSchedule`]; `end` before `start` wraps past midnight.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateWindow {
+    /// Minutes since midnight
+    pub start: u32,
+    pub end: u32,
+    /// Bytes per second; None is full speed
+    pub rate: Option<u64>,
+}
+
+impl RateWindow {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Download speed by local time of day: the first matching window, else `default`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateSchedule {
+    pub default: Option<u64>,
+    pub windows: Vec<RateWindow>,
+}
+
+impl RateSchedule {
+    /// `limit_rate` as in [`parse_rate`]; `schedule` as comma-separated `HH:MM-HH:MM=RATE`
+    /// entries, where a rate of 0 means full speed (e.g. "01:00-07:00=0").
+    pub fn parse(limit_rate: Option<&str>, schedule: Option<&str>) -> Result<Self> {
+        let default = limit_rate.map(parse_rate).transpose()?.filter(|&r| r > 0);
+        let mut windows = Vec::new();
+        for entry in schedule.unwrap_or("").split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (span, rate) = entry.split_once('=').ok_or_else(|| anyhow!("schedule entry needs =RATE: {}", entry))?;
+            let (start, end) = span.split_once('-').ok_or_else(|| anyhow!("schedule entry needs HH:MM-HH:MM: {}", entry))?;
+            let rate = parse_rate(rate)?;
+            windows.push(RateWindow { start: parse_clock_time(start)?, end: parse_clock_time(end)?, rate: (rate > 0).then_some(rate) });
+        }
+        Ok(Self { default, windows })
+    }
+
+    /// The limit at `minute` past local midnight.
+    pub fn rate_at(&self, minute: u32) -> Option<u64> {
+        match self.windows.iter().find(|w| w.contains(minute)) {
+            Some(w) => w.rate,
+            None => self.default,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.default.is_none() && self.windows.iter().all(|w| w.rate.is_none())
+    }
+}
+
+fn parse_clock_time(s: &str) -> Result<u32> {
+    let (h, m) = s.trim().split_once(':').ok_or_else(|| anyhow!("invalid time: {}", s))?;
+    match (h.parse::<u32>(), m.parse::<u32>()) {
+        (Ok(h), Ok(m)) if h <= 24 && m < 60 && h * 60 + m <= 24 * 60 => Ok(h * 60 + m),
+        _ => Err(anyhow!("invalid time: {}", s)),
+    }
+}
+
+/// Time source of a [`RateLimiter`].
+pub trait Clock: Send + Sync {
+    /// Monotonic time since an arbitrary start.
+    fn now(&self) -> Duration;
+    /// Minutes since local midnight.
+    fn minute_of_day(&self) -> u32;
+    fn sleep(&self, d: Duration) -> BoxFuture<'static, ()>;
+}
+
+pub struct SystemClock(std::time::Instant);
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self(std::time::Instant::now())
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    fn minute_of_day(&self) -> u32 {
+        use chrono::Timelike;
+        let t = chrono::Local::now();
+        t.hour() * 60 + t.minute()
+    }
+
+    fn sleep(&self, d: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(d))
+    }
+}
+
+/// A token bucket shared by all downloads. It holds up to one second of the current rate;
+/// a read larger than what is left is let through and paid for by waiting.
+pub struct RateLimiter {
+    schedule: RateSchedule,
+    clock: Arc<dyn Clock>,
+    /// (tokens, time of the last refill); tokens go negative while a read is paid off
+    bucket: std::sync::Mutex<(f64, Duration)>,
+}
+
+impl RateLimiter {
+    pub fn new(schedule: RateSchedule) -> Self {
+        Self::with_clock(schedule, Arc::new(SystemClock::default()))
+    }
+
+    pub fn with_clock(schedule: RateSchedule, clock: Arc<dyn Clock>) -> Self {
+        let start = clock.now();
+        let tokens = schedule.rate_at(clock.minute_of_day()).unwrap_or(0) as f64;
+        Self { schedule, clock, bucket: std::sync::Mutex::new((tokens, start)) }
+    }
+
+    /// Waits until `bytes` more may be read.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = self.clock.now();
+            let Some(rate) = self.schedule.rate_at(self.clock.minute_of_day()) else {
+                *bucket = (0.0, now);
+                return;
+            };
+            let rate = rate as f64;
+            let elapsed = now.saturating_sub(bucket.1).as_secs_f64();
+            bucket.0 = (bucket.0 + elapsed * rate).min(rate) - bytes as f64;
+            bucket.1 = now;
+            if bucket.0 >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.0 / rate)
+        };
+        self.clock.sleep(wait).await;
+    }
+}
+
 pub async fn ffmpeg_mux(video_path: &str, audio_path: &str, out_path: &str) -> Result<()> {
+    ffmpeg_mux_with(video_path, audio_path, out_path, &MuxOptions::default()).await
+}
+
+/// Whether an `ffmpeg` binary can be run. Checked once per process.
+pub fn ffmpeg_available() -> bool {
+    static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("ffmpeg")
+            .arg("-version")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    })
+}
+
+/// Extra streams and tags embedded by `ffmpeg_mux_with`.
+#[derive(Debug, Clone, Default)]
+pub struct MuxOptions {
+    pub subtitles: Vec<MuxSubtitle>,
+    /// Cover image (jpg/png)
+    pub cover: Option<String>,
+    /// Global tags such as title, artist, date
+    pub metadata: Vec<(String, String)>,
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MuxSubtitle {
+    /// SRT, WebVTT or ASS file
+    pub path: String,
+    /// ISO 639-2 code, e.g. "chi"
+    pub language: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    /// Start in milliseconds
+    pub start_ms: u64,
+    /// End in milliseconds
+    pub end_ms: u64,
+    pub title: String,
+}
+
+impl MuxOptions {
+    pub fn is_empty(&self) -> bool {
+        self.subtitles.is_empty() && self.cover.is_none() && self.metadata.is_empty() && self.chapters.is_empty()
+    }
+}
+
+/// `ffmpeg_mux` plus subtitle streams, cover art, tags and chapters.
+/// Tags and chapters are passed through a temporary FFMETADATA file next to the output.
+pub async fn ffmpeg_mux_with(video_path: &str, audio_path: &str, out_path: &str, opts: &MuxOptions) -> Result<()> {
     // Check ffmpeg presence
     let status = Command::new("ffmpeg")
         .arg("-version")
@@ -93,15 +660,121 @@ pub async fn ffmpeg_mux(video_path: &str, audio_path: &str, out_path: &str) -> R
         return Err(anyhow!("ffmpeg not available"));
     }
 
+    let meta_path = if opts.metadata.is_empty() && opts.chapters.is_empty() {
+        None
+    } else {
+        let p = format!("{}.ffmeta.txt", out_path);
+        std::fs::write(&p, ffmetadata(&opts.metadata, &opts.chapters)).context("write ffmetadata")?;
+        Some(p)
+    };
     let status = Command::new("ffmpeg")
-        .args(["-y", "-i", video_path, "-i", audio_path, "-c", "copy", out_path])
+        .args(ffmpeg_mux_args(video_path, audio_path, out_path, opts, meta_path.as_deref()))
         .status()
-        .context("ffmpeg mux run")?;
+        .context("ffmpeg mux run");
+    if let Some(p) = &meta_path {
+        let _ = std::fs::remove_file(p);
+    }
+    let status = status?;
     if !status.success() {
         return Err(anyhow!("ffmpeg mux failed with status {:?}", status.code()));
     }
     Ok(())
 }
 
+/// ffmpeg arguments for `ffmpeg_mux_with`. Subtitles become mov_text in mp4 and are copied
+/// as-is into mkv; the cover is an attached picture in mp4 and an attachment in mkv.
+pub fn ffmpeg_mux_args(video_path: &str, audio_path: &str, out_path: &str, opts: &MuxOptions, meta_path: Option<&str>) -> Vec<String> {
+    let mkv = Path::new(out_path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("mkv"));
+    let mut args: Vec<String> = vec!["-y".into(), "-i".into(), video_path.into(), "-i".into(), audio_path.into()];
+    if opts.is_empty() {
+        args.extend(["-c", "copy", out_path].map(String::from));
+        return args;
+    }
+
+    let mut maps: Vec<String> = vec!["-map".into(), "0".into(), "-map".into(), "1".into()];
+    let mut next_input = 2;
+    for sub in &opts.subtitles {
+        args.extend(["-i".into(), sub.path.clone()]);
+        maps.extend(["-map".into(), next_input.to_string()]);
+        next_input += 1;
+    }
+    let cover_input = match (&opts.cover, mkv) {
+        (Some(cover), false) => {
+            args.extend(["-i".into(), cover.clone()]);
+            maps.extend(["-map".into(), next_input.to_string()]);
+            next_input += 1;
+            true
+        }
+        _ => false,
+    };
+    if let Some(meta) = meta_path {
+        args.extend(["-i".into(), meta.into()]);
+        maps.extend([
+            "-map_metadata".into(),
+            next_input.to_string(),
+            "-map_chapters".into(),
+            next_input.to_string(),
+        ]);
+    }
+    args.extend(maps);
+    args.extend(["-c".into(), "copy".into()]);
+    if !opts.subtitles.is_empty() {
+        args.extend(["-c:s".into(), if mkv { "copy" } else { "mov_text" }.into()]);
+        for (i, sub) in opts.subtitles.iter().enumerate() {
+            args.push(format!("-metadata:s:s:{}", i));
+            args.push(format!("language={}", sub.language));
+            args.push(format!("-metadata:s:s:{}", i));
+            args.push(format!("title={}", sub.title));
+        }
+    }
+    if let Some(cover) = &opts.cover {
+        if cover_input {
+            args.extend(["-disposition:v:1", "attached_pic"].map(String::from));
+        } else {
+            let ext = Path::new(cover).extension().and_then(|e| e.to_str()).unwrap_or("jpg").to_ascii_lowercase();
+            let mime = if ext == "png" { "image/png" } else { "image/jpeg" };
+            args.extend([
+                "-attach".into(),
+                cover.clone(),
+                "-metadata:s:t".into(),
+                format!("mimetype={}", mime),
+                "-metadata:s:t".into(),
+                format!("filename=cover.{}", ext),
+            ]);
+        }
+    }
+    args.push(out_path.into());
+    args
+}
+
+/// Renders tags and chapters as an FFMETADATA1 file.
+pub fn ffmetadata(metadata: &[(String, String)], chapters: &[Chapter]) -> String {
+    fn esc(s: &str) -> String {
+        let mut out = String::with_capacity(s.len());
+        for c in s.chars() {
+            if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    }
+    let mut out = String::from(";FFMETADATA1\n");
+    for (k, v) in metadata {
+        out.push_str(&format!("{}={}\n", esc(k), esc(v)));
+    }
+    for ch in chapters {
+        out.push_str(&format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            ch.start_ms,
+            ch.end_ms,
+            esc(&ch.title)
+        ));
+    }
+    out
+}
+
 // Needed for bytes_stream() iteration
 use futures_util::StreamExt;
@@ -0,0 +1,200 @@
+//! FLV demuxing for legacy `durl` segments: H.264 video and AAC audio tags become
+//! [`SegmentTrack`]s with MP4 sample descriptions (`avc1`/`avcC`, `mp4a`/`esds`).
+
+use crate::concat::{SegmentSample, SegmentTrack};
+use crate::remux::write_box;
+use anyhow::{anyhow, Context, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+const TAG_AUDIO: u8 = 8;
+const TAG_VIDEO: u8 = 9;
+const TAG_SCRIPT: u8 = 18;
+const CODEC_AVC: u8 = 7;
+const SOUND_AAC: u8 = 10;
+/// Samples per AAC frame
+const AAC_FRAME: u32 = 1024;
+
+fn u24(b: &[u8]) -> u32 {
+    (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32
+}
+
+fn read_at<R: Read + Seek>(r: &mut R, pos: u64, len: usize) -> Result<Vec<u8>> {
+    r.seek(SeekFrom::Start(pos))?;
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf).context("read FLV tag")?;
+    Ok(buf)
+}
+
+/// Demuxes an FLV file. Video must be H.264 and audio AAC, as served by Bilibili.
+pub fn read_flv_segment<R: Read + Seek>(r: &mut R) -> Result<Vec<SegmentTrack>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let header = read_at(r, 0, 9)?;
+    if &header[..3] != b"FLV" {
+        return Err(anyhow!("not an FLV file"));
+    }
+    let mut pos = u32::from_be_bytes(header[5..9].try_into().unwrap()) as u64 + 4;
+
+    let mut avcc = None;
+    let mut asc = None;
+    let (mut width, mut height) = (0u16, 0u16);
+    // (offset, size, timestamp ms, composition offset ms, keyframe)
+    let mut video = Vec::new();
+    let mut audio = Vec::new();
+    while pos + 11 <= len {
+        let h = read_at(r, pos, 11)?;
+        let size = u24(&h[1..4]) as u64;
+        let time = u24(&h[4..7]) | (h[7] as u32) << 24;
+        let data = pos + 11;
+        // A truncated last tag ends the segment
+        if data + size > len {
+            break;
+        }
+        match h[0] & 0x1f {
+            TAG_VIDEO if size >= 5 => {
+                let b = read_at(r, data, 5)?;
+                if b[0] & 0x0f != CODEC_AVC {
+                    return Err(anyhow!("unsupported FLV video codec {}", b[0] & 0x0f));
+                }
+                // Composition time is a signed 24-bit value
+                let cts = ((u24(&b[2..5]) << 8) as i32) >> 8;
+                match b[1] {
+                    0 => avcc = Some(read_at(r, data + 5, size as usize - 5)?),
+                    1 => video.push((data + 5, size as u32 - 5, time, cts, b[0] >> 4 == 1)),
+                    _ => {}
+                }
+            }
+            TAG_AUDIO if size >= 2 => {
+                let b = read_at(r, data, 2)?;
+                if b[0] >> 4 != SOUND_AAC {
+                    return Err(anyhow!("unsupported FLV audio format {}", b[0] >> 4));
+                }
+                match b[1] {
+                    0 => asc = Some(read_at(r, data + 2, size as usize - 2)?),
+                    _ => audio.push((data + 2, size as u32 - 2, time)),
+                }
+            }
+            TAG_SCRIPT => {
+                let meta = read_at(r, data, size as usize)?;
+                width = amf_number(&meta, "width").unwrap_or(0.0) as u16;
+                height = amf_number(&meta, "height").unwrap_or(0.0) as u16;
+            }
+            _ => {}
+        }
+        pos = data + size + 4;
+    }
+
+    let mut tracks = Vec::new();
+    if let (Some(avcc), false) = (avcc, video.is_empty()) {
+        let mut samples: Vec<SegmentSample> = video
+            .iter()
+            .map(|&(offset, size, time, cts, keyframe)| SegmentSample {
+                input: 0,
+                offset,
+                size,
+                dts: time as u64,
+                cts_offset: cts,
+                duration: 0,
+                keyframe,
+            })
+            .collect();
+        set_durations(&mut samples);
+        tracks.push(SegmentTrack { handler: *b"vide", timescale: 1000, width, height, stsd: avc_stsd(&avcc, width, height), media_time: 0, samples });
+    }
+    if let (Some(asc), Some(&(_, _, start))) = (asc, audio.first()) {
+        let (sample_rate, channels) = aac_params(&asc)?;
+        // Timestamps are whole milliseconds; count frames in the sample rate instead
+        let first = start as u64 * sample_rate as u64 / 1000;
+        let samples = audio
+            .iter()
+            .enumerate()
+            .map(|(i, &(offset, size, _))| SegmentSample {
+                input: 0,
+                offset,
+                size,
+                dts: first + i as u64 * AAC_FRAME as u64,
+                cts_offset: 0,
+                duration: AAC_FRAME,
+                keyframe: true,
+            })
+            .collect();
+        tracks.push(SegmentTrack { handler: *b"soun", timescale: sample_rate, width: 0, height: 0, stsd: aac_stsd(&asc, sample_rate, channels), media_time: 0, samples });
+    }
+    if tracks.is_empty() {
+        return Err(anyhow!("no H.264 or AAC data in FLV"));
+    }
+    Ok(tracks)
+}
+
+/// Each sample lasts until the next; the last one repeats the previous duration.
+fn set_durations(samples: &mut [SegmentSample]) {
+    for i in 0..samples.len() {
+        samples[i].duration = match samples.get(i + 1) {
+            Some(next) => next.dts.saturating_sub(samples[i].dts) as u32,
+            None if i > 0 => samples[i - 1].duration,
+            None => 40,
+        };
+    }
+}
+
+/// A number property of the `onMetaData` script tag.
+fn amf_number(meta: &[u8], key: &str) -> Option<f64> {
+    // Property: u16 name length, name, then type 0 (number) and a big-endian f64
+    let mut needle = (key.len() as u16).to_be_bytes().to_vec();
+    needle.extend_from_slice(key.as_bytes());
+    needle.push(0);
+    let at = meta.windows(needle.len()).position(|w| w == needle.as_slice())? + needle.len();
+    meta.get(at..at + 8).map(|b| f64::from_be_bytes(b.try_into().unwrap()))
+}
+
+/// Sample rate and channel count from an AAC AudioSpecificConfig.
+fn aac_params(asc: &[u8]) -> Result<(u32, u16)> {
+    const RATES: [u32; 13] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+    if asc.len() < 2 {
+        return Err(anyhow!("short AAC AudioSpecificConfig"));
+    }
+    let index = ((asc[0] & 0x07) << 1 | asc[1] >> 7) as usize;
+    let rate = *RATES.get(index).ok_or_else(|| anyhow!("unsupported AAC sample rate index {}", index))?;
+    Ok((rate, ((asc[1] >> 3) & 0x0f) as u16))
+}
+
+/// `stsd` payload with one sample entry.
+fn stsd(kind: &[u8; 4], entry: &[u8]) -> Vec<u8> {
+    let mut out = [vec![0, 0, 0, 0], 1u32.to_be_bytes().to_vec()].concat();
+    write_box(&mut out, kind, entry);
+    out
+}
+
+fn avc_stsd(avcc: &[u8], width: u16, height: u16) -> Vec<u8> {
+    let mut p = vec![0u8; 6];
+    p.extend_from_slice(&1u16.to_be_bytes());
+    p.extend_from_slice(&[0; 16]);
+    p.extend_from_slice(&width.to_be_bytes());
+    p.extend_from_slice(&height.to_be_bytes());
+    p.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+    p.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+    p.extend_from_slice(&[0; 4]);
+    p.extend_from_slice(&1u16.to_be_bytes());
+    p.extend_from_slice(&[0; 32]);
+    p.extend_from_slice(&0x0018u16.to_be_bytes());
+    p.extend_from_slice(&(-1i16).to_be_bytes());
+    write_box(&mut p, b"avcC", avcc);
+    stsd(b"avc1", &p)
+}
+
+fn aac_stsd(asc: &[u8], sample_rate: u32, channels: u16) -> Vec<u8> {
+    let descriptor = |tag: u8, body: &[u8]| [&[tag, body.len() as u8][..], body].concat();
+    let dsi = descriptor(0x05, asc);
+    // objectTypeIndication 0x40 (AAC), streamType audio, no buffer/bitrate hints
+    let dcd = descriptor(0x04, &[&[0x40, 0x15][..], &[0; 11], &dsi].concat());
+    let es = descriptor(0x03, &[&[0, 0, 0][..], &dcd, &descriptor(0x06, &[0x02])].concat());
+
+    let mut p = vec![0u8; 6];
+    p.extend_from_slice(&1u16.to_be_bytes());
+    p.extend_from_slice(&[0; 8]);
+    p.extend_from_slice(&channels.max(1).to_be_bytes());
+    p.extend_from_slice(&16u16.to_be_bytes());
+    p.extend_from_slice(&[0; 4]);
+    p.extend_from_slice(&(sample_rate.min(0xffff) << 16).to_be_bytes());
+    write_box(&mut p, b"esds", &[&[0u8; 4][..], &es].concat());
+    stsd(b"mp4a", &p)
+}
@@ -1,6 +1,18 @@
 pub mod cli;
 pub mod wbi;
 pub mod bilibili;
+pub mod bangumi;
+pub mod playlist;
+pub mod archive;
+pub mod metadata;
+pub mod danmaku;
+pub mod subtitles;
 pub mod downloader;
+pub mod segmented;
+pub mod remux;
+pub mod mkv;
+pub mod concat;
+pub mod flv;
+pub mod postprocess;
 pub mod util;
 pub mod cookies_browser;
@@ -1,8 +1,10 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 
-use bilibili_dl::{cli, bilibili, downloader, cookies_browser};
-use bilibili_dl::util::{parse_format, expand_template, sanitize_filename};
+use bilibili_dl::{cli, bilibili, concat, danmaku, downloader, cookies_browser, metadata, mkv, playlist, postprocess, remux, subtitles};
+use bilibili_dl::archive::DownloadArchive;
+use bilibili_dl::util::{parse_format, expand_template_fields, read_batch_file, sanitize_filename, TemplateFields};
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -10,6 +12,8 @@ async fn main() -> Result<()> {
 
     if args.list_formats {
         run_list_formats(args).await
+    } else if args.list_subs {
+        run_list_subs(args).await
     } else if args.print_only {
         run_and_print(args).await
     } else {
@@ -19,79 +23,197 @@ async fn main() -> Result<()> {
 
 async fn run_and_print(args: cli::Args) -> Result<()> {
     let client = build_client(&args)?;
-    let (bvid, cid) = client
-        .resolve_bvid_and_cid(&args.input, args.page)
-        .await
-        .context("resolve BV and CID failed")?;
 
+    for input in collect_inputs(&args)? {
+        let mut queue = resolve_queue(&client, &args, &input, args.max_downloads).await?;
+        while let Some((_, entry)) = queue.next_entry(&client).await? {
+            for page in entry_pages(&client, &args, &entry).await? {
+                print_page(&client, &args, &page).await?;
+            }
+        }
+    }
+    save_cookies(&client, &args);
+    Ok(())
+}
+
+async fn print_page(client: &bilibili::BiliClient, args: &cli::Args, page: &bilibili::VideoPage) -> Result<()> {
     let play = client
-        .get_playurl(&bvid, cid, args.quality, args.fnval)
+        .get_playurl_for(page, args.quality, args.fnval)
         .await
         .context("get playurl failed")?;
 
-    if let Some(dash) = play.data.and_then(|d| d.dash) {
-        let (vsel, asel) = if let Some(ref fstr) = args.format {
-            bilibili::select_streams_with_format(&dash, fstr)
-        } else {
-            let fmt = parse_format(&args.format, args.prefer_codec.as_deref());
-            bilibili::select_streams(&dash, fmt.prefer_codec.as_deref(), fmt.max_height, fmt.want_video, fmt.want_audio)
-        };
-        println!("bvid: {}  cid: {}", bvid, cid);
+    let data = play.data;
+    if let Some(dash) = data.as_ref().and_then(|d| d.dash.as_ref()) {
+        let (vsel, asel) = select_streams(args, dash);
+        println!("bvid: {}  cid: {}  page: {}", page.bvid, page.cid, page.page);
         if let Some(v) = vsel {
-            println!("video[{} {} {}p]: {}", v.id, v.codecs, v.height.unwrap_or(0), v.base_url);
+            println!("video[{} {} {}p {}]: {}", v.id, v.codecs, v.height.unwrap_or(0), v.dynamic_range(), v.base_url);
         }
         if let Some(a) = asel {
-            println!("audio[{} {}]: {}", a.id, a.codecs, a.base_url);
+            let label = if a.label().is_empty() { String::new() } else { format!(" {}", a.label()) };
+            println!("audio[{} {}{}]: {}", a.id, a.codecs, label, a.base_url);
+        }
+    } else if let Some(data) = data.as_ref().filter(|d| !d.durl.is_empty()) {
+        println!("bvid: {}  cid: {}  page: {}", page.bvid, page.cid, page.page);
+        for d in &data.durl {
+            println!("{}[{} {}s]: {}", data.durl_ext(), d.order, d.length / 1000, d.url);
         }
     } else {
-        println!("No DASH data available (maybe login required or invalid params)");
-    }
-    if let (Some(jar), Some(path)) = (client.cookie_jar(), args.save_cookies.as_deref()) {
-        if let Err(e) = bilibili::save_jar_as_netscape(&jar, path) { eprintln!("save cookies failed: {e}"); }
+        println!("No DASH data available for {} page {} (maybe login required or invalid params)", page.bvid, page.page);
     }
     Ok(())
 }
 
 async fn run_list_formats(args: cli::Args) -> Result<()> {
     let client = build_client(&args)?;
-    let (bvid, cid) = client
-        .resolve_bvid_and_cid(&args.input, args.page)
-        .await
-        .context("resolve BV and CID failed")?;
 
+    for input in collect_inputs(&args)? {
+        let mut queue = resolve_queue(&client, &args, &input, None).await?;
+        while let Some((_, entry)) = queue.next_entry(&client).await? {
+            for page in entry_pages(&client, &args, &entry).await? {
+                list_page_formats(&client, &args, &page).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn list_page_formats(client: &bilibili::BiliClient, args: &cli::Args, page: &bilibili::VideoPage) -> Result<()> {
     let play = client
-        .get_playurl(&bvid, cid, args.quality, args.fnval)
+        .get_playurl_for(page, args.quality, args.fnval)
         .await
         .context("get playurl failed")?;
 
-    let Some(dash) = play.data.and_then(|d| d.dash) else {
-        eprintln!("No DASH data returned. Try with cookies or other quality.");
+    if let Some(data) = play.data.as_ref().filter(|d| d.dash.is_none() && !d.durl.is_empty()) {
+        let size: u64 = data.durl.iter().map(|d| d.size).sum();
+        let quality = data.quality.map(|q| data.quality_label(q)).unwrap_or_default();
+        println!("Formats for {} p{} (cid {}):", page.bvid, page.page, page.cid);
+        println!("legacy {} {}: {} segment(s), {:.1} MiB (no DASH)", data.durl_ext(), quality, data.durl.len(), size as f64 / 1048576.0);
+        return Ok(());
+    }
+    let Some((data, dash)) = play.data.and_then(|mut d| d.dash.take().map(|dash| (d, dash))) else {
+        eprintln!("No DASH data returned for {} page {}. Try with cookies or other quality.", page.bvid, page.page);
         return Ok(());
     };
 
-    println!("Formats for {} (cid {}):", bvid, cid);
-    println!("ID   type   res    codec         br (kbps)");
-    println!("---- ------ ------ ------------- ----------");
+    println!("Formats for {} p{} (cid {}):", page.bvid, page.page, page.cid);
+    println!("ID    type   res        fps    range codec         br (kbps)  note");
+    println!("----- ------ ---------- ------ ----- ------------- ---------- ------------");
     let mut vids = dash.video.clone();
     vids.sort_by(|a,b| a.height.cmp(&b.height).then(a.id.cmp(&b.id)));
     vids.reverse();
     for v in vids.iter() {
-        let h = v.height.unwrap_or(0);
+        let res = match (v.width, v.height) {
+            (Some(w), Some(h)) => format!("{}x{}", w, h),
+            (None, Some(h)) => format!("{}p", h),
+            _ => "----".to_string(),
+        };
+        let fps = v.fps().map(|f| format!("{:.0}", f)).unwrap_or_else(|| "--".to_string());
         let br = v.bandwidth.map(|x| x/1000).unwrap_or(0);
-        println!("{:<4} video  {:>4}p {:<13} {:>10}", v.id, h, v.codecs, br);
+        println!("{:<5} video  {:<10} {:>6} {:<5} {:<13} {:>10} {}", v.id, res, fps, v.dynamic_range(), v.codecs, br, data.quality_label(v.id));
+    }
+    let mut auds = dash.all_audio();
+    auds.sort_by_key(|a| a.id);
+    auds.reverse();
+    for a in auds.iter() {
+        let br = a.bandwidth.map(|x| x/1000).unwrap_or(0);
+        println!("{:<5} audio  {:<10} {:>6} {:<5} {:<13} {:>10} {}", a.id, "----", "--", "", a.codecs, br, a.label());
     }
-    if let Some(auds) = dash.audio.clone() {
-        let mut auds = auds;
-        auds.sort_by(|a,b| a.id.cmp(&b.id));
-        auds.reverse();
-        for a in auds.iter() {
-            let br = a.bandwidth.map(|x| x/1000).unwrap_or(0);
-            println!("{:<4} audio   ----  {:<13} {:>10}", a.id, a.codecs, br);
+    Ok(())
+}
+
+async fn run_list_subs(args: cli::Args) -> Result<()> {
+    let client = build_client(&args)?;
+
+    for input in collect_inputs(&args)? {
+        let mut queue = resolve_queue(&client, &args, &input, None).await?;
+        while let Some((_, entry)) = queue.next_entry(&client).await? {
+            for page in entry_pages(&client, &args, &entry).await? {
+                let tracks = client.get_subtitle_tracks(page.aid, page.cid).await.context("get subtitles failed")?;
+                if tracks.is_empty() {
+                    println!("{} p{} has no subtitles", page.bvid, page.page);
+                    continue;
+                }
+                println!("Subtitles for {} p{} (cid {}):", page.bvid, page.page, page.cid);
+                println!("Language   Type  Name");
+                for t in &tracks {
+                    println!("{:<10} {:<5} {}", t.lan, if t.is_ai() { "AI" } else { "CC" }, t.lan_doc);
+                }
+            }
         }
     }
     Ok(())
 }
 
+/// Positional inputs followed by the lines of --batch-file.
+fn collect_inputs(args: &cli::Args) -> Result<Vec<String>> {
+    let mut inputs = args.inputs.clone();
+    if let Some(path) = args.batch_file.as_deref() {
+        inputs.extend(read_batch_file(path)?);
+    }
+    if inputs.is_empty() {
+        return Err(anyhow!("no inputs given"));
+    }
+    Ok(inputs)
+}
+
+async fn resolve_queue(client: &bilibili::BiliClient, args: &cli::Args, input: &str, max_downloads: Option<usize>) -> Result<playlist::PlaylistQueue> {
+    let opts = playlist::ResolveOptions {
+        page_spec: args.page.clone(),
+        all_pages: args.all_pages,
+        whole_collection: args.whole_collection,
+        space_order: if args.space_order == "oldest" { playlist::SpaceOrder::Oldest } else { playlist::SpaceOrder::Newest },
+        max_items: args.space_limit,
+    };
+    let list = client
+        .resolve_playlist(input, &opts)
+        .await
+        .context("resolve BV and CID failed")?;
+    let selection = playlist::PlaylistSelection {
+        items: args.playlist_items.clone(),
+        start: args.playlist_start,
+        end: args.playlist_end,
+        reverse: args.playlist_reverse,
+        max_downloads,
+    };
+    let is_listing = list.entries.iter().any(|e| matches!(e, playlist::PlaylistEntry::Video { .. }));
+    let available = list.len();
+    let title = list.title.clone();
+    let queue = playlist::PlaylistQueue::new(list, &selection).context("invalid playlist selection")?;
+    if is_listing || queue.len() != available {
+        println!("{}: {} of {} items selected", title, queue.len(), available);
+    }
+    Ok(queue)
+}
+
+/// Pages to download for a queue entry; videos from listings are resolved here.
+async fn entry_pages(client: &bilibili::BiliClient, args: &cli::Args, entry: &playlist::PlaylistEntry) -> Result<Vec<bilibili::VideoPage>> {
+    match entry {
+        playlist::PlaylistEntry::Page(page) => Ok(vec![page.clone()]),
+        playlist::PlaylistEntry::Video { bvid, .. } => client
+            .resolve_pages(bvid, args.page.as_deref(), args.all_pages)
+            .await
+            .with_context(|| format!("resolve {} failed", bvid)),
+    }
+}
+
+fn select_streams(args: &cli::Args, dash: &bilibili::Dash) -> (Option<bilibili::DashVideo>, Option<bilibili::DashAudio>) {
+    if let Some(ref fstr) = args.format {
+        bilibili::select_streams_with_format(dash, fstr)
+    } else {
+        let fmt = parse_format(&args.format, args.prefer_codec.as_deref());
+        bilibili::select_streams(dash, fmt.prefer_codec.as_deref(), fmt.max_height, fmt.want_video, fmt.want_audio)
+    }
+}
+
+fn save_cookies(client: &bilibili::BiliClient, args: &cli::Args) {
+    if let (Some(jar), Some(path)) = (client.cookie_jar(), args.save_cookies.as_deref())
+        && let Err(e) = bilibili::save_jar_as_netscape(&jar, path)
+    {
+        eprintln!("save cookies failed: {e}");
+    }
+}
+
 fn build_client(args: &cli::Args) -> Result<bilibili::BiliClient> {
     // Priority: cookies-from-browser > cookies file > none
     if let Some(spec) = &args.cookies_from_browser {
@@ -112,95 +234,293 @@ fn build_client(args: &cli::Args) -> Result<bilibili::BiliClient> {
     )
 }
 
+#[derive(Default)]
+struct Summary {
+    done: usize,
+    skipped: usize,
+    failed: Vec<String>,
+}
+
 async fn run_and_download(args: cli::Args) -> Result<()> {
-    let client = bilibili::BiliClient::new(
-        args.user_agent.clone(),
-        args.referer.clone(),
-        args.cookies.clone(),
-        args.proxy.clone(),
-    )?;
-    let (bvid, cid) = client
-        .resolve_bvid_and_cid(&args.input, args.page)
-        .await
-        .context("resolve BV and CID failed")?;
+    let client = build_client(&args)?;
+    let schedule = downloader::RateSchedule::parse(args.limit_rate.as_deref(), args.limit_schedule.as_deref())?;
+    let limiter = (!schedule.is_unlimited()).then(|| Arc::new(downloader::RateLimiter::new(schedule)));
+    if args.muxer == "auto" && !args.no_mux && !downloader::ffmpeg_available() {
+        println!("ffmpeg not found; using the native muxer");
+    }
+    let inputs = collect_inputs(&args)?;
+    let mut archive = match args.download_archive.as_deref() {
+        Some(path) => Some(DownloadArchive::open(path)?),
+        None => None,
+    };
+
+    let mut summary = Summary::default();
+    for (i, input) in inputs.iter().enumerate() {
+        if inputs.len() > 1 {
+            println!("=== [{}/{}] {}", i + 1, inputs.len(), input);
+        }
+        let remaining = args.max_downloads.map(|m| m.saturating_sub(summary.done));
+        if remaining == Some(0) { break; }
+        if let Err(e) = download_input(&client, &args, limiter.as_ref(), input, remaining, &mut archive, &mut summary).await {
+            eprintln!("{}: {e:#}", input);
+            summary.failed.push(format!("{}: {e}", input));
+        }
+    }
 
+    save_cookies(&client, &args);
+
+    if inputs.len() > 1 || !summary.failed.is_empty() {
+        println!("Summary: {} downloaded, {} already archived, {} failed", summary.done, summary.skipped, summary.failed.len());
+        for f in &summary.failed {
+            println!("  failed: {}", f);
+        }
+    }
+    if !summary.failed.is_empty() {
+        return Err(anyhow!("{} downloads failed ({} succeeded)", summary.failed.len(), summary.done));
+    }
+    Ok(())
+}
+
+async fn download_input(
+    client: &bilibili::BiliClient,
+    args: &cli::Args,
+    limiter: Option<&Arc<downloader::RateLimiter>>,
+    input: &str,
+    max_downloads: Option<usize>,
+    archive: &mut Option<DownloadArchive>,
+    summary: &mut Summary,
+) -> Result<()> {
+    let mut queue = resolve_queue(client, args, input, max_downloads).await?;
+    let total = queue.len();
+
+    let mut pos = 0usize;
+    'queue: while let Some((index, entry)) = queue.next_entry(client).await? {
+        pos += 1;
+        if total > 1 {
+            println!("[{}/{}] #{} {} {}", pos, total, index, entry.bvid(), entry.title());
+        }
+        let pages = match entry_pages(client, args, &entry).await {
+            Ok(pages) => pages,
+            Err(e) => {
+                eprintln!("{e:#}");
+                summary.failed.push(format!("{}: {e}", entry.bvid()));
+                continue;
+            }
+        };
+        for page in &pages {
+            if queue.max_reached() { break; }
+            if let Some(a) = archive.as_ref() && a.contains(&page.bvid, page.cid) {
+                println!("{} p{} (cid {}) already in archive, skipping", page.bvid, page.page, page.cid);
+                summary.skipped += 1;
+                if args.break_on_existing {
+                    println!("Stopping at archived item (--break-on-existing).");
+                    break 'queue;
+                }
+                continue;
+            }
+            if page.page_count > 1 {
+                println!("[page {}/{}] {}", page.page, page.page_count, page.page_title);
+            }
+            match download_page(client, args, limiter, page).await {
+                Ok(complete) => {
+                    summary.done += 1;
+                    queue.record_download();
+                    if let Some(a) = archive.as_mut() {
+                        if !complete {
+                            println!("Not recording {} p{} in the archive: no final file was written", page.bvid, page.page);
+                        } else if let Err(e) = a.record(&page.bvid, page.cid) {
+                            eprintln!("update download archive failed: {e}");
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{} page {} (cid {}) failed: {e:#}", page.bvid, page.page, page.cid);
+                    summary.failed.push(format!("{} p{}: {e}", page.bvid, page.page));
+                }
+            }
+        }
+    }
+    if queue.max_reached() {
+        println!("Reached --max-downloads ({}), stopping.", summary.done);
+    }
+    Ok(())
+}
+
+/// Downloads (and muxes) one page. Returns false when only the tracks were saved (--no-mux,
+/// a single track or a failed mux); such pages are not recorded in the download archive.
+async fn download_page(
+    client: &bilibili::BiliClient,
+    args: &cli::Args,
+    limiter: Option<&Arc<downloader::RateLimiter>>,
+    page: &bilibili::VideoPage,
+) -> Result<bool> {
     let play = client
-        .get_playurl(&bvid, cid, args.quality, args.fnval)
+        .get_playurl_for(page, args.quality, args.fnval)
         .await
         .context("get playurl failed")?;
 
-    let title = client
-        .get_title(&bvid)
-        .await
-        .unwrap_or_else(|_| bvid.clone());
+    let mut data = play.data.ok_or_else(|| anyhow!("No DASH data returned. Try a different quality, or with cookies."))?;
+    // Without DASH, fall back to the legacy durl segments
+    let dash = data.dash.take();
+    let legacy = dash.is_none();
+    if legacy && data.durl.is_empty() {
+        return Err(anyhow!("No DASH data returned. Try a different quality, or with cookies."));
+    }
+    if legacy && args.extract_audio {
+        return Err(anyhow!("-x needs DASH audio; only legacy {} segments are available", data.durl_ext()));
+    }
 
-    let Some(dash) = play.data.and_then(|d| d.dash) else {
-        eprintln!("No DASH data returned. Try a different quality, or with cookies.");
-        return Ok(());
+    let (vsel, asel) = dash.as_ref().map(|d| select_streams(args, d)).unwrap_or((None, None));
+    // -x only needs the audio
+    let vsel = if args.extract_audio { None } else { vsel };
+    if !legacy && vsel.is_none() && asel.is_none() {
+        return Err(anyhow!("No suitable streams found."));
+    }
+
+    let audio_format = match (&asel, args.extract_audio) {
+        (Some(a), true) => Some(postprocess::AudioFormat::parse(&args.audio_format)?.resolve(&a.codecs)),
+        _ => None,
+    };
+    let container = match audio_format {
+        Some(f) => f.ext().to_string(),
+        // FLAC goes into mkv unless a container was asked for; E-AC-3 fits mp4
+        None => match (&args.merge_output_format, &asel) {
+            _ if legacy => "mp4".to_string(),
+            (Some(f), _) => f.clone(),
+            (None, Some(a)) if a.is_flac() && vsel.is_some() => "mkv".to_string(),
+            (None, _) => "mp4".to_string(),
+        },
+    };
+    let out_stem = if let Some(tpl) = args.output.clone().or(args.out.clone()) {
+        expand_template_fields(&tpl, &template_fields(page), &container)
+    } else if let Some(ep) = &page.episode {
+        sanitize_filename(&format!("{} - {}", ep.season_title, ep.episode_title))
+    } else if page.page_count > 1 {
+        sanitize_filename(&format!("{} P{} {}", page.title, page.page, page.page_title))
+    } else {
+        sanitize_filename(&page.title)
     };
 
-    let (vsel, asel) = if let Some(ref fstr) = args.format {
-        bilibili::select_streams_with_format(&dash, fstr)
+    let info = if args.write_info_json || args.embed_metadata || args.embed_thumbnail || args.extract_audio {
+        Some(client.get_video_info(&page.bvid).await.context("fetch video info failed")?)
     } else {
-        let fmt = parse_format(&args.format, args.prefer_codec.as_deref());
-        bilibili::select_streams(&dash, fmt.prefer_codec.as_deref(), fmt.max_height, fmt.want_video, fmt.want_audio)
+        None
     };
-    if vsel.is_none() && asel.is_none() {
-        eprintln!("No suitable streams found.");
-        return Ok(());
+
+    if args.write_info_json && let Some(info) = &info {
+        let path = format!("{}.info.json", out_stem);
+        metadata::write_info_json(&path, &metadata::InfoJson::new(page, info, vsel.as_ref(), asel.as_ref()))?;
+        println!("Wrote metadata -> {}", path);
     }
 
-    let container = args
-        .merge_output_format
-        .clone()
-        .unwrap_or_else(|| "mp4".to_string());
-    let out_stem = if let Some(tpl) = args.output.clone().or(args.out.clone()) {
-        expand_template(&tpl, &title, &bvid, cid, &container)
+    let subs = if args.write_subs || args.embed_subs {
+        fetch_subtitles(client, args, page).await.unwrap_or_else(|e| {
+            eprintln!("Warning: subtitles failed: {e:#}");
+            Vec::new()
+        })
     } else {
-        sanitize_filename(&title)
+        Vec::new()
     };
+    if args.write_subs {
+        match write_subtitle_files(&subs, &out_stem, write_subs_format(args)).await {
+            Ok(written) => {
+                for (_, path) in written {
+                    println!("Wrote subtitle -> {}", path);
+                }
+            }
+            Err(e) => eprintln!("Warning: writing subtitles failed: {e:#}"),
+        }
+    }
 
-    let mut video_path = None;
-    let mut audio_path = None;
-
-    if let Some(v) = vsel {
-        let vp = format!("{}-v-{}.m4s", out_stem, v.id);
-        downloader::download_with_progress(
-            &v.base_url,
-            &vp,
-            &args.user_agent,
-            &args.referer,
-            client.cookie_header(),
-            client.cookie_jar(),
-            args.resume,
-        ).await?;
-        video_path = Some(vp);
-    }
-
-    if let Some(a) = asel {
-        let ap = format!("{}-a-{}.m4s", out_stem, a.id);
-        downloader::download_with_progress(
-            &a.base_url,
-            &ap,
-            &args.user_agent,
-            &args.referer,
-            client.cookie_header(),
-            client.cookie_jar(),
-            args.resume,
-        ).await?;
-        audio_path = Some(ap);
+    // Danmaku written only for --embed-danmaku is removed after muxing
+    let mut danmaku_ass = None;
+    let mut danmaku_temp = Vec::new();
+    if args.write_danmaku || (args.embed_danmaku && !args.no_mux) {
+        match write_danmaku(client, args, page, &out_stem).await {
+            Ok((xml_path, ass_path)) => {
+                if args.write_danmaku {
+                    println!("Wrote danmaku -> {}, {}", xml_path, ass_path);
+                } else {
+                    danmaku_temp.extend([xml_path, ass_path.clone()]);
+                }
+                danmaku_ass = Some(ass_path);
+            }
+            Err(e) => eprintln!("Warning: danmaku failed: {e:#}"),
+        }
+    }
+
+    if legacy {
+        return download_durl(client, args, limiter, page, &data, &out_stem).await;
+    }
+
+    let video_path = vsel.as_ref().map(|v| format!("{}-v-{}.m4s", out_stem, v.id));
+    let audio_path = asel.as_ref().map(|a| format!("{}-a-{}.m4s", out_stem, a.id));
+    let mut jobs = Vec::new();
+    if let (Some(v), Some(vp)) = (&vsel, &video_path) {
+        jobs.push(downloader::TrackJob {
+            urls: downloader::rank_mirrors(&v.urls(), &args.cdn_host),
+            path: vp.clone(),
+            label: "video".into(),
+            source: stream_source(page, v.id, &v.codecs),
+            refresh: Some(dash_refresher(client, args, page, v.id, &v.codecs)),
+        });
+    }
+    if let (Some(a), Some(ap)) = (&asel, &audio_path) {
+        jobs.push(downloader::TrackJob {
+            urls: downloader::rank_mirrors(&a.urls(), &args.cdn_host),
+            path: ap.clone(),
+            label: "audio".into(),
+            source: stream_source(page, a.id, &a.codecs),
+            refresh: Some(dash_refresher(client, args, page, a.id, &a.codecs)),
+        });
+    }
+    downloader::download_tracks(&jobs, &download_options(client, args, limiter)).await?;
+
+    if let (true, Some(ap), Some(a)) = (args.extract_audio, audio_path.as_deref(), &asel) {
+        return extract_audio(client, args, page, info.as_ref(), ap, a, &out_stem).await;
     }
 
     if args.no_mux {
         println!("Saved tracks. Skipping mux (--no-mux). Done.");
-        return Ok(());
+        return Ok(false);
     }
 
     if let (Some(vp), Some(ap)) = (video_path.as_deref(), audio_path.as_deref()) {
         let out_path = format!("{}.{}", out_stem, container);
-        match downloader::ffmpeg_mux(vp, ap, &out_path).await {
+        let native = use_native_muxer(args);
+        // The native mp4 muxer writes the tracks only; don't fetch what it would drop
+        let (mut mux, mut temp_files) = if native && container == "mp4" {
+            if args.embed_subs || args.embed_metadata || args.embed_chapters || args.embed_thumbnail {
+                eprintln!("Warning: the native mp4 muxer ignores --embed-*; use ffmpeg or --merge-output-format mkv");
+            }
+            Default::default()
+        } else {
+            mux_options(client, args, page, info.as_ref(), &subs, &out_stem).await?
+        };
+        temp_files.extend(danmaku_temp);
+        if args.embed_danmaku && let Some(path) = &danmaku_ass {
+            if container == "mkv" {
+                mux.subtitles.push(downloader::MuxSubtitle {
+                    path: path.clone(),
+                    language: "chi".to_string(),
+                    title: "Danmaku".to_string(),
+                });
+            } else {
+                eprintln!("Warning: --embed-danmaku needs --merge-output-format mkv; skipped");
+            }
+        }
+        let muxed = match (native, container.as_str()) {
+            (false, _) => downloader::ffmpeg_mux_with(vp, ap, &out_path, &mux).await,
+            (true, "mkv") => mkv::native_mux_mkv(vp, ap, &out_path, &mux).await,
+            (true, "mp4") => remux::native_mux(vp, ap, &out_path).await,
+            (true, other) => Err(anyhow!("the native muxer can't write {}", other)),
+        };
+        for f in &temp_files {
+            let _ = tokio::fs::remove_file(f).await;
+        }
+        match muxed {
             Ok(_) => {
-                println!("Muxed -> {}", out_path);
+                println!("Muxed{} -> {}", if native { " (native)" } else { "" }, out_path);
                 let do_cleanup = if args.no_cleanup { false } else { args.cleanup };
                 if do_cleanup {
                     let _ = tokio::fs::remove_file(vp).await;
@@ -208,16 +528,299 @@ async fn run_and_download(args: cli::Args) -> Result<()> {
                 }
             }
             Err(e) => {
-                eprintln!("ffmpeg mux failed: {e}. Tracks left as-is");
+                eprintln!("mux failed: {e}. Tracks left as-is");
+                return Ok(false);
             }
         }
     }
 
-    if let (Some(jar), Some(path)) = (client.cookie_jar(), args.save_cookies.as_deref()) {
-        if let Err(e) = bilibili::save_jar_as_netscape(&jar, path) { eprintln!("save cookies failed: {e}"); }
+    // A single track is left as a bare .m4s
+    Ok(video_path.is_some() && audio_path.is_some())
+}
+
+/// Downloads the segments of a legacy `durl` stream and joins them into `<stem>.mp4`.
+/// Returns false when the segments were saved but not joined (--no-mux or a failed join).
+async fn download_durl(
+    client: &bilibili::BiliClient,
+    args: &cli::Args,
+    limiter: Option<&Arc<downloader::RateLimiter>>,
+    page: &bilibili::VideoPage,
+    data: &bilibili::PlayUrlData,
+    out_stem: &str,
+) -> Result<bool> {
+    let ext = data.durl_ext();
+    if args.merge_output_format.as_deref().is_some_and(|f| f != "mp4") {
+        eprintln!("Warning: legacy {} streams are always saved as mp4", ext);
     }
+    let quality = data.quality.map(|q| data.quality_label(q)).unwrap_or_default();
+    println!("No DASH streams; downloading {} legacy {} segment(s) {}", data.durl.len(), ext, quality);
 
-    Ok(())
+    let mut segments = data.durl.clone();
+    segments.sort_by_key(|d| d.order);
+    let mut parts = Vec::new();
+    for d in &segments {
+        let path = format!("{}-durl-{}.{}", out_stem, d.order, ext);
+        let order = d.order;
+        let opts = downloader::DownloadOptions {
+            source: stream_source(page, data.quality.unwrap_or(0), ""),
+            refresh: Some(refresher(client, args, page, move |data| {
+                data.durl.iter().find(|d| d.order == order).map(bilibili::Durl::urls)
+            })),
+            ..download_options(client, args, limiter)
+        };
+        downloader::download_mirrors(&downloader::rank_mirrors(&d.urls(), &args.cdn_host), &path, &opts).await?;
+        parts.push(path);
+    }
+
+    if args.no_mux {
+        println!("Saved segments. Skipping join (--no-mux). Done.");
+        return Ok(false);
+    }
+    let out_path = format!("{}.mp4", out_stem);
+    match concat::concat_files(&parts, &out_path).await {
+        Ok(()) => {
+            println!("Joined {} segment(s) -> {}", parts.len(), out_path);
+            if !args.no_cleanup && args.cleanup {
+                for p in &parts {
+                    let _ = tokio::fs::remove_file(p).await;
+                }
+            }
+            Ok(true)
+        }
+        Err(e) => {
+            eprintln!("joining segments failed: {e:#}. Segments left as-is");
+            Ok(false)
+        }
+    }
+}
+
+fn download_options(
+    client: &bilibili::BiliClient,
+    args: &cli::Args,
+    limiter: Option<&Arc<downloader::RateLimiter>>,
+) -> downloader::DownloadOptions {
+    downloader::DownloadOptions {
+        user_agent: args.user_agent.clone(),
+        referer: args.referer.clone(),
+        cookie: client.cookie_header().map(str::to_string),
+        jar: client.cookie_jar(),
+        resume: args.resume,
+        concurrent_fragments: args.concurrent_fragments as usize,
+        limiter: limiter.cloned(),
+        ..Default::default()
+    }
+}
+
+fn stream_source(page: &bilibili::VideoPage, stream_id: i32, codecs: &str) -> downloader::StreamSource {
+    downloader::StreamSource { bvid: page.bvid.clone(), cid: page.cid, stream_id, codecs: codecs.to_string() }
+}
+
+/// Calls playurl again for `page` and takes the mirrors `pick` finds in the response.
+fn refresher<F>(client: &bilibili::BiliClient, args: &cli::Args, page: &bilibili::VideoPage, pick: F) -> downloader::Refresher
+where
+    F: Fn(&bilibili::PlayUrlData) -> Option<Vec<String>> + Send + Sync + 'static,
+{
+    let (client, page, cdn_host) = (client.clone(), page.clone(), args.cdn_host.clone());
+    let (quality, fnval) = (args.quality, args.fnval);
+    let pick = Arc::new(pick);
+    Arc::new(move || {
+        let (client, page, cdn_host, pick) = (client.clone(), page.clone(), cdn_host.clone(), pick.clone());
+        Box::pin(async move {
+            let data = client.get_playurl_for(&page, quality, fnval).await?.data.ok_or_else(|| anyhow!("playurl returned no data"))?;
+            let urls = pick(&data).ok_or_else(|| anyhow!("the stream is no longer offered"))?;
+            Ok(downloader::rank_mirrors(&urls, &cdn_host))
+        })
+    })
+}
+
+/// A refresher for the DASH stream `id` with `codecs`.
+fn dash_refresher(client: &bilibili::BiliClient, args: &cli::Args, page: &bilibili::VideoPage, id: i32, codecs: &str) -> downloader::Refresher {
+    let codecs = codecs.to_string();
+    refresher(client, args, page, move |data| data.dash.as_ref()?.stream_urls(id, &codecs))
+}
+
+fn use_native_muxer(args: &cli::Args) -> bool {
+    match args.muxer.as_str() {
+        "native" => true,
+        "ffmpeg" => false,
+        _ => !downloader::ffmpeg_available(),
+    }
+}
+
+/// -x: converts the downloaded audio track to `<stem>.<ext>` with tags (and cover/chapters
+/// when asked). Returns false when the conversion failed and the track was kept.
+async fn extract_audio(
+    client: &bilibili::BiliClient,
+    args: &cli::Args,
+    page: &bilibili::VideoPage,
+    info: Option<&bilibili::VideoInfo>,
+    audio_path: &str,
+    audio: &bilibili::DashAudio,
+    out_stem: &str,
+) -> Result<bool> {
+    let codecs = audio.codecs.as_str();
+    let format = postprocess::AudioFormat::parse(&args.audio_format)?.resolve(codecs);
+    let quality = postprocess::AudioQuality::parse(&args.audio_quality)?;
+    let out_path = format!("{}.{}", out_stem, format.ext());
+    let (mut mux, temp_files) = mux_options(client, args, page, info, &[], out_stem).await?;
+    mux.subtitles.clear();
+    let native = use_native_muxer(args);
+    let extracted = if native {
+        postprocess::extract_audio_native(audio_path, &out_path, format, codecs, &mux.metadata).await
+    } else {
+        postprocess::extract_audio_ffmpeg(audio_path, &out_path, format, codecs, quality, &mux).await
+    };
+    for f in &temp_files {
+        let _ = tokio::fs::remove_file(f).await;
+    }
+    match extracted {
+        Ok(()) => {
+            println!("Extracted audio{} -> {}", if native { " (native)" } else { "" }, out_path);
+            if !args.no_cleanup && args.cleanup {
+                let _ = tokio::fs::remove_file(audio_path).await;
+            }
+            Ok(true)
+        }
+        Err(e) => {
+            eprintln!("audio extraction failed: {e:#}. Track left as {}", audio_path);
+            Ok(false)
+        }
+    }
+}
+
+fn write_subs_format(args: &cli::Args) -> subtitles::SubFormat {
+    args.convert_subs
+        .as_deref()
+        .and_then(|f| subtitles::SubFormat::parse(f).ok())
+        .unwrap_or(subtitles::SubFormat::Json)
+}
+
+/// Fetches the subtitle tracks selected by --sub-langs; tracks that fail are skipped with a warning.
+async fn fetch_subtitles(client: &bilibili::BiliClient, args: &cli::Args, page: &bilibili::VideoPage) -> Result<Vec<(subtitles::SubtitleTrack, subtitles::SubtitleBody)>> {
+    let tracks = client.get_subtitle_tracks(page.aid, page.cid).await?;
+    let selected = subtitles::select_tracks(&tracks, &args.sub_langs);
+    if selected.is_empty() {
+        println!("No subtitles for {} p{}", page.bvid, page.page);
+    }
+    let mut subs = Vec::new();
+    for track in selected {
+        match client.get_subtitle(track).await {
+            Ok(body) => subs.push((track.clone(), body)),
+            Err(e) => eprintln!("Warning: subtitle {}: {e:#}", track.lan),
+        }
+    }
+    Ok(subs)
+}
+
+async fn write_subtitle_files<'a>(
+    subs: &'a [(subtitles::SubtitleTrack, subtitles::SubtitleBody)],
+    out_stem: &str,
+    format: subtitles::SubFormat,
+) -> Result<Vec<(&'a subtitles::SubtitleTrack, String)>> {
+    let mut written = Vec::new();
+    for (track, body) in subs {
+        let path = format!("{}.{}.{}", out_stem, track.lan, format.ext());
+        if let Some(parent) = std::path::Path::new(&path).parent() && !parent.as_os_str().is_empty() {
+            tokio::fs::create_dir_all(parent).await.ok();
+        }
+        tokio::fs::write(&path, format.render(body)?).await.context("write subtitle")?;
+        written.push((track, path));
+    }
+    Ok(written)
+}
+
+/// Collects what --embed-* asks for. Returns the options and the temporary files to delete after muxing.
+async fn mux_options(
+    client: &bilibili::BiliClient,
+    args: &cli::Args,
+    page: &bilibili::VideoPage,
+    info: Option<&bilibili::VideoInfo>,
+    subs: &[(subtitles::SubtitleTrack, subtitles::SubtitleBody)],
+    out_stem: &str,
+) -> Result<(downloader::MuxOptions, Vec<String>)> {
+    let mut mux = downloader::MuxOptions::default();
+    let mut temp_files = Vec::new();
+
+    if args.embed_subs {
+        // JSON can't be embedded; use the converted format, or SRT
+        let format = match write_subs_format(args) {
+            subtitles::SubFormat::Json => subtitles::SubFormat::Srt,
+            f => f,
+        };
+        let keep = args.write_subs && format == write_subs_format(args);
+        for (track, path) in write_subtitle_files(subs, out_stem, format).await? {
+            if !keep { temp_files.push(path.clone()); }
+            mux.subtitles.push(downloader::MuxSubtitle {
+                path,
+                language: subtitles::iso639_2(&track.lan).to_string(),
+                title: track.lan_doc.clone(),
+            });
+        }
+    }
+
+    if (args.embed_metadata || args.extract_audio) && let Some(info) = info {
+        mux.metadata = metadata::mux_tags(info, page);
+    }
+
+    if args.embed_chapters {
+        match client.get_player_info(page.aid, page.cid).await {
+            Ok(player) => mux.chapters = metadata::chapters_from_view_points(&player.view_points, page.duration),
+            Err(e) => eprintln!("Warning: chapters failed: {e:#}"),
+        }
+    }
+
+    if args.embed_thumbnail && let Some(info) = info {
+        match client.download_cover(&info.pic, out_stem).await {
+            Ok(path) => {
+                temp_files.push(path.clone());
+                mux.cover = Some(path);
+            }
+            Err(e) => eprintln!("Warning: cover failed: {e:#}"),
+        }
+    }
+
+    Ok((mux, temp_files))
+}
+
+/// Writes `<stem>.danmaku.xml` and `<stem>.danmaku.ass`; returns both paths.
+async fn write_danmaku(client: &bilibili::BiliClient, args: &cli::Args, page: &bilibili::VideoPage, out_stem: &str) -> Result<(String, String)> {
+    let list = client.get_danmaku(page.cid, page.duration).await?;
+    let xml_path = format!("{}.danmaku.xml", out_stem);
+    if let Some(parent) = std::path::Path::new(&xml_path).parent() && !parent.as_os_str().is_empty() {
+        tokio::fs::create_dir_all(parent).await.ok();
+    }
+    // The list.so body as served; comments from seg.so are rendered in its layout
+    let xml = list.xml.unwrap_or_else(|| danmaku::to_xml(page.cid, &list.items));
+    tokio::fs::write(&xml_path, xml).await.context("write danmaku xml")?;
+    let opts = danmaku::AssOptions {
+        font_size: args.danmaku_font_size,
+        opacity: args.danmaku_opacity,
+        density: args.danmaku_density,
+        ..Default::default()
+    };
+    let ass_path = format!("{}.danmaku.ass", out_stem);
+    tokio::fs::write(&ass_path, danmaku::to_ass(&list.items, &opts)).await.context("write danmaku ass")?;
+    Ok((xml_path, ass_path))
+}
+
+fn template_fields(page: &bilibili::VideoPage) -> TemplateFields {
+    let mut fields = TemplateFields {
+        title: page.title.clone(),
+        id: page.bvid.clone(),
+        aid: page.aid,
+        cid: page.cid,
+        page: page.page,
+        page_title: page.page_title.clone(),
+        ..Default::default()
+    };
+    if let Some(ep) = &page.episode {
+        fields.season = ep.season_title.clone();
+        fields.season_id = ep.season_id;
+        fields.episode = ep.episode_title.clone();
+        fields.episode_id = ep.ep_id;
+        fields.episode_number = ep.episode_number;
+    }
+    fields
 }
 
 // helpers moved to library (util.rs)
@@ -0,0 +1,128 @@
+use crate::bangumi::EpisodeInfo;
+use crate::bilibili::{BiliClient, DashAudio, DashVideo, VideoInfo, VideoPage, ViewPoint};
+use crate::downloader::Chapter;
+use anyhow::{anyhow, Context, Result};
+use reqwest::Url;
+use serde::Serialize;
+use std::path::Path;
+
+/// Contents of the `.info.json` sidecar: the downloaded page, the full video
+/// metadata and the formats that were selected for it.
+#[derive(Debug, Serialize)]
+pub struct InfoJson<'a> {
+    pub id: &'a str,
+    pub aid: u64,
+    pub cid: u64,
+    pub page: u32,
+    pub page_title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub episode: Option<&'a EpisodeInfo>,
+    pub info: &'a VideoInfo,
+    pub video_format: Option<&'a DashVideo>,
+    pub audio_format: Option<&'a DashAudio>,
+}
+
+impl<'a> InfoJson<'a> {
+    pub fn new(page: &'a VideoPage, info: &'a VideoInfo, video: Option<&'a DashVideo>, audio: Option<&'a DashAudio>) -> Self {
+        Self {
+            id: &page.bvid,
+            aid: page.aid,
+            cid: page.cid,
+            page: page.page,
+            page_title: &page.page_title,
+            episode: page.episode.as_ref(),
+            info,
+            video_format: video,
+            audio_format: audio,
+        }
+    }
+}
+
+pub fn write_info_json(path: impl AsRef<Path>, info: &InfoJson) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() && !parent.as_os_str().is_empty() {
+        std::fs::create_dir_all(parent).ok();
+    }
+    let json = serde_json::to_string_pretty(info)?;
+    std::fs::write(path, json).context("write info json")?;
+    Ok(())
+}
+
+/// Container tags for `--embed-metadata`.
+pub fn mux_tags(info: &VideoInfo, page: &VideoPage) -> Vec<(String, String)> {
+    let title = if page.episode.is_some() || page.page_count > 1 {
+        format!("{} - {}", page.title, page.page_title)
+    } else {
+        info.title.clone()
+    };
+    let mut tags = vec![
+        ("title", title),
+        ("artist", info.owner.name.clone()),
+        ("date", format_date(info.pubdate)),
+        ("description", info.desc.clone()),
+        ("comment", format!("https://www.bilibili.com/video/{}?p={}", page.bvid, page.page)),
+        ("genre", info.tname.clone()),
+    ];
+    if let Some(ep) = &page.episode {
+        tags.push(("album", ep.season_title.clone()));
+        tags.push(("track", ep.episode_number.to_string()));
+    }
+    tags.into_iter()
+        .filter(|(_, v)| !v.is_empty())
+        .map(|(k, v)| (k.to_string(), v))
+        .collect()
+}
+
+/// Chapters from the player's view points (type 2), clamped to `duration` seconds; points
+/// that start at or after the end are dropped.
+pub fn chapters_from_view_points(points: &[ViewPoint], duration: u64) -> Vec<Chapter> {
+    points
+        .iter()
+        .filter(|p| p.kind == 2)
+        .map(|p| (p, if duration > 0 { p.to.min(duration) } else { p.to }))
+        .filter(|(p, end)| *end > p.from)
+        .map(|(p, end)| Chapter {
+            start_ms: p.from * 1000,
+            end_ms: end * 1000,
+            title: p.content.clone(),
+        })
+        .collect()
+}
+
+/// `YYYY-MM-DD` (UTC) for a unix timestamp; empty for 0.
+fn format_date(unix: i64) -> String {
+    if unix <= 0 {
+        return String::new();
+    }
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm).
+    let z = unix.div_euclid(86_400) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+impl BiliClient {
+    /// Downloads the cover image to `<stem>.cover.<ext>` and returns the path.
+    pub async fn download_cover(&self, pic: &str, stem: &str) -> Result<String> {
+        if pic.is_empty() {
+            return Err(anyhow!("video has no cover"));
+        }
+        let url = Url::parse(&pic.replacen("http://", "https://", 1))?;
+        let ext = Path::new(url.path())
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase)
+            .filter(|e| e == "png" || e == "jpg" || e == "jpeg")
+            .unwrap_or_else(|| "jpg".to_string());
+        let body = self.get_bytes_retry(url).await?;
+        let path = format!("{}.cover.{}", stem, ext);
+        std::fs::write(&path, &body).context("write cover")?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,544 @@
+//! Native Matroska writer: the samples of the DASH tracks plus optional subtitle tracks,
+//! chapters, title and cover, without ffmpeg.
+
+use crate::downloader::{Chapter, MuxOptions};
+use crate::remux::{read_fmp4_track, Fmp4Track, ReadSeek};
+use anyhow::{anyhow, Context, Result};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Element ids
+const EBML: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const TRACKS: u32 = 0x1654_AE6B;
+const CHAPTERS: u32 = 0x1043_A770;
+const ATTACHMENTS: u32 = 0x1941_A469;
+const CLUSTER: u32 = 0x1F43_B675;
+const CUES: u32 = 0x1C53_BB6B;
+
+/// A subtitle track for the Matroska output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MkvSubtitle {
+    /// ISO 639-2 code
+    pub language: String,
+    pub name: String,
+    /// `Some(header)` for ASS (`S_TEXT/ASS`), `None` for plain text (`S_TEXT/UTF8`)
+    pub ass_header: Option<String>,
+    pub events: Vec<SubtitleEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtitleEvent {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// Cue text, or for ASS the Matroska block layout (`ReadOrder,Layer,Style,...,Text`)
+    pub text: String,
+}
+
+impl MkvSubtitle {
+    /// Loads an SRT, WebVTT or ASS file.
+    pub fn from_file(path: &str, language: &str, name: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("read subtitle {}", path))?;
+        let ass = Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case("ass"));
+        let (ass_header, events) = if ass { parse_ass(&text) } else { (None, parse_srt(&text)) };
+        Ok(Self { language: language.to_string(), name: name.to_string(), ass_header, events })
+    }
+}
+
+/// Cues of an SRT or WebVTT document.
+pub fn parse_srt(text: &str) -> Vec<SubtitleEvent> {
+    let text = text.replace("\r\n", "\n");
+    let mut events = Vec::new();
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|l| !l.contains("-->"));
+        let Some(timing) = lines.next() else { continue };
+        let Some((a, b)) = timing.split_once("-->") else { continue };
+        let (Some(start_ms), Some(end_ms)) = (parse_clock(a), parse_clock(b.split_whitespace().next().unwrap_or(""))) else { continue };
+        let body: Vec<&str> = lines.collect();
+        events.push(SubtitleEvent { start_ms, end_ms, text: body.join("\n") });
+    }
+    events
+}
+
+/// `HH:MM:SS,mmm`, `HH:MM:SS.mmm` or `MM:SS.mmm` to milliseconds.
+fn parse_clock(s: &str) -> Option<u64> {
+    let s = s.trim().replace(',', ".");
+    let (hms, frac) = s.split_once('.').unwrap_or((&s, "0"));
+    let mut secs = 0u64;
+    for part in hms.split(':') {
+        secs = secs * 60 + part.parse::<u64>().ok()?;
+    }
+    let frac: String = frac.chars().chain("000".chars()).take(3).collect();
+    Some(secs * 1000 + frac.parse::<u64>().ok()?)
+}
+
+/// Splits an ASS document into the codec private header and block events.
+pub fn parse_ass(text: &str) -> (Option<String>, Vec<SubtitleEvent>) {
+    let mut header = String::new();
+    let mut events = Vec::new();
+    for line in text.lines() {
+        let Some(rest) = line.strip_prefix("Dialogue:") else {
+            header.push_str(line);
+            header.push('\n');
+            continue;
+        };
+        // Layer,Start,End,Style,Name,MarginL,MarginR,MarginV,Effect,Text
+        let f: Vec<&str> = rest.trim_start().splitn(10, ',').collect();
+        if f.len() < 10 { continue; }
+        let (Some(start_ms), Some(end_ms)) = (parse_ass_time(f[1]), parse_ass_time(f[2])) else { continue };
+        let text = format!("{},{},{}", events.len(), f[0], f[3..].join(","));
+        events.push(SubtitleEvent { start_ms, end_ms, text });
+    }
+    (Some(header), events)
+}
+
+fn parse_ass_time(s: &str) -> Option<u64> {
+    let (hms, cs) = s.trim().split_once('.')?;
+    let mut secs = 0u64;
+    for part in hms.split(':') {
+        secs = secs * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(secs * 1000 + cs.parse::<u64>().ok()? * 10)
+}
+
+// ==== EBML ====
+
+fn put_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn put_size(out: &mut Vec<u8>, size: u64) {
+    let len = (1..=8).find(|&n| size < (1u64 << (7 * n)) - 1).unwrap_or(8);
+    let marked = size | (1u64 << (7 * len));
+    out.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+fn element(out: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    put_id(out, id);
+    put_size(out, payload.len() as u64);
+    out.extend_from_slice(payload);
+}
+
+fn uint(out: &mut Vec<u8>, id: u32, v: u64) {
+    let bytes = v.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    element(out, id, &bytes[skip..]);
+}
+
+fn float(out: &mut Vec<u8>, id: u32, v: f64) {
+    element(out, id, &v.to_be_bytes());
+}
+
+fn string(out: &mut Vec<u8>, id: u32, s: &str) {
+    element(out, id, s.as_bytes());
+}
+
+fn master(out: &mut Vec<u8>, id: u32, build: impl FnOnce(&mut Vec<u8>)) {
+    let mut inner = Vec::new();
+    build(&mut inner);
+    element(out, id, &inner);
+}
+
+// ==== Tracks ====
+
+/// Matroska codec id and codec private data for a DASH sample entry.
+fn codec_for(track: &Fmp4Track) -> Result<(&'static str, Option<Vec<u8>>)> {
+    let e = &track.entry;
+    let private = || (!e.config.is_empty()).then(|| e.config.clone());
+    Ok(match e.format.as_str() {
+        "avc1" | "avc3" => ("V_MPEG4/ISO/AVC", private()),
+        "hev1" | "hvc1" | "dvh1" | "dvhe" => ("V_MPEGH/ISO/HEVC", private()),
+        "av01" => ("V_AV1", private()),
+        "mp4a" => ("A_AAC", private()),
+        "fLaC" => ("A_FLAC", Some([b"fLaC".as_slice(), &e.config].concat())),
+        "ec-3" => ("A_EAC3", None),
+        "ac-3" => ("A_AC3", None),
+        other => return Err(anyhow!("unsupported codec for mkv: {}", other)),
+    })
+}
+
+/// A block to write: track number, presentation time and where its data comes from.
+struct Block {
+    track: u64,
+    /// Ordering key (decode time) in milliseconds
+    order_ms: i64,
+    pts_ms: i64,
+    keyframe: bool,
+    source: BlockSource,
+}
+
+enum BlockSource {
+    /// Input index and sample byte range
+    Sample(usize, u64, u32),
+    Text { data: Vec<u8>, duration_ms: u64 },
+}
+
+fn to_ms(t: i64, timescale: u32) -> i64 {
+    let ts = timescale.max(1) as i64;
+    (t * 1000 + ts / 2).div_euclid(ts)
+}
+
+/// Writes a Matroska file from DASH fMP4 inputs (video and/or audio) and the subtitle tracks,
+/// chapters, title and cover of `opts`.
+pub fn write_mkv<W: Write + Seek>(inputs: &mut [&mut dyn ReadSeek], out: &mut W, opts: &MkvOptions) -> Result<()> {
+    let tracks: Vec<Fmp4Track> = inputs.iter_mut().map(read_fmp4_track).collect::<Result<_>>()?;
+
+    // Gather blocks of all tracks, ordered by decode time
+    let mut blocks = Vec::new();
+    for (i, t) in tracks.iter().enumerate() {
+        for s in &t.samples {
+            let pts = s.dts as i64 + s.cts_offset - t.media_time;
+            blocks.push(Block {
+                track: i as u64 + 1,
+                order_ms: to_ms(s.dts as i64 - t.media_time, t.timescale),
+                pts_ms: to_ms(pts, t.timescale).max(0),
+                keyframe: s.keyframe,
+                source: BlockSource::Sample(i, s.offset, s.size),
+            });
+        }
+    }
+    for (i, sub) in opts.subtitles.iter().enumerate() {
+        for e in &sub.events {
+            blocks.push(Block {
+                track: (tracks.len() + i) as u64 + 1,
+                order_ms: e.start_ms as i64,
+                pts_ms: e.start_ms as i64,
+                keyframe: true,
+                source: BlockSource::Text { data: e.text.clone().into_bytes(), duration_ms: e.end_ms.saturating_sub(e.start_ms) },
+            });
+        }
+    }
+    blocks.sort_by_key(|b| (b.order_ms, b.track));
+    let duration_ms = tracks
+        .iter()
+        .filter_map(|t| t.samples.last().map(|s| to_ms((s.dts + s.duration as u64) as i64 - t.media_time, t.timescale)))
+        .max()
+        .unwrap_or(0);
+
+    let mut head = Vec::new();
+    master(&mut head, EBML, |b| {
+        uint(b, 0x4286, 1);
+        uint(b, 0x42F7, 1);
+        uint(b, 0x42F2, 4);
+        uint(b, 0x42F3, 8);
+        string(b, 0x4282, "matroska");
+        uint(b, 0x4287, 4);
+        uint(b, 0x4285, 2);
+    });
+    out.write_all(&head)?;
+    // Segment with an 8-byte size, patched at the end
+    let mut segment = Vec::new();
+    put_id(&mut segment, SEGMENT);
+    let segment_size_pos = out.stream_position()? + segment.len() as u64;
+    segment.extend_from_slice(&[0x01, 0, 0, 0, 0, 0, 0, 0]);
+    out.write_all(&segment)?;
+    let segment_start = out.stream_position()?;
+
+    let info = build_info(opts, duration_ms);
+    let track_entries = build_tracks(&tracks, &opts.subtitles)?;
+    let chapters = (!opts.chapters.is_empty()).then(|| build_chapters(&opts.chapters));
+    let attachments = opts.cover.as_ref().map(build_attachments);
+
+    // SeekHead with fixed 8-byte positions so the Cues entry can be patched later
+    let mut level1 = vec![(INFO, info), (TRACKS, track_entries)];
+    level1.extend(chapters.map(|c| (CHAPTERS, c)));
+    level1.extend(attachments.map(|a| (ATTACHMENTS, a)));
+    let seek_entry = |b: &mut Vec<u8>, id: u32, pos: u64| {
+        master(b, SEEK, |s| {
+            let mut idb = Vec::new();
+            put_id(&mut idb, id);
+            element(s, SEEK_ID, &idb);
+            element(s, SEEK_POSITION, &pos.to_be_bytes());
+        })
+    };
+    let seek_head_len = {
+        let mut probe = Vec::new();
+        master(&mut probe, SEEK_HEAD, |b| {
+            for (id, _) in &level1 { seek_entry(b, *id, 0); }
+            seek_entry(b, CUES, 0);
+        });
+        probe.len() as u64
+    };
+    let mut pos = seek_head_len;
+    let mut positions = Vec::new();
+    for (id, data) in &level1 {
+        positions.push((*id, pos));
+        pos += data.len() as u64;
+    }
+    let mut seek_head = Vec::new();
+    master(&mut seek_head, SEEK_HEAD, |b| {
+        for (id, p) in &positions { seek_entry(b, *id, *p); }
+        seek_entry(b, CUES, 0);
+    });
+    // The Cues position is the last 8 bytes of the SeekHead
+    let cues_seek_pos = segment_start + seek_head.len() as u64 - 8;
+    out.write_all(&seek_head)?;
+    for (_, data) in &level1 {
+        out.write_all(data)?;
+    }
+
+    // Clusters
+    let video_track = tracks.iter().position(|t| t.entry.width > 0).map(|i| i as u64 + 1);
+    let mut cues: Vec<(i64, u64, u64)> = Vec::new();
+    let mut cluster: Vec<u8> = Vec::new();
+    let mut cluster_time = 0i64;
+    let mut buf = Vec::new();
+    for block in &blocks {
+        let rel = block.pts_ms - cluster_time;
+        let starts_gop = block.keyframe && Some(block.track) == video_track;
+        let new_cluster = cluster.is_empty()
+            || !(-30_000..30_000).contains(&rel)
+            || (starts_gop && rel > 0)
+            || (video_track.is_none() && rel >= 5_000)
+            || cluster.len() > 8 << 20;
+        if new_cluster {
+            if !cluster.is_empty() {
+                flush_cluster(out, &mut cluster)?;
+            }
+            cluster_time = block.pts_ms;
+            uint(&mut cluster, 0xE7, cluster_time as u64);
+            if starts_gop || (video_track.is_none() && block.track == 1) {
+                cues.push((cluster_time, block.track, out.stream_position()? - segment_start));
+            }
+        }
+        let rel = (block.pts_ms - cluster_time) as i16;
+        let mut header = Vec::new();
+        put_size(&mut header, block.track);
+        header.extend_from_slice(&rel.to_be_bytes());
+        match &block.source {
+            BlockSource::Sample(input, offset, size) => {
+                let r = &mut inputs[*input];
+                r.seek(SeekFrom::Start(*offset))?;
+                buf.resize(*size as usize, 0);
+                r.read_exact(&mut buf)?;
+                header.push(if block.keyframe { 0x80 } else { 0 });
+                put_id(&mut cluster, 0xA3);
+                put_size(&mut cluster, (header.len() + buf.len()) as u64);
+                cluster.extend_from_slice(&header);
+                cluster.extend_from_slice(&buf);
+            }
+            BlockSource::Text { data, duration_ms } => {
+                header.push(0);
+                master(&mut cluster, 0xA0, |g| {
+                    element(g, 0xA1, &[header.as_slice(), data].concat());
+                    uint(g, 0x9B, *duration_ms);
+                });
+            }
+        }
+    }
+    if !cluster.is_empty() {
+        flush_cluster(out, &mut cluster)?;
+    }
+
+    let cues_pos = out.stream_position()? - segment_start;
+    let mut cue_bytes = Vec::new();
+    master(&mut cue_bytes, CUES, |c| {
+        for (time, track, cluster_pos) in &cues {
+            master(c, 0xBB, |p| {
+                uint(p, 0xB3, *time as u64);
+                master(p, 0xB7, |t| {
+                    uint(t, 0xF7, *track);
+                    uint(t, 0xF1, *cluster_pos);
+                });
+            });
+        }
+    });
+    out.write_all(&cue_bytes)?;
+
+    let end = out.stream_position()?;
+    out.seek(SeekFrom::Start(cues_seek_pos))?;
+    out.write_all(&cues_pos.to_be_bytes())?;
+    out.seek(SeekFrom::Start(segment_size_pos))?;
+    let size = end - segment_start;
+    out.write_all(&[&[0x01u8][..], &size.to_be_bytes()[1..]].concat())?;
+    out.seek(SeekFrom::Start(end))?;
+    out.flush()?;
+    Ok(())
+}
+
+fn flush_cluster<W: Write>(out: &mut W, cluster: &mut Vec<u8>) -> Result<()> {
+    let mut head = Vec::new();
+    put_id(&mut head, CLUSTER);
+    put_size(&mut head, cluster.len() as u64);
+    out.write_all(&head)?;
+    out.write_all(cluster)?;
+    cluster.clear();
+    Ok(())
+}
+
+fn build_info(opts: &MkvOptions, duration_ms: i64) -> Vec<u8> {
+    let mut out = Vec::new();
+    master(&mut out, INFO, |b| {
+        uint(b, 0x2AD7B1, 1_000_000);
+        float(b, 0x4489, duration_ms as f64);
+        string(b, 0x4D80, concat!("bilibili-dl ", env!("CARGO_PKG_VERSION")));
+        string(b, 0x5741, concat!("bilibili-dl ", env!("CARGO_PKG_VERSION")));
+        if let Some(title) = &opts.title {
+            string(b, 0x7BA9, title);
+        }
+    });
+    out
+}
+
+fn build_tracks(tracks: &[Fmp4Track], subtitles: &[MkvSubtitle]) -> Result<Vec<u8>> {
+    let mut entries = Vec::new();
+    for (i, t) in tracks.iter().enumerate() {
+        let (codec, private) = codec_for(t)?;
+        let number = i as u64 + 1;
+        let video = codec.starts_with("V_");
+        master(&mut entries, 0xAE, |b| {
+            uint(b, 0xD7, number);
+            uint(b, 0x73C5, number);
+            uint(b, 0x83, if video { 1 } else { 2 });
+            uint(b, 0x9C, 0);
+            string(b, 0x86, codec);
+            if let Some(p) = &private {
+                element(b, 0x63A2, p);
+            }
+            // BlockAdditionMapping carrying the DOVIDecoderConfigurationRecord, as mkvmerge writes it
+            if let Some((kind, config)) = &t.entry.dolby_vision {
+                master(b, 0x41E4, |m| {
+                    string(m, 0x41A4, "Dolby Vision configuration");
+                    uint(m, 0x41E7, u32::from_be_bytes(*kind) as u64);
+                    element(m, 0x41ED, config);
+                });
+            }
+            if let Some(first) = t.samples.first().filter(|s| s.duration > 0) {
+                uint(b, 0x23E383, first.duration as u64 * 1_000_000_000 / t.timescale.max(1) as u64);
+            }
+            if video {
+                master(b, 0xE0, |v| {
+                    uint(v, 0xB0, t.entry.width as u64);
+                    uint(v, 0xBA, t.entry.height as u64);
+                });
+            } else {
+                master(b, 0xE1, |a| {
+                    float(a, 0xB5, t.entry.sample_rate as f64);
+                    uint(a, 0x9F, t.entry.channels.max(1) as u64);
+                });
+            }
+        });
+    }
+    for (i, sub) in subtitles.iter().enumerate() {
+        let number = (tracks.len() + i) as u64 + 1;
+        master(&mut entries, 0xAE, |b| {
+            uint(b, 0xD7, number);
+            uint(b, 0x73C5, number);
+            uint(b, 0x83, 0x11);
+            uint(b, 0x9C, 0);
+            uint(b, 0x88, 0);
+            string(b, 0x22B59C, &sub.language);
+            if !sub.name.is_empty() {
+                string(b, 0x536E, &sub.name);
+            }
+            match &sub.ass_header {
+                Some(header) => {
+                    string(b, 0x86, "S_TEXT/ASS");
+                    string(b, 0x63A2, header);
+                }
+                None => string(b, 0x86, "S_TEXT/UTF8"),
+            }
+        });
+    }
+    let mut out = Vec::new();
+    element(&mut out, TRACKS, &entries);
+    Ok(out)
+}
+
+fn build_chapters(chapters: &[Chapter]) -> Vec<u8> {
+    let mut out = Vec::new();
+    master(&mut out, CHAPTERS, |c| {
+        master(c, 0x45B9, |e| {
+            for (i, ch) in chapters.iter().enumerate() {
+                master(e, 0xB6, |a| {
+                    uint(a, 0x73C4, i as u64 + 1);
+                    uint(a, 0x91, ch.start_ms * 1_000_000);
+                    uint(a, 0x92, ch.end_ms * 1_000_000);
+                    master(a, 0x80, |d| {
+                        string(d, 0x85, &ch.title);
+                        string(d, 0x437C, "und");
+                    });
+                });
+            }
+        });
+    });
+    out
+}
+
+fn build_attachments(cover: &MkvAttachment) -> Vec<u8> {
+    let mut out = Vec::new();
+    master(&mut out, ATTACHMENTS, |a| {
+        master(a, 0x61A7, |f| {
+            string(f, 0x466E, &cover.file_name);
+            string(f, 0x4660, &cover.mime_type);
+            element(f, 0x465C, &cover.data);
+            uint(f, 0x46AE, 1);
+        });
+    });
+    out
+}
+
+/// Extras written by [`write_mkv`].
+#[derive(Debug, Clone, Default)]
+pub struct MkvOptions {
+    pub title: Option<String>,
+    pub subtitles: Vec<MkvSubtitle>,
+    pub chapters: Vec<Chapter>,
+    pub cover: Option<MkvAttachment>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MkvAttachment {
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl MkvOptions {
+    /// Reads the subtitle and cover files referenced by the ffmpeg mux options.
+    pub fn from_mux_options(mux: &MuxOptions) -> Result<Self> {
+        let subtitles = mux
+            .subtitles
+            .iter()
+            .map(|s| MkvSubtitle::from_file(&s.path, &s.language, &s.title))
+            .collect::<Result<_>>()?;
+        let cover = match &mux.cover {
+            Some(path) => {
+                let ext = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("jpg").to_ascii_lowercase();
+                Some(MkvAttachment {
+                    file_name: format!("cover.{}", ext),
+                    mime_type: if ext == "png" { "image/png" } else { "image/jpeg" }.to_string(),
+                    data: std::fs::read(path).context("read cover")?,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            title: mux.metadata.iter().find(|(k, _)| k == "title").map(|(_, v)| v.clone()),
+            subtitles,
+            chapters: mux.chapters.clone(),
+            cover,
+        })
+    }
+}
+
+/// Native counterpart of `downloader::ffmpeg_mux_with` for mkv output.
+pub async fn native_mux_mkv(video_path: &str, audio_path: &str, out_path: &str, mux: &MuxOptions) -> Result<()> {
+    let (video_path, audio_path, out_path) = (video_path.to_string(), audio_path.to_string(), out_path.to_string());
+    let mux = mux.clone();
+    tokio::task::spawn_blocking(move || {
+        let opts = MkvOptions::from_mux_options(&mux)?;
+        let mut video = BufReader::new(std::fs::File::open(video_path).context("open video track")?);
+        let mut audio = BufReader::new(std::fs::File::open(audio_path).context("open audio track")?);
+        let mut out = BufWriter::new(std::fs::File::create(out_path).context("create output")?);
+        write_mkv(&mut [&mut video, &mut audio], &mut out, &opts)
+    })
+    .await?
+}
@@ -0,0 +1,623 @@
+use crate::bangumi::extract_pgc_id;
+use crate::bilibili::{pages_from_view, parse_bvid_and_page, BiliClient, UgcSeason, VideoPage};
+use crate::util::parse_index_ranges;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use reqwest::Url;
+use serde::Deserialize;
+
+/// One item of a multi-video input, downloaded in queue order.
+#[derive(Debug, Clone)]
+pub enum PlaylistEntry {
+    /// A page already resolved to its cid (pages of a video, bangumi episodes)
+    Page(VideoPage),
+    /// A video whose pages are resolved when the queue reaches it
+    Video { bvid: String, title: String },
+}
+
+impl PlaylistEntry {
+    pub fn bvid(&self) -> &str {
+        match self {
+            PlaylistEntry::Page(p) => &p.bvid,
+            PlaylistEntry::Video { bvid, .. } => bvid,
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        match self {
+            PlaylistEntry::Page(p) => &p.title,
+            PlaylistEntry::Video { title, .. } => title,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Playlist {
+    pub title: String,
+    pub entries: Vec<PlaylistEntry>,
+    /// Listing pages past `entries` that have not been requested yet
+    pub pending: Option<PendingPages>,
+}
+
+impl Playlist {
+    /// Number of items, counting those on pages not fetched yet
+    pub fn len(&self) -> usize {
+        self.pending.as_ref().map_or(0, |p| p.total).max(self.entries.len())
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+/// Pages of a space or favorite folder listing, requested by [`PlaylistQueue`] only once it
+/// reaches their entries, so `--break-on-existing` and `--max-downloads` stop the paging too.
+#[derive(Debug, Clone)]
+pub struct PendingPages {
+    /// Item count the listing reports, including the entries already loaded
+    total: usize,
+    source: PageSource,
+}
+
+#[derive(Debug, Clone)]
+enum PageSource {
+    /// Space page numbers still to fetch, the next one last
+    Space { mid: u64, order: SpaceOrder, pages: Vec<usize> },
+    Favorites { fid: u64, next_pn: Option<usize> },
+}
+
+/// yt-dlp style selection of playlist items (`--playlist-items`, `--playlist-start/end`,
+/// `--playlist-reverse`, `--max-downloads`). Indices are 1-based positions in the playlist.
+#[derive(Debug, Clone, Default)]
+pub struct PlaylistSelection {
+    /// Item list like `1-10,15`; takes precedence over `start`/`end`
+    pub items: Option<String>,
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+    pub reverse: bool,
+    /// Stop after this many successful downloads
+    pub max_downloads: Option<usize>,
+}
+
+impl PlaylistSelection {
+    /// Returns the selected 1-based indices for a playlist of `len` entries, in download order.
+    pub fn indices(&self, len: usize) -> Result<Vec<usize>> {
+        let mut idx: Vec<usize> = match self.items.as_deref() {
+            Some(spec) => {
+                let mut v = Vec::new();
+                for (start, end) in parse_index_ranges(spec)? {
+                    let end = end.map(|e| e as usize).unwrap_or(len).min(len);
+                    v.extend(start as usize..=end);
+                }
+                v.sort_unstable();
+                v.dedup();
+                v
+            }
+            None => {
+                let start = self.start.unwrap_or(1).max(1);
+                let end = self.end.unwrap_or(len).min(len);
+                (start..=end).collect()
+            }
+        };
+        if self.reverse {
+            idx.reverse();
+        }
+        Ok(idx)
+    }
+}
+
+/// Queue over the selected entries of a playlist, yielding `(playlist_index, entry)` from
+/// [`PlaylistQueue::next_entry`]. Call [`PlaylistQueue::record_download`] after each successful
+/// download so `max_downloads` can end the queue.
+#[derive(Debug)]
+pub struct PlaylistQueue {
+    /// Selected 1-based positions not reached yet, in download order
+    wanted: std::iter::Peekable<std::vec::IntoIter<usize>>,
+    /// Entries by position; grows as pending pages are fetched
+    loaded: Vec<Option<PlaylistEntry>>,
+    pending: Option<PendingPages>,
+    total: usize,
+    downloaded: usize,
+    max_downloads: Option<usize>,
+}
+
+impl PlaylistQueue {
+    pub fn new(playlist: Playlist, selection: &PlaylistSelection) -> Result<Self> {
+        let indices = selection.indices(playlist.len())?;
+        Ok(Self {
+            total: indices.len(),
+            wanted: indices.into_iter().peekable(),
+            loaded: playlist.entries.into_iter().map(Some).collect(),
+            pending: playlist.pending,
+            downloaded: 0,
+            max_downloads: selection.max_downloads,
+        })
+    }
+
+    /// Number of selected entries
+    pub fn len(&self) -> usize { self.total }
+
+    pub fn is_empty(&self) -> bool { self.total == 0 }
+
+    pub fn record_download(&mut self) { self.downloaded += 1; }
+
+    pub fn max_reached(&self) -> bool {
+        self.max_downloads.is_some_and(|m| self.downloaded >= m)
+    }
+
+    /// Next selected entry, fetching listing pages up to its position when needed.
+    pub async fn next_entry(&mut self, client: &BiliClient) -> Result<Option<(usize, PlaylistEntry)>> {
+        loop {
+            if self.max_reached() { return Ok(None); }
+            let Some(&index) = self.wanted.peek() else { return Ok(None) };
+            if index > self.loaded.len() {
+                match self.pending.as_mut() {
+                    Some(pending) => match client.next_listing_page(pending).await? {
+                        Some(entries) => self.loaded.extend(entries.into_iter().map(Some)),
+                        None => self.pending = None,
+                    },
+                    // the listing ended short of its reported count
+                    None => { self.wanted.next(); }
+                }
+                continue;
+            }
+            self.wanted.next();
+            if let Some(entry) = self.loaded[index - 1].take() {
+                return Ok(Some((index, entry)));
+            }
+        }
+    }
+}
+
+/// Order in which an uploader's videos are enumerated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpaceOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+    /// Page list for multi-part videos (see `parse_page_spec`)
+    pub page_spec: Option<String>,
+    pub all_pages: bool,
+    /// Expand a video that belongs to a collection (ugc_season) to the whole collection
+    pub whole_collection: bool,
+    pub space_order: SpaceOrder,
+    /// Cap on the number of videos enumerated from an uploader space
+    pub max_items: Option<usize>,
+}
+
+/// Extracts the uploader id from `space.bilibili.com/<mid>` and `space.bilibili.com/<mid>/video` links.
+pub fn extract_space_mid(input: &str) -> Option<u64> {
+    let re = Regex::new(r"space\.bilibili\.com/(\d+)(?:/video|/upload/video)?/?(?:$|[?#])").ok()?;
+    re.captures(input)?[1].parse().ok()
+}
+
+/// Extracts the folder id from `space.bilibili.com/<mid>/favlist?fid=<fid>` and `.../medialist/detail/ml<fid>` links.
+pub fn extract_favlist_id(input: &str) -> Option<u64> {
+    let re = Regex::new(r"(?:favlist\?(?:[^#]*&)?fid=|/ml)(\d+)").ok()?;
+    re.captures(input)?[1].parse().ok()
+}
+
+/// Extracts `(mid, season_id)` from `space.bilibili.com/<mid>/channel/collectiondetail?sid=<sid>`
+/// and `space.bilibili.com/<mid>/lists/<sid>?type=season` links.
+pub fn extract_collection(input: &str) -> Option<(u64, u64)> {
+    let re = Regex::new(r"space\.bilibili\.com/(\d+)/(?:channel/collectiondetail\?(?:[^#]*&)?sid=(\d+)|lists/(\d+)\?(?:[^#]*&)?type=season)").ok()?;
+    let caps = re.captures(input)?;
+    let sid = caps.get(2).or(caps.get(3))?.as_str().parse().ok()?;
+    Some((caps[1].parse().ok()?, sid))
+}
+
+/// Extracts `(mid, series_id)` from `space.bilibili.com/<mid>/channel/seriesdetail?sid=<sid>`
+/// and `space.bilibili.com/<mid>/lists/<sid>?type=series` links.
+pub fn extract_series(input: &str) -> Option<(u64, u64)> {
+    let re = Regex::new(r"space\.bilibili\.com/(\d+)/(?:channel/seriesdetail\?(?:[^#]*&)?sid=(\d+)|lists/(\d+)\?(?:[^#]*&)?type=series)").ok()?;
+    let caps = re.captures(input)?;
+    let sid = caps.get(2).or(caps.get(3))?.as_str().parse().ok()?;
+    Some((caps[1].parse().ok()?, sid))
+}
+
+pub fn is_watch_later(input: &str) -> bool {
+    input.contains("bilibili.com/watchlater") || input.contains("bilibili.com/list/watchlater")
+}
+
+/// Multi-video inputs recognized before falling back to single-video resolution.
+enum ListInput {
+    Space(u64),
+    Favorites(u64),
+    WatchLater,
+    Collection { mid: u64, sid: u64 },
+    Series { mid: u64, sid: u64 },
+}
+
+fn classify_list_input(input: &str) -> Option<ListInput> {
+    if is_watch_later(input) {
+        Some(ListInput::WatchLater)
+    } else if let Some(fid) = extract_favlist_id(input) {
+        Some(ListInput::Favorites(fid))
+    } else if let Some((mid, sid)) = extract_collection(input) {
+        Some(ListInput::Collection { mid, sid })
+    } else if let Some((mid, sid)) = extract_series(input) {
+        Some(ListInput::Series { mid, sid })
+    } else {
+        extract_space_mid(input).map(ListInput::Space)
+    }
+}
+
+/// Flattens all sections of a collection into one queue, in display order.
+fn ugc_season_playlist(season: UgcSeason) -> Playlist {
+    let entries = season
+        .sections
+        .into_iter()
+        .flat_map(|s| s.episodes)
+        .map(|e| PlaylistEntry::Video { bvid: e.bvid, title: e.title })
+        .collect();
+    Playlist { title: season.title, entries, pending: None }
+}
+
+fn favorite_entries(medias: Vec<FavMedia>) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+    for m in medias {
+        // type 2 = video; attr != 0 marks invalid (deleted/hidden) resources
+        if m.kind != 2 || m.attr != 0 || m.bvid.is_empty() {
+            eprintln!("warning: skipping unavailable favorite entry {} ({})", m.bvid, m.title);
+            continue;
+        }
+        entries.push(PlaylistEntry::Video { bvid: m.bvid, title: m.title });
+    }
+    entries
+}
+
+const SPACE_PAGE_SIZE: usize = 30;
+const LIST_PAGE_SIZE: usize = 30;
+const FAV_PAGE_SIZE: usize = 20;
+
+impl BiliClient {
+    /// Resolves any supported input to a download queue. A single video yields its selected
+    /// pages; spaces, favorite folders and watch later yield videos, whose pages are resolved later.
+    pub async fn resolve_playlist(&self, input: &str, opts: &ResolveOptions) -> Result<Playlist> {
+        let (target, list) = match classify_list_input(input) {
+            Some(list) => (input.to_string(), Some(list)),
+            None => {
+                let target = self.expand_short_link(input).await?;
+                let list = classify_list_input(&target);
+                (target, list)
+            }
+        };
+        match list {
+            Some(ListInput::Space(mid)) => return self.get_space_videos(mid, opts.space_order, opts.max_items).await,
+            Some(ListInput::Favorites(fid)) => return self.get_favorite_videos(fid).await,
+            Some(ListInput::WatchLater) => return self.get_watch_later_videos().await,
+            Some(ListInput::Collection { mid, sid }) => return self.get_collection_videos(mid, sid).await,
+            Some(ListInput::Series { mid, sid }) => return self.get_series_videos(mid, sid).await,
+            None => {}
+        }
+        let pages = if opts.whole_collection && extract_pgc_id(&target).is_none() {
+            let (bvid, page_from_url) = parse_bvid_and_page(&target)?;
+            let mut view = self.get_view(&bvid).await?;
+            if let Some(season) = view.ugc_season.take() {
+                return Ok(ugc_season_playlist(season));
+            }
+            pages_from_view(&bvid, &view, page_from_url, opts.page_spec.as_deref(), opts.all_pages)?
+        } else {
+            self.resolve_expanded_pages(&target, opts.page_spec.as_deref(), opts.all_pages).await?
+        };
+        Ok(Playlist {
+            title: pages.first().map(|p| p.title.clone()).unwrap_or_default(),
+            entries: pages.into_iter().map(PlaylistEntry::Page).collect(),
+            pending: None,
+        })
+    }
+
+    /// Enumerates an uploader's videos via the WBI-signed `x/space/wbi/arc/search` API.
+    /// `Oldest` walks the listing from its last page backwards so `max_items` stays cheap.
+    /// Only the first page is fetched here; the queue requests the rest as it reaches them.
+    pub async fn get_space_videos(&self, mid: u64, order: SpaceOrder, max_items: Option<usize>) -> Result<Playlist> {
+        let first = self.get_space_page(mid, 1).await?;
+        let count = first.page.count as usize;
+        let last_pn = count.div_ceil(SPACE_PAGE_SIZE).max(1);
+        let title = first
+            .list
+            .vlist
+            .first()
+            .map(|v| v.author.clone())
+            .filter(|a| !a.is_empty())
+            .unwrap_or_else(|| format!("space {}", mid));
+
+        let (entries, pages) = match order {
+            SpaceOrder::Newest => (
+                first.list.vlist.into_iter().map(SpaceVideo::into_entry).collect(),
+                (2..=last_pn).rev().collect(),
+            ),
+            // page 1 is requested again last, after the older pages
+            SpaceOrder::Oldest => (Vec::new(), (1..=last_pn).collect()),
+        };
+        let total = count.min(max_items.unwrap_or(usize::MAX));
+        let pending = PendingPages { total, source: PageSource::Space { mid, order, pages } };
+        Ok(Playlist { title, entries, pending: Some(pending) })
+    }
+
+    /// Pages through a favorite folder (`x/v3/fav/resource/list`). Deleted or
+    /// non-video entries are skipped with a warning.
+    pub async fn get_favorite_videos(&self, fid: u64) -> Result<Playlist> {
+        let data = self.get_favorite_page(fid, 1).await?;
+        let info = data.info.unwrap_or_default();
+        let title = if info.title.is_empty() { format!("favlist {}", fid) } else { info.title };
+        let next_pn = (data.has_more && !data.medias.is_empty()).then_some(2);
+        let entries = favorite_entries(data.medias);
+        let pending = PendingPages { total: info.media_count, source: PageSource::Favorites { fid, next_pn } };
+        Ok(Playlist { title, entries, pending: Some(pending) })
+    }
+
+    /// Fetches the next page of a paged listing, or `None` once it is exhausted.
+    async fn next_listing_page(&self, pending: &mut PendingPages) -> Result<Option<Vec<PlaylistEntry>>> {
+        match &mut pending.source {
+            PageSource::Space { mid, order, pages } => {
+                let Some(pn) = pages.pop() else { return Ok(None) };
+                let page = self.get_space_page(*mid, pn).await?;
+                if page.list.vlist.is_empty() {
+                    pages.clear();
+                    return Ok(None);
+                }
+                let videos = page.list.vlist.into_iter().map(SpaceVideo::into_entry);
+                Ok(Some(match order {
+                    SpaceOrder::Newest => videos.collect(),
+                    SpaceOrder::Oldest => videos.rev().collect(),
+                }))
+            }
+            PageSource::Favorites { fid, next_pn } => {
+                let Some(pn) = next_pn.take() else { return Ok(None) };
+                let data = self.get_favorite_page(*fid, pn).await?;
+                if data.has_more && !data.medias.is_empty() {
+                    *next_pn = Some(pn + 1);
+                }
+                Ok(Some(favorite_entries(data.medias)))
+            }
+        }
+    }
+
+    async fn get_favorite_page(&self, fid: u64, pn: usize) -> Result<FavData> {
+        let url = Url::parse_with_params(
+            "https://api.bilibili.com/x/v3/fav/resource/list",
+            &[
+                ("media_id", fid.to_string()),
+                ("pn", pn.to_string()),
+                ("ps", FAV_PAGE_SIZE.to_string()),
+                ("platform", "web".to_string()),
+            ],
+        )?;
+        let resp: ApiResp<FavData> = self.get_json_retry(url).await?;
+        resp.into_data("favorite folder")
+    }
+
+    /// Lists the logged-in user's watch later queue (`x/v2/history/toview`); requires cookies.
+    pub async fn get_watch_later_videos(&self) -> Result<Playlist> {
+        if self.cookie_jar().is_none() && self.cookie_header().is_none() {
+            return Err(anyhow!("watch later requires login cookies (--cookies or --cookies-from-browser)"));
+        }
+        let url = Url::parse("https://api.bilibili.com/x/v2/history/toview")?;
+        let resp: ApiResp<ToViewData> = self.get_json_retry(url).await?;
+        let data = resp.into_data("watch later")?;
+        let mut entries = Vec::new();
+        for item in data.list.unwrap_or_default() {
+            // state < 0: deleted, under review or otherwise unavailable
+            if item.state < 0 || item.bvid.is_empty() {
+                eprintln!("warning: skipping unavailable watch later entry {} ({})", item.bvid, item.title);
+                continue;
+            }
+            entries.push(PlaylistEntry::Video { bvid: item.bvid, title: item.title });
+        }
+        Ok(Playlist { title: "watch later".to_string(), entries, pending: None })
+    }
+
+    /// Lists a collection (合集) page by page (`x/polymer/web-space/seasons_archives_list`).
+    pub async fn get_collection_videos(&self, mid: u64, season_id: u64) -> Result<Playlist> {
+        let mut title = format!("collection {}", season_id);
+        let mut entries = Vec::new();
+        for pn in 1.. {
+            let url = Url::parse_with_params(
+                "https://api.bilibili.com/x/polymer/web-space/seasons_archives_list",
+                &[
+                    ("mid", mid.to_string()),
+                    ("season_id", season_id.to_string()),
+                    ("page_num", pn.to_string()),
+                    ("page_size", LIST_PAGE_SIZE.to_string()),
+                    ("sort_reverse", "false".to_string()),
+                ],
+            )?;
+            let resp: ApiResp<ArchivesData> = self.get_json_retry(url).await?;
+            let data = resp.into_data("collection")?;
+            if let Some(meta) = data.meta && !meta.name.is_empty() {
+                title = meta.name;
+            }
+            let got = data.archives.len();
+            entries.extend(data.archives.into_iter().map(Archive::into_entry));
+            if got == 0 || entries.len() as u64 >= data.page.total { break; }
+        }
+        Ok(Playlist { title, entries, pending: None })
+    }
+
+    /// Lists a series (系列) page by page (`x/series/archives`), oldest first.
+    pub async fn get_series_videos(&self, mid: u64, series_id: u64) -> Result<Playlist> {
+        let title = self
+            .get_series_name(series_id)
+            .await
+            .unwrap_or_else(|_| format!("series {}", series_id));
+        let mut entries = Vec::new();
+        for pn in 1.. {
+            let url = Url::parse_with_params(
+                "https://api.bilibili.com/x/series/archives",
+                &[
+                    ("mid", mid.to_string()),
+                    ("series_id", series_id.to_string()),
+                    ("pn", pn.to_string()),
+                    ("ps", LIST_PAGE_SIZE.to_string()),
+                    ("sort", "asc".to_string()),
+                ],
+            )?;
+            let resp: ApiResp<ArchivesData> = self.get_json_retry(url).await?;
+            let data = resp.into_data("series")?;
+            let got = data.archives.len();
+            entries.extend(data.archives.into_iter().map(Archive::into_entry));
+            if got == 0 || entries.len() as u64 >= data.page.total { break; }
+        }
+        Ok(Playlist { title, entries, pending: None })
+    }
+
+    async fn get_series_name(&self, series_id: u64) -> Result<String> {
+        let url = Url::parse_with_params(
+            "https://api.bilibili.com/x/series/series",
+            &[("series_id", series_id.to_string())],
+        )?;
+        let resp: ApiResp<SeriesInfo> = self.get_json_retry(url).await?;
+        Ok(resp.into_data("series info")?.meta.name)
+    }
+
+    async fn get_space_page(&self, mid: u64, pn: usize) -> Result<SpaceArcData> {
+        let params = vec![
+            ("mid".to_string(), mid.to_string()),
+            ("pn".to_string(), pn.to_string()),
+            ("ps".to_string(), SPACE_PAGE_SIZE.to_string()),
+            ("order".to_string(), "pubdate".to_string()),
+            // browser fingerprint fields the API checks before answering (-352 otherwise)
+            ("dm_img_list".to_string(), "[]".to_string()),
+            ("dm_img_str".to_string(), "V2ViR0wgMS4wIChPcGVuR0wgRVMgMi4wIENocm9taXVtKQ".to_string()),
+            ("dm_cover_img_str".to_string(), "QU5HTEUgKEludGVsLCBJbnRlbChSKSBIRCBHcmFwaGljcyBEaXJlY3QzRDExIHZzXzVfMCBwc181XzApLCBvciBzaW1pbGFyKQ".to_string()),
+        ];
+        let url = self.wbi_signed_url("https://api.bilibili.com/x/space/wbi/arc/search", params).await?;
+        let resp: ApiResp<SpaceArcData> = self.get_json_retry(url).await?;
+        resp.into_data("space arc search")
+    }
+}
+
+// ==== Types ====
+
+#[derive(Debug, Deserialize)]
+struct ApiResp<T> {
+    code: i32,
+    message: Option<String>,
+    data: Option<T>,
+}
+
+impl<T> ApiResp<T> {
+    fn into_data(self, what: &str) -> Result<T> {
+        if self.code != 0 {
+            return Err(anyhow!("{} api error code {}: {}", what, self.code, self.message.unwrap_or_default()));
+        }
+        self.data.ok_or_else(|| anyhow!("{} data missing", what))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SpaceArcData {
+    list: SpaceArcList,
+    page: SpaceArcPage,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpaceArcList {
+    #[serde(default)]
+    vlist: Vec<SpaceVideo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpaceArcPage {
+    count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpaceVideo {
+    bvid: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    author: String,
+}
+
+impl SpaceVideo {
+    fn into_entry(self) -> PlaylistEntry {
+        PlaylistEntry::Video { bvid: self.bvid, title: self.title }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FavData {
+    info: Option<FavInfo>,
+    #[serde(default)]
+    medias: Vec<FavMedia>,
+    #[serde(default)]
+    has_more: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FavInfo {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    media_count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct FavMedia {
+    #[serde(rename = "type", default)]
+    kind: i32,
+    #[serde(default)]
+    attr: i32,
+    #[serde(default)]
+    bvid: String,
+    #[serde(default)]
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToViewData {
+    list: Option<Vec<ToViewItem>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToViewItem {
+    #[serde(default)]
+    bvid: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    state: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchivesData {
+    #[serde(default)]
+    archives: Vec<Archive>,
+    meta: Option<ArchivesMeta>,
+    page: ArchivesPage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchivesMeta {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchivesPage {
+    #[serde(default)]
+    total: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Archive {
+    bvid: String,
+    #[serde(default)]
+    title: String,
+}
+
+impl Archive {
+    fn into_entry(self) -> PlaylistEntry {
+        PlaylistEntry::Video { bvid: self.bvid, title: self.title }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SeriesInfo {
+    meta: ArchivesMeta,
+}
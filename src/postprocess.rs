@@ -0,0 +1,301 @@
+//! Post-processing of downloaded tracks: audio extraction (`-x`).
+
+use crate::downloader::{ffmetadata, MuxOptions};
+use crate::concat::write_progressive_mp4_with_udta;
+use crate::remux::{read_fmp4_track, segment_track, write_box};
+use anyhow::{anyhow, Context, Result};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    /// Keep the codec: m4a for AAC/E-AC-3, flac for FLAC
+    Best,
+    M4a,
+    Mp3,
+    Flac,
+    Opus,
+}
+
+impl AudioFormat {
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "best" => Self::Best,
+            "m4a" | "aac" => Self::M4a,
+            "mp3" => Self::Mp3,
+            "flac" => Self::Flac,
+            "opus" => Self::Opus,
+            other => return Err(anyhow!("unknown audio format: {}", other)),
+        })
+    }
+
+    /// The concrete format for a DASH codecs string such as "mp4a.40.2", "fLaC" or "ec-3".
+    pub fn resolve(self, codecs: &str) -> Self {
+        match self {
+            Self::Best if codec_family(codecs) == "flac" => Self::Flac,
+            Self::Best => Self::M4a,
+            f => f,
+        }
+    }
+
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Best | Self::M4a => "m4a",
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Opus => "opus",
+        }
+    }
+
+    /// Whether audio in `codecs` can be stored in this format without transcoding.
+    pub fn can_copy(self, codecs: &str) -> bool {
+        matches!(
+            (self.resolve(codecs), codec_family(codecs).as_str()),
+            (Self::M4a, "mp4a" | "ec-3" | "ac-3") | (Self::Flac, "flac") | (Self::Opus, "opus")
+        )
+    }
+
+    fn encoder(self) -> &'static str {
+        match self {
+            Self::Best | Self::M4a => "aac",
+            Self::Mp3 => "libmp3lame",
+            Self::Flac => "flac",
+            Self::Opus => "libopus",
+        }
+    }
+}
+
+fn codec_family(codecs: &str) -> String {
+    codecs.split('.').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// `--audio-quality`: a VBR level from 0 (best) to 10 (worst), or a bitrate in kbit/s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioQuality {
+    Vbr(u8),
+    Kbps(u32),
+}
+
+impl AudioQuality {
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(k) = s.strip_suffix(['k', 'K']) {
+            return k.parse().map(Self::Kbps).map_err(|_| anyhow!("invalid audio bitrate: {}", s));
+        }
+        match s.parse::<u8>() {
+            Ok(q) if q <= 10 => Ok(Self::Vbr(q)),
+            _ => Err(anyhow!("audio quality must be 0-10 or a bitrate like 128K, got {}", s)),
+        }
+    }
+
+    /// Encoder options for `format`; FLAC is lossless and takes none.
+    fn ffmpeg_args(self, format: AudioFormat) -> Vec<String> {
+        match (format, self) {
+            (AudioFormat::Flac, _) => Vec::new(),
+            (AudioFormat::Mp3, Self::Vbr(q)) => vec!["-q:a".into(), q.min(9).to_string()],
+            (_, Self::Kbps(k)) => vec!["-b:a".into(), format!("{}k", k)],
+            // AAC and Opus: map the VBR scale onto 256..56 kbit/s
+            (_, Self::Vbr(q)) => vec!["-b:a".into(), format!("{}k", 256 - 20 * q as u32)],
+        }
+    }
+}
+
+/// ffmpeg arguments extracting the audio of `input` into `out`. Tags and chapters come from
+/// `meta_path` (an FFMETADATA file); the cover of `opts` is attached except for Opus.
+pub fn extract_audio_args(
+    input: &str,
+    out: &str,
+    format: AudioFormat,
+    codecs: &str,
+    quality: AudioQuality,
+    opts: &MuxOptions,
+    meta_path: Option<&str>,
+) -> Vec<String> {
+    let format = format.resolve(codecs);
+    let cover = opts.cover.as_ref().filter(|_| format != AudioFormat::Opus);
+    let mut args: Vec<String> = vec!["-y".into(), "-i".into(), input.into()];
+    let mut maps: Vec<String> = vec!["-map".into(), "0:a".into()];
+    let mut next_input = 1;
+    if let Some(cover) = cover {
+        args.extend(["-i".into(), cover.clone()]);
+        maps.extend(["-map".into(), next_input.to_string()]);
+        next_input += 1;
+    }
+    if let Some(meta) = meta_path {
+        args.extend(["-i".into(), meta.into()]);
+        maps.extend(["-map_metadata".into(), next_input.to_string(), "-map_chapters".into(), next_input.to_string()]);
+    }
+    args.extend(maps);
+    if format.can_copy(codecs) {
+        args.extend(["-c:a", "copy"].map(String::from));
+    } else {
+        args.extend(["-c:a".into(), format.encoder().into()]);
+        args.extend(quality.ffmpeg_args(format));
+    }
+    if cover.is_some() {
+        args.extend(["-c:v", "copy", "-disposition:v:0", "attached_pic"].map(String::from));
+    }
+    if format == AudioFormat::Mp3 {
+        args.extend(["-id3v2_version", "3"].map(String::from));
+    }
+    args.push(out.into());
+    args
+}
+
+/// Extracts the audio of a DASH `.m4s` into `out` with ffmpeg.
+pub async fn extract_audio_ffmpeg(
+    input: &str,
+    out: &str,
+    format: AudioFormat,
+    codecs: &str,
+    quality: AudioQuality,
+    opts: &MuxOptions,
+) -> Result<()> {
+    let meta_path = if opts.metadata.is_empty() && opts.chapters.is_empty() {
+        None
+    } else {
+        let p = format!("{}.ffmeta.txt", out);
+        std::fs::write(&p, ffmetadata(&opts.metadata, &opts.chapters)).context("write ffmetadata")?;
+        Some(p)
+    };
+    let status = Command::new("ffmpeg")
+        .args(extract_audio_args(input, out, format, codecs, quality, opts, meta_path.as_deref()))
+        .status()
+        .context("invoke ffmpeg");
+    if let Some(p) = &meta_path {
+        let _ = std::fs::remove_file(p);
+    }
+    let status = status?;
+    if !status.success() {
+        return Err(anyhow!("ffmpeg audio extraction failed with status {:?}", status.code()));
+    }
+    Ok(())
+}
+
+/// Lossless extraction without ffmpeg. AAC/E-AC-3 is rewritten as a progressive m4a with
+/// iTunes tags; FLAC is unwrapped into a native `.flac` stream carrying the tags.
+pub async fn extract_audio_native(input: &str, out: &str, format: AudioFormat, codecs: &str, tags: &[(String, String)]) -> Result<()> {
+    let format = format.resolve(codecs);
+    if !format.can_copy(codecs) {
+        return Err(anyhow!("converting {} to {} needs ffmpeg", codecs, format.ext()));
+    }
+    let (input, out, tags) = (input.to_string(), out.to_string(), tags.to_vec());
+    tokio::task::spawn_blocking(move || match format {
+        AudioFormat::Flac => {
+            let mut r = BufReader::new(std::fs::File::open(input).context("open audio track")?);
+            let mut w = BufWriter::new(std::fs::File::create(out).context("create output")?);
+            write_flac(&mut r, &mut w, &tags)
+        }
+        _ => {
+            let mut r = BufReader::new(std::fs::File::open(input).context("open audio track")?);
+            let track = segment_track(&mut r, "audio", b"soun", 0)?;
+            let mut w = BufWriter::new(std::fs::File::create(out).context("create output")?);
+            write_progressive_mp4_with_udta(&[track], &itunes_tags(&tags), &mut [&mut r], &mut w)
+        }
+    })
+    .await?
+}
+
+/// Writes the FLAC frames of a fragmented MP4 as a `.flac` file, adding a Vorbis comment
+/// block with `tags` (keys as produced by `metadata::mux_tags`).
+pub fn write_flac<R: Read + Seek, W: Write>(input: &mut R, out: &mut W, tags: &[(String, String)]) -> Result<()> {
+    let track = read_fmp4_track(input)?;
+    if track.entry.format != "fLaC" {
+        return Err(anyhow!("not a FLAC track: {}", track.entry.format));
+    }
+    let mut blocks = metadata_blocks(&track.entry.config)?;
+    if !tags.is_empty() {
+        blocks.retain(|(kind, _)| *kind != 4);
+        blocks.push((4, vorbis_comment(tags)));
+    }
+    out.write_all(b"fLaC")?;
+    for (i, (kind, data)) in blocks.iter().enumerate() {
+        let last = if i + 1 == blocks.len() { 0x80 } else { 0 };
+        out.write_all(&[last | kind])?;
+        out.write_all(&(data.len() as u32).to_be_bytes()[1..])?;
+        out.write_all(data)?;
+    }
+    let mut buf = Vec::new();
+    for s in &track.samples {
+        input.seek(SeekFrom::Start(s.offset))?;
+        buf.resize(s.size as usize, 0);
+        input.read_exact(&mut buf)?;
+        out.write_all(&buf)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Splits the `dfLa` metadata into (block type, data) pairs.
+fn metadata_blocks(config: &[u8]) -> Result<Vec<(u8, Vec<u8>)>> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while pos + 4 <= config.len() {
+        let kind = config[pos] & 0x7f;
+        let len = u32::from_be_bytes([0, config[pos + 1], config[pos + 2], config[pos + 3]]) as usize;
+        let data = config.get(pos + 4..pos + 4 + len).ok_or_else(|| anyhow!("truncated FLAC metadata"))?;
+        blocks.push((kind, data.to_vec()));
+        pos += 4 + len;
+    }
+    if blocks.first().is_none_or(|(kind, _)| *kind != 0) {
+        return Err(anyhow!("FLAC track has no STREAMINFO"));
+    }
+    Ok(blocks)
+}
+
+/// A `udta` box with an iTunes `meta`/`ilst` holding `tags` (keys as produced by
+/// `metadata::mux_tags`); empty when there are no tags.
+pub fn itunes_tags(tags: &[(String, String)]) -> Vec<u8> {
+    let mut ilst = Vec::new();
+    for (k, v) in tags {
+        let (kind, data): (&[u8; 4], Vec<u8>) = match k.as_str() {
+            "title" => (b"\xa9nam", data_box(1, v.as_bytes())),
+            "artist" => (b"\xa9ART", data_box(1, v.as_bytes())),
+            "album" => (b"\xa9alb", data_box(1, v.as_bytes())),
+            "date" => (b"\xa9day", data_box(1, v.as_bytes())),
+            "genre" => (b"\xa9gen", data_box(1, v.as_bytes())),
+            "comment" => (b"\xa9cmt", data_box(1, v.as_bytes())),
+            "description" => (b"desc", data_box(1, v.as_bytes())),
+            // Reserved, track number, total, reserved
+            "track" => match v.parse::<u16>() {
+                Ok(n) => (b"trkn", data_box(0, &[&[0, 0][..], &n.to_be_bytes(), &[0; 4]].concat())),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        write_box(&mut ilst, kind, &data);
+    }
+    if ilst.is_empty() {
+        return Vec::new();
+    }
+    let mut meta = vec![0u8; 4];
+    write_box(&mut meta, b"hdlr", &[&[0u8; 8][..], b"mdir", b"appl", &[0; 9]].concat());
+    write_box(&mut meta, b"ilst", &ilst);
+    let mut udta = Vec::new();
+    write_box(&mut udta, b"meta", &meta);
+    let mut out = Vec::new();
+    write_box(&mut out, b"udta", &udta);
+    out
+}
+
+/// A `data` box: `kind` 1 is UTF-8 text, 0 binary.
+fn data_box(kind: u32, value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_box(&mut out, b"data", &[&kind.to_be_bytes()[..], &0u32.to_be_bytes(), value].concat());
+    out
+}
+
+fn vorbis_comment(tags: &[(String, String)]) -> Vec<u8> {
+    fn put(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+    let mut out = Vec::new();
+    put(&mut out, concat!("bilibili-dl ", env!("CARGO_PKG_VERSION")));
+    out.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+    for (k, v) in tags {
+        let key = if k == "track" { "TRACKNUMBER".to_string() } else { k.to_ascii_uppercase() };
+        put(&mut out, &format!("{}={}", key, v));
+    }
+    out
+}
@@ -0,0 +1,466 @@
+//! Pure-Rust muxing of Bilibili's DASH tracks, used when ffmpeg is missing (`--muxer native`).
+//!
+//! The `.m4s` tracks are fragmented MP4 (`ftyp`, `moov`, `sidx`, then `moof`/`mdat` pairs).
+//! [`remux_fmp4`] reads the samples of one video and one audio track from their fragments
+//! and writes them as a single progressive MP4 (track ids 1 and 2, `moov` before `mdat`,
+//! see `concat::write_progressive_mp4`), so players can seek without a fragment index.
+
+use crate::concat::{write_progressive_mp4, SegmentSample, SegmentTrack};
+use anyhow::{anyhow, Context, Result};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+/// A box with its four-character type and payload (header stripped).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mp4Box {
+    pub kind: [u8; 4],
+    pub payload: Vec<u8>,
+}
+
+impl Mp4Box {
+    pub fn new(kind: &[u8; 4], payload: Vec<u8>) -> Self {
+        Self { kind: *kind, payload }
+    }
+
+    /// Child boxes of a container box.
+    pub fn children(&self) -> Result<Vec<Mp4Box>> {
+        parse_boxes(&self.payload)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.payload.len() + 8);
+        write_box(&mut out, &self.kind, &self.payload);
+        out
+    }
+}
+
+/// Parses consecutive boxes from a byte slice.
+pub fn parse_boxes(data: &[u8]) -> Result<Vec<Mp4Box>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0usize;
+    while pos + 8 <= data.len() {
+        let size32 = read_u32(data, pos)? as u64;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let (header, size) = match size32 {
+            0 => (8, (data.len() - pos) as u64),
+            1 => (16, read_u64(data, pos + 8)?),
+            n => (8, n),
+        };
+        let end = pos
+            .checked_add(size as usize)
+            .filter(|&e| e <= data.len() && size >= header as u64)
+            .ok_or_else(|| anyhow!("box {} overruns its parent", fourcc(&kind)))?;
+        boxes.push(Mp4Box { kind, payload: data[pos + header..end].to_vec() });
+        pos = end;
+    }
+    Ok(boxes)
+}
+
+pub fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    let size = payload.len() as u64 + 8;
+    if size <= u32::MAX as u64 {
+        out.extend_from_slice(&(size as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+    } else {
+        out.extend_from_slice(&1u32.to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&(size + 8).to_be_bytes());
+    }
+    out.extend_from_slice(payload);
+}
+
+pub fn find_box<'a>(boxes: &'a [Mp4Box], kind: &[u8; 4]) -> Option<&'a Mp4Box> {
+    boxes.iter().find(|b| &b.kind == kind)
+}
+
+pub(crate) fn fourcc(kind: &[u8; 4]) -> String {
+    String::from_utf8_lossy(kind).into_owned()
+}
+
+pub(crate) fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("truncated box"))
+}
+
+pub(crate) fn read_u64(data: &[u8], pos: usize) -> Result<u64> {
+    data.get(pos..pos + 8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("truncated box"))
+}
+
+pub(crate) fn is_v1(payload: &[u8]) -> bool {
+    payload.first() == Some(&1)
+}
+
+/// Reads a version 0 (32-bit) or version 1 (64-bit) field.
+fn read_versioned(data: &[u8], pos: usize, v1: bool) -> Result<u64> {
+    if v1 { read_u64(data, pos) } else { read_u32(data, pos).map(u64::from) }
+}
+
+// ==== Input tracks ====
+
+/// Position of a top-level box in a file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BoxPos {
+    pub(crate) kind: [u8; 4],
+    pub(crate) offset: u64,
+    pub(crate) header: u64,
+    pub(crate) size: u64,
+}
+
+pub(crate) fn scan_top_level<R: Read + Seek>(r: &mut R) -> Result<Vec<BoxPos>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let mut boxes = Vec::new();
+    let mut offset = 0u64;
+    while offset + 8 <= len {
+        r.seek(SeekFrom::Start(offset))?;
+        let mut head = [0u8; 16];
+        r.read_exact(&mut head[..8])?;
+        let kind: [u8; 4] = head[4..8].try_into().unwrap();
+        let (header, size) = match u32::from_be_bytes(head[..4].try_into().unwrap()) {
+            0 => (8, len - offset),
+            1 => {
+                r.read_exact(&mut head[8..16])?;
+                (16, u64::from_be_bytes(head[8..16].try_into().unwrap()))
+            }
+            n => (8, n as u64),
+        };
+        if size < header || offset + size > len {
+            return Err(anyhow!("box {} at {} overruns the file", fourcc(&kind), offset));
+        }
+        boxes.push(BoxPos { kind, offset, header, size });
+        offset += size;
+    }
+    Ok(boxes)
+}
+
+pub(crate) fn read_payload<R: Read + Seek>(r: &mut R, pos: &BoxPos) -> Result<Vec<u8>> {
+    r.seek(SeekFrom::Start(pos.offset + pos.header))?;
+    let mut buf = vec![0u8; (pos.size - pos.header) as usize];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// The single track of one DASH input.
+#[derive(Debug, Clone)]
+struct InputTrack {
+    trak: Mp4Box,
+    timescale: u32,
+    samples: Vec<Sample>,
+}
+
+fn read_track<R: Read + Seek>(r: &mut R, what: &str) -> Result<InputTrack> {
+    let top = scan_top_level(r)?;
+    let moov_pos = top.iter().find(|b| &b.kind == b"moov").ok_or_else(|| anyhow!("{}: no moov box", what))?;
+    let moov = parse_boxes(&read_payload(r, moov_pos)?)?;
+    let traks: Vec<&Mp4Box> = moov.iter().filter(|b| &b.kind == b"trak").collect();
+    if traks.len() != 1 {
+        return Err(anyhow!("{}: expected one track, found {}", what, traks.len()));
+    }
+    let trak = traks[0].clone();
+    let mvex = find_box(&moov, b"mvex").map(|b| b.children()).transpose()?.unwrap_or_default();
+    let defaults = match find_box(&mvex, b"trex") {
+        Some(t) => SampleDefaults {
+            duration: read_u32(&t.payload, 12)?,
+            size: read_u32(&t.payload, 16)?,
+            flags: read_u32(&t.payload, 20)?,
+        },
+        None => SampleDefaults::default(),
+    };
+
+    let mdia = find_box(&trak.children()?, b"mdia").cloned().ok_or_else(|| anyhow!("{}: no mdia", what))?;
+    let mdhd = find_box(&mdia.children()?, b"mdhd").cloned().ok_or_else(|| anyhow!("{}: no mdhd", what))?;
+    let timescale = read_u32(&mdhd.payload, if is_v1(&mdhd.payload) { 20 } else { 12 })?;
+
+    let mut samples = Vec::new();
+    let mut next_time = 0u64;
+    let moofs: Vec<&BoxPos> = top.iter().filter(|b| &b.kind == b"moof").collect();
+    if moofs.is_empty() {
+        return Err(anyhow!("{}: not a fragmented MP4 (no moof boxes)", what));
+    }
+    for pos in moofs {
+        let moof = read_payload(r, pos)?;
+        let (decode_time, frag_samples) = fragment_samples(&moof, pos.offset, defaults, next_time)?;
+        next_time = frag_samples.last().map(|s| s.dts + s.duration as u64).unwrap_or(decode_time);
+        samples.extend(frag_samples);
+    }
+    Ok(InputTrack { trak, timescale, samples })
+}
+
+/// Defaults from `trex`, overridable per fragment by `tfhd`.
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleDefaults {
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+/// Walks the `traf`/`trun` boxes of a `moof` payload. Returns the fragment's decode time
+/// (`tfdt`, or `next_time` when absent) and its samples.
+fn fragment_samples(moof: &[u8], moof_offset: u64, trex: SampleDefaults, next_time: u64) -> Result<(u64, Vec<Sample>)> {
+    let mut samples = Vec::new();
+    let mut decode_time = None;
+    let mut dts = next_time;
+    for traf in parse_boxes(moof)?.iter().filter(|b| &b.kind == b"traf") {
+        let children = traf.children()?;
+        let mut d = trex;
+        let mut base = moof_offset;
+        if let Some(tfhd) = find_box(&children, b"tfhd") {
+            let p = &tfhd.payload;
+            let flags = read_u32(p, 0)? & 0xFF_FFFF;
+            let mut pos = 8;
+            if flags & 0x01 != 0 { base = read_u64(p, pos)?; pos += 8; }
+            if flags & 0x02 != 0 { pos += 4; }
+            if flags & 0x08 != 0 { d.duration = read_u32(p, pos)?; pos += 4; }
+            if flags & 0x10 != 0 { d.size = read_u32(p, pos)?; pos += 4; }
+            if flags & 0x20 != 0 { d.flags = read_u32(p, pos)?; }
+        }
+        if let Some(b) = find_box(&children, b"tfdt") {
+            dts = read_versioned(&b.payload, 4, is_v1(&b.payload))?;
+        }
+        decode_time.get_or_insert(dts);
+        let mut data_pos = base;
+        for trun in children.iter().filter(|b| &b.kind == b"trun") {
+            let p = &trun.payload;
+            let signed_cto = is_v1(p);
+            let flags = read_u32(p, 0)? & 0xFF_FFFF;
+            let count = read_u32(p, 4)? as usize;
+            let mut pos = 8;
+            if flags & 0x01 != 0 {
+                data_pos = base.checked_add_signed(read_u32(p, pos)? as i32 as i64).ok_or_else(|| anyhow!("bad trun data offset"))?;
+                pos += 4;
+            }
+            let mut first_flags = None;
+            if flags & 0x04 != 0 { first_flags = Some(read_u32(p, pos)?); pos += 4; }
+            for i in 0..count {
+                let mut field = |bit: u32| -> Result<Option<u32>> {
+                    if flags & bit == 0 { return Ok(None); }
+                    let v = read_u32(p, pos)?;
+                    pos += 4;
+                    Ok(Some(v))
+                };
+                let duration = field(0x100)?.unwrap_or(d.duration);
+                let size = field(0x200)?.unwrap_or(d.size);
+                let sample_flags = field(0x400)?.or(if i == 0 { first_flags } else { None }).unwrap_or(d.flags);
+                let cts_offset = match field(0x800)? {
+                    Some(v) if signed_cto => v as i32 as i64,
+                    Some(v) => v as i64,
+                    None => 0,
+                };
+                samples.push(Sample {
+                    offset: data_pos,
+                    size,
+                    dts,
+                    cts_offset,
+                    duration,
+                    keyframe: sample_flags & 0x0001_0000 == 0,
+                });
+                data_pos += size as u64;
+                dts += duration as u64;
+            }
+        }
+    }
+    Ok((decode_time.unwrap_or(next_time), samples))
+}
+
+/// The `stsd` box of a track's `trak` box.
+fn sample_descriptions(trak: &Mp4Box) -> Result<Mp4Box> {
+    let mdia = find_box(&trak.children()?, b"mdia").cloned().ok_or_else(|| anyhow!("no mdia"))?;
+    let minf = find_box(&mdia.children()?, b"minf").cloned().ok_or_else(|| anyhow!("no minf"))?;
+    let stbl = find_box(&minf.children()?, b"stbl").cloned().ok_or_else(|| anyhow!("no stbl"))?;
+    find_box(&stbl.children()?, b"stsd").cloned().ok_or_else(|| anyhow!("no stsd"))
+}
+
+/// Codec parameters from the `stsd` sample entry of a track's `trak` box.
+fn parse_sample_entry(trak: &Mp4Box) -> Result<SampleEntry> {
+    let stsd = sample_descriptions(trak)?;
+    let entry = parse_boxes(stsd.payload.get(8..).unwrap_or_default())?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("empty stsd"))?;
+    let format = fourcc(&entry.kind);
+    let p = &entry.payload;
+    let mut out = SampleEntry { format: format.clone(), ..Default::default() };
+    let u16_at = |pos: usize| p.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).unwrap_or(0);
+    let children = match format.as_str() {
+        "avc1" | "avc3" | "hev1" | "hvc1" | "dvh1" | "dvhe" | "av01" => {
+            out.width = u16_at(24);
+            out.height = u16_at(26);
+            parse_boxes(p.get(78..).unwrap_or_default())?
+        }
+        "mp4a" | "fLaC" | "ec-3" | "ac-3" | "Opus" => {
+            out.channels = u16_at(16);
+            out.sample_rate = read_u32(p, 24)? >> 16;
+            parse_boxes(p.get(28..).unwrap_or_default())?
+        }
+        _ => Vec::new(),
+    };
+    for child in &children {
+        match &child.kind {
+            b"avcC" | b"hvcC" | b"av1C" | b"dec3" | b"dac3" => out.config = child.payload.clone(),
+            b"esds" => out.config = audio_specific_config(&child.payload).unwrap_or_default(),
+            // FLAC metadata blocks follow the full box header
+            b"dfLa" => out.config = child.payload.get(4..).unwrap_or_default().to_vec(),
+            b"dvcC" | b"dvvC" | b"dvwC" => out.dolby_vision = Some((child.kind, child.payload.clone())),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// The DecoderSpecificInfo (AAC AudioSpecificConfig) inside an `esds` payload.
+fn audio_specific_config(esds: &[u8]) -> Option<Vec<u8>> {
+    // Descriptors: tag, then a length of up to four 7-bit bytes
+    fn descriptor(data: &[u8], pos: usize) -> Option<(u8, usize, usize)> {
+        let tag = *data.get(pos)?;
+        let mut len = 0usize;
+        let mut i = pos + 1;
+        for _ in 0..4 {
+            let b = *data.get(i)?;
+            i += 1;
+            len = (len << 7) | (b & 0x7f) as usize;
+            if b & 0x80 == 0 { break; }
+        }
+        Some((tag, i, len))
+    }
+    let (tag, mut pos, _) = descriptor(esds, 4)?;
+    if tag != 0x03 { return None; }
+    let es_flags = *esds.get(pos + 2)?;
+    pos += 3;
+    if es_flags & 0x80 != 0 { pos += 2; }
+    if es_flags & 0x40 != 0 { pos += 1 + *esds.get(pos)? as usize; }
+    if es_flags & 0x20 != 0 { pos += 2; }
+    let (tag, pos, _) = descriptor(esds, pos)?;
+    if tag != 0x04 { return None; }
+    let (tag, start, len) = descriptor(esds, pos + 13)?;
+    if tag != 0x05 { return None; }
+    esds.get(start..start + len).map(<[u8]>::to_vec)
+}
+
+/// The first edit's `media_time` (the presentation start in the track timescale), 0 if none.
+pub(crate) fn edit_media_time(trak: &Mp4Box) -> Result<i64> {
+    let Some(edts) = find_box(&trak.children()?, b"edts").cloned() else { return Ok(0) };
+    let Some(elst) = find_box(&edts.children()?, b"elst").cloned() else { return Ok(0) };
+    let p = &elst.payload;
+    let v1 = is_v1(p);
+    let count = read_u32(p, 4)? as usize;
+    let entry = if v1 { 20 } else { 12 };
+    for i in 0..count {
+        let pos = 8 + i * entry;
+        let media_time = if v1 { read_u64(p, pos + 8)? as i64 } else { read_u32(p, pos + 4)? as i32 as i64 };
+        // -1 marks an empty edit
+        if media_time >= 0 { return Ok(media_time); }
+    }
+    Ok(0)
+}
+
+/// One media sample of a fragmented MP4 track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Position of the sample data in the input
+    pub offset: u64,
+    pub size: u32,
+    /// Decode time in the track timescale
+    pub dts: u64,
+    /// Presentation minus decode time
+    pub cts_offset: i64,
+    pub duration: u32,
+    pub keyframe: bool,
+}
+
+/// Codec parameters from a track's sample entry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleEntry {
+    /// Sample entry type, e.g. `avc1`, `hev1`, `av01`, `mp4a`, `fLaC`, `ec-3`
+    pub format: String,
+    /// Decoder configuration: the avcC/hvcC/av1C/dec3 payload, the AAC AudioSpecificConfig
+    /// or the FLAC metadata blocks
+    pub config: Vec<u8>,
+    pub width: u16,
+    pub height: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Dolby Vision configuration box type (`dvcC`, `dvvC` or `dvwC`) and payload
+    pub dolby_vision: Option<([u8; 4], Vec<u8>)>,
+}
+
+/// The samples and codec parameters of a single-track fragmented MP4.
+#[derive(Debug, Clone)]
+pub struct Fmp4Track {
+    pub entry: SampleEntry,
+    pub timescale: u32,
+    /// Edit list start in the track timescale; presentation times are shifted back by it
+    pub media_time: i64,
+    pub samples: Vec<Sample>,
+}
+
+pub fn read_fmp4_track<R: Read + Seek>(r: &mut R) -> Result<Fmp4Track> {
+    let t = read_track(r, "track")?;
+    Ok(Fmp4Track {
+        entry: parse_sample_entry(&t.trak)?,
+        timescale: t.timescale,
+        media_time: edit_media_time(&t.trak)?,
+        samples: t.samples,
+    })
+}
+
+// ==== Output ====
+
+/// Merges a video and an audio fragmented MP4 into one progressive MP4 written to `out`,
+/// with the `moov` (and its sample tables) in front of a single `mdat`.
+pub fn remux_fmp4<V, A, W>(video: &mut V, audio: &mut A, out: &mut W) -> Result<()>
+where
+    V: Read + Seek,
+    A: Read + Seek,
+    W: Write,
+{
+    let tracks = [segment_track(video, "video", b"vide", 0)?, segment_track(audio, "audio", b"soun", 1)?];
+    write_progressive_mp4(&tracks, &mut [video, audio], out)
+}
+
+/// Reads the single track of a fragmented MP4 as the `input`-th input of a progressive MP4:
+/// its samples, sample descriptions and display size.
+pub(crate) fn segment_track<R: Read + Seek>(r: &mut R, what: &str, handler: &[u8; 4], input: usize) -> Result<SegmentTrack> {
+    let t = read_track(r, what)?;
+    let tkhd = find_box(&t.trak.children()?, b"tkhd").cloned().ok_or_else(|| anyhow!("no tkhd"))?;
+    let dims = tkhd.payload.len().saturating_sub(8);
+    let (width, height) = ((read_u32(&tkhd.payload, dims)? >> 16) as u16, (read_u32(&tkhd.payload, dims + 4)? >> 16) as u16);
+    let samples = t
+        .samples
+        .iter()
+        .map(|s| SegmentSample {
+            input,
+            offset: s.offset,
+            size: s.size,
+            dts: s.dts,
+            cts_offset: s.cts_offset as i32,
+            duration: s.duration,
+            keyframe: s.keyframe,
+        })
+        .collect();
+    Ok(SegmentTrack {
+        handler: *handler,
+        timescale: t.timescale,
+        width,
+        height,
+        stsd: sample_descriptions(&t.trak)?.payload,
+        media_time: edit_media_time(&t.trak)?,
+        samples,
+    })
+}
+
+/// Object-safe `Read + Seek`.
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Native counterpart of `downloader::ffmpeg_mux` for mp4 output.
+pub async fn native_mux(video_path: &str, audio_path: &str, out_path: &str) -> Result<()> {
+    let (video_path, audio_path, out_path) = (video_path.to_string(), audio_path.to_string(), out_path.to_string());
+    tokio::task::spawn_blocking(move || {
+        let mut video = BufReader::new(std::fs::File::open(video_path).context("open video track")?);
+        let mut audio = BufReader::new(std::fs::File::open(audio_path).context("open audio track")?);
+        let mut out = BufWriter::new(std::fs::File::create(out_path).context("create output")?);
+        remux_fmp4(&mut video, &mut audio, &mut out)
+    })
+    .await?
+}
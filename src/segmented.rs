@@ -0,0 +1,230 @@
+//! Multi-connection downloads (`-N`): a file is split into byte ranges that are fetched
+//! concurrently into a preallocated `.part` file. Completed ranges are kept in its state
+//! file until the download is done, so `--continue` only fetches what is still missing.
+
+use crate::downloader::{load_state, progress_bar, save_state, state_path, url_host, DownloadOptions, DownloadState, HttpStatus};
+use anyhow::{anyhow, Context, Result};
+use futures_util::StreamExt;
+use indicatif::ProgressBar;
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::io::SeekFrom;
+use std::sync::Mutex;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+/// Smallest range fetched by one request
+pub const MIN_FRAGMENT: u64 = 1 << 20;
+
+/// Sorted, merged half-open byte ranges.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRanges(Vec<(u64, u64)>);
+
+impl ByteRanges {
+    pub fn insert(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let mut merged = (start, end);
+        let mut out = Vec::with_capacity(self.0.len() + 1);
+        for &(s, e) in &self.0 {
+            if e < merged.0 || s > merged.1 {
+                out.push((s, e));
+            } else {
+                merged = (merged.0.min(s), merged.1.max(e));
+            }
+        }
+        out.push(merged);
+        out.sort_unstable();
+        self.0 = out;
+    }
+
+    pub fn ranges(&self) -> &[(u64, u64)] {
+        &self.0
+    }
+
+    /// Number of bytes covered.
+    pub fn covered(&self) -> u64 {
+        self.0.iter().map(|(s, e)| e - s).sum()
+    }
+
+    /// The gaps in `0..total`.
+    pub fn missing(&self, total: u64) -> Vec<(u64, u64)> {
+        let mut gaps = Vec::new();
+        let mut pos = 0;
+        for &(s, e) in &self.0 {
+            if s > pos {
+                gaps.push((pos, s.min(total)));
+            }
+            pos = pos.max(e);
+        }
+        if pos < total {
+            gaps.push((pos, total));
+        }
+        gaps.retain(|(s, e)| s < e);
+        gaps
+    }
+}
+
+/// Splits the missing parts of a `total`-byte file into requests for `workers` connections:
+/// about four per worker, none smaller than [`MIN_FRAGMENT`] unless a gap is.
+pub fn plan_fragments(missing: &[(u64, u64)], total: u64, workers: usize) -> Vec<(u64, u64)> {
+    let piece = total.div_ceil(workers.max(1) as u64 * 4).max(MIN_FRAGMENT);
+    let mut out = Vec::new();
+    for &(mut s, e) in missing {
+        while s < e {
+            let end = (s + piece).min(e);
+            out.push((s, end));
+            s = end;
+        }
+    }
+    out
+}
+
+/// Index of the first mirror that honours Range requests and the file size, or None when
+/// the server ignores Range. Fails when no mirror answers.
+pub(crate) async fn probe(client: &Client, urls: &[String]) -> Result<Option<(usize, u64)>> {
+    let mut last_err = anyhow!("no download URL");
+    for (i, url) in urls.iter().enumerate() {
+        let resp = match client.get(url).header(RANGE, "bytes=0-0").send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                last_err = e.into();
+                continue;
+            }
+        };
+        match resp.status().as_u16() {
+            206 => {
+                // format: bytes 0-0/TOTAL
+                let total = resp
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|cr| cr.to_str().ok()?.rsplit('/').next()?.parse().ok());
+                return Ok(total.map(|t| (i, t)));
+            }
+            // The mirror works but ignores Range
+            200 => return Ok(None),
+            _ => last_err = HttpStatus(resp.status()).into(),
+        }
+    }
+    Err(last_err)
+}
+
+/// Downloads the `.part` file `part` over `opts.concurrent_fragments` connections, continuing
+/// the completed ranges of a matching state. Returns false, after dropping the partial file,
+/// when the server can't serve ranges or the file is too small to split.
+pub(crate) async fn download_segmented(client: &Client, urls: &[String], part: &str, opts: &DownloadOptions) -> Result<bool> {
+    let saved = if opts.resume { load_state(part).filter(|s| s.source == opts.source) } else { None };
+    let on_disk = std::fs::metadata(part).map(|m| m.len()).ok();
+    let probed = probe(client, urls).await?;
+    let done = match (saved, probed) {
+        (Some(DownloadState { size, ranges: Some(done), .. }), Some((_, total))) if size == total && on_disk == Some(total) => Some(done),
+        // A single-stream `.part` is complete up to its length
+        (Some(DownloadState { size, ranges: None, .. }), Some((_, total))) if size == total && on_disk.is_some_and(|len| len <= total) => {
+            let mut done = ByteRanges::default();
+            done.insert(0, on_disk.unwrap_or(0));
+            Some(done)
+        }
+        _ => None,
+    };
+    let Some((first, total)) = probed.filter(|&(_, total)| done.is_some() || total >= 2 * MIN_FRAGMENT) else {
+        // The single-stream download must not trust a preallocated file
+        let _ = std::fs::remove_file(part);
+        let _ = std::fs::remove_file(state_path(part));
+        return Ok(false);
+    };
+
+    let resumable = done.is_some();
+    let done = done.unwrap_or_default();
+    let file = tokio::fs::OpenOptions::new().write(true).create(true).truncate(!resumable).open(part).await?;
+    file.set_len(total).await.context("preallocate file")?;
+    drop(file);
+    let state = |done: &ByteRanges| DownloadState { source: opts.source.clone(), size: total, ranges: Some(done.clone()) };
+    save_state(part, &state(&done))?;
+
+    let workers = opts.concurrent_fragments.max(1);
+    let pb = progress_bar(total, opts);
+    pb.set_position(done.covered());
+    let fragments = plan_fragments(&done.missing(total), total, workers);
+    let shared = Shared { client, urls: &urls[first..], path: part, done: Mutex::new(done), pb, opts };
+    let (done, pb) = (&shared.done, &shared.pb);
+    let mut results = futures_util::stream::iter(fragments)
+        .map(|(start, end)| fetch_fragment(&shared, start, end))
+        .buffer_unordered(workers);
+    let mut failed = None;
+    while let Some(r) = results.next().await {
+        if let Err(e) = r {
+            failed = Some(e);
+            break;
+        }
+        let snapshot = state(&done.lock().unwrap());
+        save_state(part, &snapshot)?;
+    }
+    // Stops the other fragments
+    drop(results);
+    let snapshot = state(&done.lock().unwrap());
+    save_state(part, &snapshot)?;
+    if let Some(e) = failed {
+        pb.abandon();
+        return Err(e);
+    }
+    pb.finish_with_message("done");
+    Ok(true)
+}
+
+/// What the fragments of one download share.
+struct Shared<'a> {
+    client: &'a Client,
+    urls: &'a [String],
+    path: &'a str,
+    done: Mutex<ByteRanges>,
+    pb: ProgressBar,
+    opts: &'a DownloadOptions,
+}
+
+/// Fetches `start..end`, moving to the next mirror from the current offset on failure.
+async fn fetch_fragment(shared: &Shared<'_>, start: u64, end: u64) -> Result<()> {
+    let mut pos = start;
+    let mut last_err = None;
+    for (i, url) in shared.urls.iter().enumerate() {
+        match fetch_range(shared, url, &mut pos, end).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if i + 1 < shared.urls.len() {
+                    let msg = format!("Warning: {} failed ({e:#}); switching to mirror {}/{}", url_host(url), i + 2, shared.urls.len());
+                    shared.pb.println(msg);
+                }
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("no download URL")).context(format!("bytes {}-{}", start, end - 1)))
+}
+
+async fn fetch_range(shared: &Shared<'_>, url: &str, pos: &mut u64, end: u64) -> Result<()> {
+    let resp = shared.client.get(url).header(RANGE, format!("bytes={}-{}", *pos, end - 1)).send().await?;
+    if resp.status().as_u16() != 206 {
+        return Err(HttpStatus(resp.status()).into());
+    }
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(shared.path).await?;
+    file.seek(SeekFrom::Start(*pos)).await?;
+    let mut stream = resp.bytes_stream();
+    while *pos < end
+        && let Some(chunk) = stream.next().await.transpose().context("read chunk")?
+    {
+        let take = chunk.len().min((end - *pos) as usize);
+        if let Some(limiter) = &shared.opts.limiter {
+            limiter.acquire(take as u64).await;
+        }
+        file.write_all(&chunk[..take]).await?;
+        // Only bytes that reached the file count as done
+        file.flush().await?;
+        shared.done.lock().unwrap().insert(*pos, *pos + take as u64);
+        *pos += take as u64;
+        shared.pb.inc(take as u64);
+    }
+    if *pos < end {
+        return Err(anyhow!("connection closed at byte {}", *pos));
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use crate::bilibili::BiliClient;
+use crate::danmaku::{ass_escape, ass_time};
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+
+/// A subtitle track offered by the player API: human CC or AI-generated (`ai-` languages).
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubtitleTrack {
+    #[serde(default)]
+    pub id: u64,
+    /// Language code, e.g. `zh-Hans`, `en`, `ai-zh`
+    pub lan: String,
+    /// Display name, e.g. "中文（简体）"
+    #[serde(default)]
+    pub lan_doc: String,
+    /// Protocol-relative URL of the JSON body; empty when login is required
+    #[serde(default)]
+    pub subtitle_url: String,
+    /// 0 = CC, 1 = AI
+    #[serde(default, rename = "type")]
+    pub kind: i32,
+}
+
+impl SubtitleTrack {
+    pub fn is_ai(&self) -> bool {
+        self.kind == 1 || self.lan.starts_with("ai-")
+    }
+}
+
+/// The JSON subtitle body (`body` holds the cues).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubtitleBody {
+    pub body: Vec<SubtitleCue>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubtitleCue {
+    /// Start time in seconds
+    pub from: f64,
+    /// End time in seconds
+    pub to: f64,
+    pub content: String,
+}
+
+/// Output format of `--convert-subs`; `Json` keeps the original body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubFormat {
+    Json,
+    Srt,
+    Vtt,
+    Ass,
+}
+
+impl SubFormat {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "srt" => Ok(Self::Srt),
+            "vtt" => Ok(Self::Vtt),
+            "ass" => Ok(Self::Ass),
+            other => Err(anyhow!("unsupported subtitle format: {}", other)),
+        }
+    }
+
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Srt => "srt",
+            Self::Vtt => "vtt",
+            Self::Ass => "ass",
+        }
+    }
+
+    pub fn render(self, sub: &SubtitleBody) -> Result<String> {
+        Ok(match self {
+            Self::Json => serde_json::to_string_pretty(sub)?,
+            Self::Srt => to_srt(sub),
+            Self::Vtt => to_vtt(sub),
+            Self::Ass => to_ass(sub),
+        })
+    }
+}
+
+/// Tracks matching a `--sub-langs` list (case-insensitive language codes; `all` matches every track).
+/// Requested languages that are not available are ignored.
+pub fn select_tracks<'a>(tracks: &'a [SubtitleTrack], langs: &[String]) -> Vec<&'a SubtitleTrack> {
+    if langs.is_empty() || langs.iter().any(|l| l.eq_ignore_ascii_case("all")) {
+        return tracks.iter().collect();
+    }
+    tracks
+        .iter()
+        .filter(|t| langs.iter().any(|l| l.eq_ignore_ascii_case(&t.lan)))
+        .collect()
+}
+
+/// ISO 639-2 code for a Bilibili language code (`zh-Hans`, `ai-en`, ...), as required by mp4.
+pub fn iso639_2(lan: &str) -> &'static str {
+    let base = lan.trim_start_matches("ai-");
+    match base.split(['-', '_']).next().unwrap_or("").to_ascii_lowercase().as_str() {
+        "zh" => "chi",
+        "en" => "eng",
+        "ja" => "jpn",
+        "ko" => "kor",
+        "es" => "spa",
+        "fr" => "fre",
+        "de" => "ger",
+        "ru" => "rus",
+        "pt" => "por",
+        "it" => "ita",
+        "ar" => "ara",
+        "th" => "tha",
+        "vi" => "vie",
+        "id" => "ind",
+        "ms" => "may",
+        _ => "und",
+    }
+}
+
+impl BiliClient {
+    /// Subtitle tracks of a page (see [`BiliClient::get_player_info`]).
+    pub async fn get_subtitle_tracks(&self, aid: u64, cid: u64) -> Result<Vec<SubtitleTrack>> {
+        Ok(self.get_player_info(aid, cid).await?.subtitles().to_vec())
+    }
+
+    pub async fn get_subtitle(&self, track: &SubtitleTrack) -> Result<SubtitleBody> {
+        if track.subtitle_url.is_empty() {
+            return Err(anyhow!("subtitle {} has no URL (login may be required)", track.lan));
+        }
+        let url = if track.subtitle_url.starts_with("//") {
+            format!("https:{}", track.subtitle_url)
+        } else {
+            track.subtitle_url.clone()
+        };
+        self.get_json_retry(Url::parse(&url)?).await
+    }
+}
+
+pub fn to_srt(sub: &SubtitleBody) -> String {
+    let mut out = String::new();
+    for (i, cue) in sub.body.iter().enumerate() {
+        let _ = write!(out, "{}\n{} --> {}\n{}\n\n", i + 1, clock(cue.from, ','), clock(cue.to, ','), cue.content.trim());
+    }
+    out
+}
+
+pub fn to_vtt(sub: &SubtitleBody) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in &sub.body {
+        let _ = write!(out, "{} --> {}\n{}\n\n", clock(cue.from, '.'), clock(cue.to, '.'), cue.content.trim());
+    }
+    out
+}
+
+pub fn to_ass(sub: &SubtitleBody) -> String {
+    let mut out = String::from(
+        "[Script Info]\nScriptType: v4.00+\nPlayResX: 1920\nPlayResY: 1080\nScaledBorderAndShadow: yes\n\n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Default,Microsoft YaHei,64,&H00FFFFFF,&H00FFFFFF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,3,0,2,40,40,50,1\n\n\
+         [Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+    );
+    for cue in &sub.body {
+        let _ = writeln!(out, "Dialogue: 0,{},{},Default,,0,0,0,,{}", ass_time(cue.from), ass_time(cue.to), ass_escape(cue.content.trim()));
+    }
+    out
+}
+
+/// `HH:MM:SS<sep>mmm` as used by SRT (`,`) and WebVTT (`.`).
+fn clock(t: f64, sep: char) -> String {
+    let ms = (t.max(0.0) * 1000.0).round() as u64;
+    format!("{:02}:{:02}:{:02}{}{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, sep, ms % 1000)
+}
@@ -1,3 +1,5 @@
+use anyhow::{anyhow, Result};
+
 #[derive(Debug, Clone)]
 pub struct FormatSel {
     pub want_video: bool,
@@ -19,18 +21,47 @@ pub fn parse_format(fmt: &Option<String>, prefer_codec_flag: Option<&str>) -> Fo
             if lower.contains(c) { sel.prefer_codec = Some(if c=="h265" {"hev1".into()} else { c.into() }); }
         }
         if let Some(pos) = lower.find("height<=") {
-            let num = lower[pos+8..].trim_start_matches(|ch: char| ch=='[' || ch=='=' || ch=='<').chars().take_while(|ch| ch.is_ascii_digit()).collect::<String>();
+            let num = lower[pos+8..].trim_start_matches(['[', '=', '<']).chars().take_while(|ch| ch.is_ascii_digit()).collect::<String>();
             if let Ok(h) = num.parse::<i32>() { sel.max_height = Some(h); }
         }
     }
     sel
 }
 
+/// Values substituted into the output (-o) template.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateFields {
+    pub title: String,
+    pub id: String,
+    pub aid: u64,
+    pub cid: u64,
+    pub page: u32,
+    pub page_title: String,
+    pub season: String,
+    pub season_id: u64,
+    pub episode: String,
+    pub episode_id: u64,
+    pub episode_number: u32,
+}
+
 pub fn expand_template(tpl: &str, title: &str, bvid: &str, cid: u64, ext: &str) -> String {
+    let fields = TemplateFields { title: title.to_string(), id: bvid.to_string(), cid, page: 1, ..Default::default() };
+    expand_template_fields(tpl, &fields, ext)
+}
+
+pub fn expand_template_fields(tpl: &str, f: &TemplateFields, ext: &str) -> String {
     let mut out = tpl.to_string();
-    out = out.replace("%(title)s", &sanitize_filename(title));
-    out = out.replace("%(id)s", bvid);
-    out = out.replace("%(cid)s", &cid.to_string());
+    out = out.replace("%(title)s", &sanitize_filename(&f.title));
+    out = out.replace("%(id)s", &f.id);
+    out = out.replace("%(aid)s", &f.aid.to_string());
+    out = out.replace("%(cid)s", &f.cid.to_string());
+    out = out.replace("%(page)s", &f.page.to_string());
+    out = out.replace("%(page_title)s", &sanitize_filename(&f.page_title));
+    out = out.replace("%(season)s", &sanitize_filename(&f.season));
+    out = out.replace("%(season_id)s", &f.season_id.to_string());
+    out = out.replace("%(episode)s", &sanitize_filename(&f.episode));
+    out = out.replace("%(episode_id)s", &f.episode_id.to_string());
+    out = out.replace("%(episode_number)s", &f.episode_number.to_string());
     out = out.replace("%(ext)s", ext);
     if !tpl.contains("%(ext)s") {
         sanitize_filename(&out)
@@ -39,6 +70,79 @@ pub fn expand_template(tpl: &str, title: &str, bvid: &str, cid: u64, ext: &str)
     }
 }
 
+/// Parses a 1-based index list like `1-5,8,10-` into inclusive ranges; `None` marks an open end.
+/// A single index `n` is returned as `(n, Some(n))`.
+pub fn parse_index_ranges(spec: &str) -> Result<Vec<(u32, Option<u32>)>> {
+    let mut ranges = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let range = match part.split_once('-') {
+            Some((a, b)) => {
+                let start = if a.trim().is_empty() { 1 } else { parse_index(a)? };
+                let end = if b.trim().is_empty() { None } else { Some(parse_index(b)?) };
+                (start, end)
+            }
+            None => {
+                let n = parse_index(part)?;
+                (n, Some(n))
+            }
+        };
+        if let (start, Some(end)) = range && start > end {
+            return Err(anyhow!("invalid range: {}", part));
+        }
+        ranges.push(range);
+    }
+    if ranges.is_empty() {
+        return Err(anyhow!("empty index list"));
+    }
+    Ok(ranges)
+}
+
+/// Parses a 1-based page list like `1-5,8,10-` into sorted, de-duplicated pages within `1..=total`.
+/// Open-ended ranges stop at `total`; a single page beyond `total` is an error.
+pub fn parse_page_spec(spec: &str, total: u32) -> Result<Vec<u32>> {
+    let mut pages = Vec::new();
+    for (start, end) in parse_index_ranges(spec)? {
+        if end == Some(start) && start > total {
+            return Err(anyhow!("page {} not found", start));
+        }
+        pages.extend(start..=end.unwrap_or(total).min(total));
+    }
+    pages.sort_unstable();
+    pages.dedup();
+    if pages.is_empty() {
+        return Err(anyhow!("no pages selected by '{}' (video has {} pages)", spec, total));
+    }
+    Ok(pages)
+}
+
+fn parse_index(s: &str) -> Result<u32> {
+    match s.trim().parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(anyhow!("invalid index: {}", s.trim())),
+    }
+}
+
+/// Inputs from a batch file: one per line, blank lines and `#` comment lines ignored.
+pub fn parse_batch_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reads a batch file (`-` reads stdin).
+pub fn read_batch_file(path: &str) -> Result<Vec<String>> {
+    use std::io::Read;
+    let mut text = String::new();
+    if path == "-" {
+        std::io::stdin().read_to_string(&mut text)?;
+    } else {
+        text = std::fs::read_to_string(path).map_err(|e| anyhow!("read batch file {}: {}", path, e))?;
+    }
+    Ok(parse_batch_lines(&text))
+}
+
 pub fn sanitize_filename(s: &str) -> String {
     let bad = ["<", ">", ":", "\"", "\\", "/", "|", "?", "*"];
     let mut out = s.to_string();
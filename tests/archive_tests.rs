@@ -0,0 +1,27 @@
+use bilibili_dl::archive::DownloadArchive;
+
+fn temp_archive(name: &str) -> std::path::PathBuf {
+    let mut p = std::env::temp_dir();
+    p.push(format!("bilibili_dl_{}_{}.txt", name, std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p
+}
+
+#[test]
+fn archive_records_and_reloads() {
+    let path = temp_archive("reload");
+    let mut archive = DownloadArchive::open(&path).expect("missing file is empty");
+    assert!(archive.is_empty());
+    archive.record("BV17x411w7KC", 279786).unwrap();
+    archive.record("BV17x411w7KC", 279786).unwrap(); // duplicate is ignored
+    assert!(archive.contains("BV17x411w7KC", 279786));
+    assert!(!archive.contains("BV17x411w7KC", 1));
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(text, "bilibili BV17x411w7KC_279786\n");
+
+    let reloaded = DownloadArchive::open(&path).unwrap();
+    assert_eq!(reloaded.len(), 1);
+    assert!(reloaded.contains("BV17x411w7KC", 279786));
+    let _ = std::fs::remove_file(&path);
+}
@@ -1,16 +1,19 @@
-use bilibili_dl::bilibili::{extract_bvid, extract_page_param, select_streams_with_format, Dash, DashVideo, DashAudio};
+use bilibili_dl::bangumi::{extract_pgc_id, PgcId};
+use bilibili_dl::bilibili::{av_to_bv, bv_to_av, extract_aid, extract_bvid, extract_page_param, select_streams_with_format, Dash, DashVideo, DashAudio, PlayUrlData};
 
 fn sample_dash() -> Dash {
     Dash {
         video: vec![
-            DashVideo { id: 80, base_url: "v1080_avc1".into(), codecs: "avc1.640028".into(), height: Some(1080), bandwidth: Some(5_000_000) },
-            DashVideo { id: 120, base_url: "v1080_hev1".into(), codecs: "hev1.1.6.L150".into(), height: Some(1080), bandwidth: Some(3_500_000) },
-            DashVideo { id: 64, base_url: "v720_av01".into(), codecs: "av01.0.05M.08".into(), height: Some(720), bandwidth: Some(2_000_000) },
+            DashVideo { id: 80, base_url: "v1080_avc1".into(), codecs: "avc1.640028".into(), height: Some(1080), bandwidth: Some(5_000_000), ..Default::default() },
+            DashVideo { id: 120, base_url: "v1080_hev1".into(), codecs: "hev1.1.6.L150".into(), height: Some(1080), bandwidth: Some(3_500_000), ..Default::default() },
+            DashVideo { id: 64, base_url: "v720_av01".into(), codecs: "av01.0.05M.08".into(), height: Some(720), bandwidth: Some(2_000_000), ..Default::default() },
         ],
         audio: Some(vec![
-            DashAudio { id: 30216, base_url: "a128".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(128_000) },
-            DashAudio { id: 30232, base_url: "a320".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(320_000) },
+            DashAudio { id: 30216, base_url: "a128".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(128_000), ..Default::default() },
+            DashAudio { id: 30232, base_url: "a320".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(320_000), ..Default::default() },
         ]),
+        dolby: None,
+        flac: None,
     }
 }
 
@@ -38,3 +41,176 @@ fn test_select_streams_with_format_prefers_av1_and_filters_height() {
     assert!(v.base_url.contains("av01"));
 }
 
+
+#[test]
+fn test_av_bv_known_pairs() {
+    let pairs = [
+        (1u64, "BV1xx411c7mQ"),
+        (170001, "BV17x411w7KC"),
+        (455017605, "BV1Q541167Qg"),
+        (882584971, "BV1mK4y1C7Bz"),
+    ];
+    for (aid, bvid) in pairs {
+        assert_eq!(av_to_bv(aid), bvid);
+        assert_eq!(bv_to_av(bvid), Some(aid));
+    }
+}
+
+#[test]
+fn test_bv_to_av_rejects_malformed() {
+    assert_eq!(bv_to_av("BV17x411w7K"), None);
+    assert_eq!(bv_to_av("BV17x411w7K0"), None); // '0' is not in the alphabet
+    assert_eq!(bv_to_av("AV17x411w7KC"), None);
+}
+
+#[test]
+fn test_extract_aid_from_inputs() {
+    assert_eq!(extract_aid("av170001"), Some(170001));
+    assert_eq!(extract_aid("AV170001"), Some(170001));
+    assert_eq!(extract_aid("https://www.bilibili.com/video/av170001/?p=2"), Some(170001));
+    assert_eq!(extract_aid("https://www.bilibili.com/video/?aid=170001"), Some(170001));
+    assert_eq!(extract_aid("https://www.bilibili.com/video/BV17x411w7KC"), None);
+}
+
+#[test]
+fn test_extract_pgc_id_from_links() {
+    assert_eq!(extract_pgc_id("https://www.bilibili.com/bangumi/play/ep123?from=search"), Some(PgcId::Episode(123)));
+    assert_eq!(extract_pgc_id("https://www.bilibili.com/bangumi/play/ss456/"), Some(PgcId::Season(456)));
+    assert_eq!(extract_pgc_id("https://www.bilibili.com/bangumi/media/md789"), Some(PgcId::Media(789)));
+    assert_eq!(extract_pgc_id("ep42"), Some(PgcId::Episode(42)));
+    assert_eq!(extract_pgc_id("https://www.bilibili.com/video/BV17x411w7KC"), None);
+}
+
+fn premium_dash() -> Dash {
+    serde_json::from_str(
+        r#"{
+            "video": [{"id": 80, "baseUrl": "v1080", "codecs": "avc1.640028", "height": 1080, "bandwidth": 5000000}],
+            "audio": [{"id": 30280, "baseUrl": "a192", "codecs": "mp4a.40.2", "bandwidth": 192000}],
+            "dolby": {"type": 2, "audio": [{"id": 30250, "baseUrl": "atmos", "codecs": "ec-3", "bandwidth": 768000}]},
+            "flac": {"display": true, "audio": {"id": 30251, "baseUrl": "hires", "codecs": "fLaC", "bandwidth": 2000000}}
+        }"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_dash_models_dolby_and_flac_tracks() {
+    let dash = premium_dash();
+    let ids: Vec<i32> = dash.all_audio().iter().map(|a| a.id).collect();
+    assert_eq!(ids, vec![30280, 30250, 30251]);
+    let labels: Vec<&str> = dash.all_audio().iter().map(|a| a.label()).collect();
+    assert_eq!(labels, vec!["", "Dolby Atmos", "Hi-Res FLAC"]);
+
+    // null dolby/flac objects are accepted
+    let plain: Dash = serde_json::from_str(r#"{"video": [], "audio": null, "dolby": {"type": 0, "audio": null}, "flac": null}"#).unwrap();
+    assert!(plain.all_audio().is_empty());
+}
+
+#[test]
+fn test_stream_urls_match_id_and_codecs() {
+    let dash = premium_dash();
+    assert_eq!(dash.stream_urls(80, "avc1.640028"), Some(vec!["v1080".to_string()]));
+    assert_eq!(dash.stream_urls(30250, ""), Some(vec!["atmos".to_string()]));
+    assert_eq!(dash.stream_urls(30251, "fLaC"), Some(vec!["hires".to_string()]));
+    assert_eq!(dash.stream_urls(80, "hev1.1.6.L150.90"), None);
+}
+
+#[test]
+fn test_select_dolby_and_flac_only_when_asked() {
+    let dash = premium_dash();
+    let (_, a) = select_streams_with_format(&dash, "bv+ba");
+    assert_eq!(a.unwrap().base_url, "a192");
+    let (v, a) = select_streams_with_format(&dash, "bestaudio[acodec=flac]");
+    assert!(v.is_none());
+    assert_eq!(a.unwrap().base_url, "hires");
+    let (v, a) = select_streams_with_format(&dash, "bv+ba[dolby]");
+    assert_eq!(v.unwrap().base_url, "v1080");
+    assert_eq!(a.unwrap().base_url, "atmos");
+    let (_, a) = select_streams_with_format(&dash, "ba[acodec^=ec-3]");
+    assert_eq!(a.unwrap().id, 30250);
+    // falls through to the next alternative when no Hi-Res track exists
+    let (_, a) = select_streams_with_format(&sample_dash(), "ba[flac]/ba");
+    assert_eq!(a.unwrap().base_url, "a320");
+}
+
+fn hdr_playurl() -> PlayUrlData {
+    serde_json::from_str(
+        r#"{
+            "accept_quality": [127, 126, 125, 116, 80],
+            "accept_description": ["超高清 8K", "杜比视界", "HDR 真彩", "高清 1080P60", "高清 1080P"],
+            "dash": {
+                "video": [
+                    {"id": 127, "baseUrl": "v8k", "codecs": "av01.0.17M.10", "width": 7680, "height": 4320, "frameRate": "60.000", "sar": "1:1", "codecid": 13},
+                    {"id": 126, "baseUrl": "vdv", "codecs": "dvh1.08.07", "width": 3840, "height": 2160, "frameRate": "29.970", "codecid": 12},
+                    {"id": 125, "baseUrl": "vhdr", "codecs": "hev1.2.4.L153.90", "width": 3840, "height": 2160, "frame_rate": "30000/1001", "codecid": 12},
+                    {"id": 116, "baseUrl": "v1080p60", "codecs": "avc1.640032", "width": 1920, "height": 1080, "frameRate": "59.940", "codecid": 7},
+                    {"id": 80, "baseUrl": "v1080", "codecs": "avc1.640032", "width": 1920, "height": 1080, "frameRate": "29.970", "codecid": 7}
+                ],
+                "audio": [{"id": 30280, "baseUrl": "a192", "codecs": "mp4a.40.2"}]
+            }
+        }"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_video_dynamic_range_and_quality_labels() {
+    let data = hdr_playurl();
+    let dash = data.dash.as_ref().unwrap();
+    let ranges: Vec<&str> = dash.video.iter().map(|v| v.dynamic_range()).collect();
+    assert_eq!(ranges, vec!["SDR", "DV", "HDR10", "SDR", "SDR"]);
+    assert_eq!(dash.video[0].width, Some(7680));
+    assert_eq!(dash.video[0].sar.as_deref(), Some("1:1"));
+    assert!((dash.video[2].fps().unwrap() - 29.97).abs() < 0.01);
+    assert_eq!(data.quality_label(126), "杜比视界");
+    // ids missing from accept_quality fall back to the built-in names
+    assert_eq!(data.quality_label(120), "4K");
+}
+
+#[test]
+fn test_select_by_dynamic_range_and_fps() {
+    let data = hdr_playurl();
+    let dash = data.dash.as_ref().unwrap();
+    let pick = |f: &str| select_streams_with_format(dash, f).0.map(|v| v.base_url);
+    assert_eq!(pick("bv[dynamic_range=HDR]+ba").as_deref(), Some("vdv"));
+    assert_eq!(pick("bv[dynamic_range=HDR10]+ba").as_deref(), Some("vhdr"));
+    assert_eq!(pick("bv[dynamic_range=SDR][height<=1080][fps>30]+ba").as_deref(), Some("v1080p60"));
+    assert_eq!(pick("bv[height<=1080][fps<=30]+ba").as_deref(), Some("v1080"));
+    assert_eq!(pick("bv[fps>60]+ba"), None);
+}
+
+#[test]
+fn test_playurl_durl_segments() {
+    let data: PlayUrlData = serde_json::from_str(
+        r#"{
+            "quality": 32, "format": "flv480", "accept_quality": [32, 16], "accept_description": ["清晰 480P", "流畅 360P"],
+            "durl": [
+                {"order": 2, "length": 300000, "size": 2048, "url": "https://cdn/2.flv?e=1", "backup_url": null},
+                {"order": 1, "length": 360000, "size": 4096, "url": "https://cdn/1.flv?e=1", "backup_url": ["https://bak/1.flv"]}
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert!(data.dash.is_none());
+    assert_eq!(data.durl.len(), 2);
+    assert_eq!(data.durl[1].backup_url, vec!["https://bak/1.flv"]);
+    assert_eq!(data.durl_ext(), "flv");
+    assert_eq!(data.quality_label(data.quality.unwrap()), "清晰 480P");
+
+    let mp4: PlayUrlData = serde_json::from_str(r#"{"durl": [{"url": "https://cdn/x.mp4?e=1"}]}"#).unwrap();
+    assert_eq!(mp4.durl_ext(), "mp4");
+}
+
+#[test]
+fn test_dash_keeps_backup_urls() {
+    // Responses carry both spellings; the camelCase one is used
+    let dash: Dash = serde_json::from_str(
+        r#"{
+            "video": [{"id": 80, "baseUrl": "https://a/v", "base_url": "https://a/v", "codecs": "avc1", "backupUrl": ["https://b/v", "https://c/v"], "backup_url": ["https://b/v", "https://c/v"]}],
+            "audio": [{"id": 30280, "baseUrl": "https://a/a", "codecs": "mp4a.40.2", "backupUrl": null}]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(dash.video[0].urls(), vec!["https://a/v", "https://b/v", "https://c/v"]);
+    assert_eq!(dash.audio.unwrap()[0].urls(), vec!["https://a/a"]);
+}
@@ -9,3 +9,9 @@ fn cli_help_shows_usage() {
     cmd.assert().success().stdout(contains("bilibili-dl")).stdout(contains("Usage"));
 }
 
+
+#[test]
+fn cli_requires_input_or_batch_file() {
+    let mut cmd = Command::cargo_bin("bilibili-dl").expect("binary exists");
+    cmd.assert().failure().stderr(contains("required arguments were not provided"));
+}
@@ -0,0 +1,88 @@
+//! A local HTTP/1.1 server for downloader tests.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+/// (path, Range start) of every request served
+pub type RequestLog = Arc<Mutex<Vec<(String, u64)>>>;
+
+/// Serves `body` at every path, answering `Range: bytes=a-[b]` with 206 (416 past the end). `/forbidden`
+/// answers 403, `/norange` ignores Range, `/cut` drops the connection after 10 bytes and
+/// `/slow` sends the body in ten pieces 100 ms apart, then logs `/slow:done`.
+/// Returns the base URL and the request log.
+pub fn serve(body: Vec<u8>) -> (String, RequestLog) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let requests = log.clone();
+    let body = Arc::new(body);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (requests, body) = (requests.clone(), body.clone());
+            std::thread::spawn(move || handle(stream, &body, &requests));
+        }
+    });
+    (base, log)
+}
+
+fn handle(mut stream: TcpStream, body: &[u8], log: &Mutex<Vec<(String, u64)>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request = String::new();
+    reader.read_line(&mut request).unwrap();
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(r) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+            let (a, b) = r.trim().split_once('-').unwrap();
+            let end = if b.is_empty() { body.len() - 1 } else { b.parse::<usize>().unwrap().min(body.len() - 1) };
+            range = Some((a.parse::<usize>().unwrap(), end));
+        }
+    }
+    log.lock().unwrap().push((path.clone(), range.map_or(0, |(a, _)| a as u64)));
+    if path == "/forbidden" {
+        let _ = stream.write_all(b"HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+        return;
+    }
+    if range.is_some_and(|(a, _)| a >= body.len()) && path != "/norange" {
+        let head = format!("HTTP/1.1 416 Range Not Satisfiable\r\ncontent-range: bytes */{}\r\n", body.len());
+        let _ = stream.write_all(format!("{}content-length: 0\r\nconnection: close\r\n\r\n", head).as_bytes());
+        return;
+    }
+    let (head, part) = match range {
+        Some((a, b)) if path != "/norange" => (
+            format!("HTTP/1.1 206 Partial Content\r\ncontent-range: bytes {}-{}/{}\r\n", a, b, body.len()),
+            &body[a..=b],
+        ),
+        _ => ("HTTP/1.1 200 OK\r\n".to_string(), body),
+    };
+    let _ = stream.write_all(format!("{}content-length: {}\r\nconnection: close\r\n\r\n", head, part.len()).as_bytes());
+    match path.as_str() {
+        "/cut" => {
+            let _ = stream.write_all(&part[..10.min(part.len())]);
+        }
+        "/slow" => {
+            for piece in part.chunks(part.len().div_ceil(10).max(1)) {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                if stream.write_all(piece).is_err() {
+                    return;
+                }
+            }
+            log.lock().unwrap().push(("/slow:done".to_string(), 0));
+        }
+        _ => {
+            let _ = stream.write_all(part);
+        }
+    }
+}
+
+/// A fresh output path under the system temp dir; the directory is not created.
+pub fn temp_path(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("bilibili-dl-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join("track.m4s").to_string_lossy().into_owned()
+}
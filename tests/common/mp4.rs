@@ -0,0 +1,71 @@
+//! Builders for MP4 box fixtures.
+
+/// A box with a 32-bit size header.
+pub fn bx(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// A full box payload: version, 24-bit flags, then `rest`.
+pub fn full(version: u8, flags: u32, rest: &[u8]) -> Vec<u8> {
+    let mut out = vec![version];
+    out.extend_from_slice(&flags.to_be_bytes()[1..]);
+    out.extend_from_slice(rest);
+    out
+}
+
+pub fn u32s(vals: &[u32]) -> Vec<u8> {
+    vals.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+/// A fragmented MP4 init segment (`ftyp`, `moov`, `sidx`) with one track, id 1, whose `stsd`
+/// holds `entry`. `edit` is one edit list entry as (duration in the 1000 Hz movie timescale,
+/// media_time in `timescale`); without it the track has no `edts`.
+pub fn init_segment(handler: &[u8; 4], timescale: u32, entry: Vec<u8>, edit: Option<(u32, u32)>) -> Vec<u8> {
+    let duration = edit.map_or(0, |(d, _)| d);
+    let mut mvhd = u32s(&[0, 0, 1000, duration, 0x0001_0000]);
+    mvhd.extend_from_slice(&[1, 0]);
+    mvhd.extend_from_slice(&[0; 10 + 36 + 24]);
+    mvhd.extend_from_slice(&2u32.to_be_bytes());
+    let mvhd = bx(b"mvhd", &full(0, 0, &mvhd));
+
+    let mut tkhd = u32s(&[0, 0, 1, 0, duration]);
+    tkhd.extend_from_slice(&[0; 60]);
+    let tkhd = bx(b"tkhd", &full(0, 3, &tkhd));
+    let edts = edit
+        .map(|(d, media_time)| bx(b"edts", &bx(b"elst", &full(0, 0, &u32s(&[1, d, media_time, 0x0001_0000])))))
+        .unwrap_or_default();
+    let mdhd = bx(b"mdhd", &full(0, 0, &[u32s(&[0, 0, timescale, 0]), vec![0x55, 0xc4, 0, 0]].concat()));
+    let hdlr = bx(b"hdlr", &full(0, 0, &[u32s(&[0]), handler.to_vec(), vec![0; 12], b"h\0".to_vec()].concat()));
+    let stsd = bx(b"stsd", &full(0, 0, &[u32s(&[1]), entry].concat()));
+    let minf = bx(b"minf", &bx(b"stbl", &stsd));
+    let mdia = bx(b"mdia", &[mdhd, hdlr, minf].concat());
+    let trak = bx(b"trak", &[tkhd, edts, mdia].concat());
+    let mvex = bx(b"mvex", &bx(b"trex", &full(0, 0, &u32s(&[1, 1, 0, 0, 0]))));
+    let moov = bx(b"moov", &[mvhd, trak, mvex].concat());
+    let ftyp = bx(b"ftyp", b"iso5\0\0\0\x01iso6mp41");
+    let sidx = bx(b"sidx", &full(0, 0, &[0; 24]));
+    [ftyp, moov, sidx].concat()
+}
+
+/// One `moof`+`mdat` for track 1; each sample is (duration, bytes). `tfdt` is omitted when
+/// `decode_time` is None.
+pub fn fragment(seq: u32, decode_time: Option<u32>, samples: &[(u32, &[u8])]) -> Vec<u8> {
+    let mfhd = bx(b"mfhd", &full(0, 0, &u32s(&[seq])));
+    let tfhd = bx(b"tfhd", &full(0, 0x02_0000, &u32s(&[1])));
+    let tfdt = decode_time.map(|t| bx(b"tfdt", &full(0, 0, &u32s(&[t])))).unwrap_or_default();
+    let build = |data_offset: u32| {
+        let mut trun = u32s(&[samples.len() as u32, data_offset]);
+        for (d, b) in samples {
+            trun.extend(u32s(&[*d, b.len() as u32]));
+        }
+        let traf = bx(b"traf", &[tfhd.clone(), tfdt.clone(), bx(b"trun", &full(0, 0x301, &trun))].concat());
+        bx(b"moof", &[mfhd.clone(), traf].concat())
+    };
+    // the data offset counts from the start of the moof to the first byte of the mdat payload
+    let moof = build(build(0).len() as u32 + 8);
+    let data: Vec<u8> = samples.iter().flat_map(|(_, b)| b.to_vec()).collect();
+    [moof, bx(b"mdat", &data)].concat()
+}
@@ -0,0 +1,145 @@
+use bilibili_dl::concat::{concat_segments, read_mp4_segment, SegmentTrack};
+use bilibili_dl::flv::read_flv_segment;
+use bilibili_dl::remux::{find_box, parse_boxes, Mp4Box};
+use std::io::Cursor;
+
+// ---- FLV fixture: H.264 + AAC tags ----
+
+fn tag(kind: u8, time: u32, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![kind];
+    out.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    out.extend_from_slice(&time.to_be_bytes()[1..]);
+    out.push((time >> 24) as u8);
+    out.extend_from_slice(&[0, 0, 0]);
+    out.extend_from_slice(body);
+    out.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+    out
+}
+
+fn script_tag() -> Vec<u8> {
+    let mut body = vec![2, 0, 10];
+    body.extend_from_slice(b"onMetaData");
+    body.extend_from_slice(&[8, 0, 0, 0, 2]);
+    for (k, v) in [("width", 640.0f64), ("height", 360.0)] {
+        body.extend_from_slice(&(k.len() as u16).to_be_bytes());
+        body.extend_from_slice(k.as_bytes());
+        body.push(0);
+        body.extend_from_slice(&v.to_be_bytes());
+    }
+    body.extend_from_slice(&[0, 0, 9]);
+    tag(18, 0, &body)
+}
+
+/// Two video frames (40 ms apart, the first a keyframe with a 40 ms composition offset)
+/// and three AAC frames at 44.1 kHz; payloads are tagged with `name`.
+fn flv(name: &str) -> Vec<u8> {
+    let mut out = b"FLV\x01\x05\0\0\0\x09\0\0\0\0".to_vec();
+    out.extend(script_tag());
+    out.extend(tag(9, 0, &[0x17, 0, 0, 0, 0, 1, 0x64, 0, 0x1e, 0xff]));
+    out.extend(tag(8, 0, &[0xaf, 0, 0x12, 0x10]));
+    out.extend(tag(9, 0, &[&[0x17, 1, 0, 0, 40][..], format!("{name}V0").as_bytes()].concat()));
+    out.extend(tag(8, 0, &[&[0xaf, 1][..], format!("{name}A0").as_bytes()].concat()));
+    out.extend(tag(8, 23, &[&[0xaf, 1][..], format!("{name}A1").as_bytes()].concat()));
+    out.extend(tag(9, 40, &[&[0x27, 1, 0, 0, 0][..], format!("{name}V1").as_bytes()].concat()));
+    out.extend(tag(8, 46, &[&[0xaf, 1][..], format!("{name}A2").as_bytes()].concat()));
+    out
+}
+
+#[test]
+fn demuxes_flv_tags() {
+    let tracks = read_flv_segment(&mut Cursor::new(flv("a"))).unwrap();
+    assert_eq!(tracks.len(), 2);
+    let (v, a) = (&tracks[0], &tracks[1]);
+    assert_eq!(&v.handler, b"vide");
+    assert_eq!((v.width, v.height, v.timescale), (640, 360, 1000));
+    let times: Vec<(u64, i32, u32, bool)> = v.samples.iter().map(|s| (s.dts, s.cts_offset, s.duration, s.keyframe)).collect();
+    assert_eq!(times, vec![(0, 40, 40, true), (40, 0, 40, false)]);
+    assert!(v.stsd.windows(4).any(|w| w == b"avcC"));
+
+    assert_eq!(&a.handler, b"soun");
+    assert_eq!(a.timescale, 44100);
+    let dts: Vec<u64> = a.samples.iter().map(|s| s.dts).collect();
+    assert_eq!(dts, vec![0, 1024, 2048]);
+    assert!(a.stsd.windows(4).any(|w| w == b"esds"));
+}
+
+// ---- reading the MP4 output ----
+
+fn u32_at(b: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(b[pos..pos + 4].try_into().unwrap())
+}
+
+fn path(boxes: &[Mp4Box], kinds: &[&[u8; 4]]) -> Mp4Box {
+    let mut cur = find_box(boxes, kinds[0]).unwrap().clone();
+    for k in &kinds[1..] {
+        cur = find_box(&cur.children().unwrap(), k).unwrap().clone();
+    }
+    cur
+}
+
+/// The payload of every sample of every track, read back through the sample tables.
+fn sample_data(file: &[u8]) -> Vec<Vec<String>> {
+    let tracks = read_mp4_segment(&mut Cursor::new(file.to_vec())).unwrap();
+    tracks
+        .iter()
+        .map(|t: &SegmentTrack| {
+            t.samples
+                .iter()
+                .map(|s| String::from_utf8(file[s.offset as usize..s.offset as usize + s.size as usize].to_vec()).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+fn concat(segments: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut cursors: Vec<Cursor<Vec<u8>>> = segments.into_iter().map(Cursor::new).collect();
+    let mut inputs: Vec<&mut dyn bilibili_dl::remux::ReadSeek> = cursors.iter_mut().map(|c| c as _).collect();
+    let mut out = Vec::new();
+    concat_segments(&mut inputs, &mut out).unwrap();
+    out
+}
+
+#[test]
+fn joins_flv_segments_into_mp4() {
+    let out = concat(vec![flv("a"), flv("b")]);
+    let top = parse_boxes(&out).unwrap();
+    let kinds: Vec<&[u8; 4]> = top.iter().map(|b| &b.kind).collect();
+    assert_eq!(kinds, [b"ftyp", b"moov", b"mdat"]);
+
+    assert_eq!(
+        sample_data(&out),
+        vec![vec!["aV0", "aV1", "bV0", "bV1"], vec!["aA0", "aA1", "aA2", "bA0", "bA1", "bA2"]]
+    );
+
+    // The second segment starts where the longer (video, 80 ms) track of the first ended
+    let stts = path(&top, &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stts"]);
+    assert_eq!(u32_at(&stts.payload, 4), 1);
+    assert_eq!((u32_at(&stts.payload, 8), u32_at(&stts.payload, 12)), (4, 40));
+    let tracks = read_mp4_segment(&mut Cursor::new(out.clone())).unwrap();
+    assert_eq!(tracks[1].samples[3].dts, 80 * 44100 / 1000);
+    // B-frame delay is trimmed with an edit list
+    let elst = path(&top, &[b"moov", b"trak", b"edts", b"elst"]);
+    assert_eq!(u32_at(&elst.payload, 12), 40);
+    // Only the keyframes are listed as sync samples
+    let stss = path(&top, &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stss"]);
+    assert_eq!((u32_at(&stss.payload, 4), u32_at(&stss.payload, 8), u32_at(&stss.payload, 12)), (2, 1, 3));
+}
+
+#[test]
+fn joins_mp4_segments() {
+    let first = concat(vec![flv("a")]);
+    let second = concat(vec![flv("b")]);
+    let out = concat(vec![first, second]);
+    assert_eq!(
+        sample_data(&out),
+        vec![vec!["aV0", "aV1", "bV0", "bV1"], vec!["aA0", "aA1", "aA2", "bA0", "bA1", "bA2"]]
+    );
+}
+
+#[test]
+fn rejects_unsupported_flv_codecs() {
+    let mut data = b"FLV\x01\x05\0\0\0\x09\0\0\0\0".to_vec();
+    data.extend(tag(9, 0, &[0x12, 0, 0, 0, 0]));
+    let err = read_flv_segment(&mut Cursor::new(data)).unwrap_err();
+    assert!(err.to_string().contains("unsupported FLV video codec"));
+}
@@ -0,0 +1,108 @@
+use bilibili_dl::danmaku::{parse_seg, parse_xml, to_ass, to_xml, AssOptions, Danmaku};
+
+const FIXTURE: &str = include_str!("fixtures/danmaku.xml");
+
+fn varint(mut v: u64, out: &mut Vec<u8>) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn field_varint(field: u64, v: u64, out: &mut Vec<u8>) {
+    varint(field << 3, out);
+    varint(v, out);
+}
+
+fn field_bytes(field: u64, b: &[u8], out: &mut Vec<u8>) {
+    varint((field << 3) | 2, out);
+    varint(b.len() as u64, out);
+    out.extend_from_slice(b);
+}
+
+fn scrolling(id: u64, time: f64, content: &str) -> Danmaku {
+    Danmaku { id, time, mode: 1, font_size: 25, color: 0xFFFFFF, ctime: 0, pool: 0, mid_hash: String::new(), weight: 0, content: content.into() }
+}
+
+#[test]
+fn parse_xml_fixture_sorts_dedups_and_unescapes() {
+    let items = parse_xml(FIXTURE);
+    assert_eq!(items.len(), 4);
+    assert_eq!(items[0].id, 1001);
+    assert_eq!(items[0].mode, 5);
+    assert_eq!(items[0].color, 0xFF0000);
+    assert_eq!(items[0].content, "top & red");
+    assert_eq!(items[1].content, "第二条");
+    assert_eq!(items[2].content, "<bottom>");
+    assert_eq!(items[2].font_size, 36);
+}
+
+#[test]
+fn xml_round_trip() {
+    let items = parse_xml(FIXTURE);
+    let again = parse_xml(&to_xml(279786, &items));
+    assert_eq!(items, again);
+}
+
+#[test]
+fn parse_seg_decodes_elements() {
+    let mut elem = Vec::new();
+    field_varint(1, 987654321012, &mut elem);
+    field_varint(2, 61_500, &mut elem);
+    field_varint(3, 4, &mut elem);
+    field_varint(4, 18, &mut elem);
+    field_varint(5, 0x00FF00, &mut elem);
+    field_bytes(6, b"deadbeef", &mut elem);
+    field_bytes(7, "你好".as_bytes(), &mut elem);
+    field_varint(8, 1_700_000_000, &mut elem);
+    field_bytes(10, b"picture:x", &mut elem); // unknown fields are skipped
+    field_varint(11, 1, &mut elem);
+    let mut reply = Vec::new();
+    field_bytes(1, &elem, &mut reply);
+    field_bytes(1, &elem, &mut reply);
+
+    let items = parse_seg(&reply).unwrap();
+    assert_eq!(items.len(), 2);
+    let d = &items[0];
+    assert_eq!(d.id, 987654321012);
+    assert!((d.time - 61.5).abs() < 1e-9);
+    assert_eq!((d.mode, d.font_size, d.color, d.pool), (4, 18, 0x00FF00, 1));
+    assert_eq!(d.mid_hash, "deadbeef");
+    assert_eq!(d.content, "你好");
+    assert_eq!(d.ctime, 1_700_000_000);
+
+    assert!(parse_seg(&reply[..reply.len() - 3]).is_err());
+    assert!(parse_seg(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn ass_positions_by_mode_and_skips_advanced() {
+    let ass = to_ass(&parse_xml(FIXTURE), &AssOptions::default());
+    assert!(ass.contains("PlayResX: 1920"));
+    assert!(ass.contains("Style: Danmaku,Microsoft YaHei,48,&H33FFFFFF"));
+    let events: Vec<&str> = ass.lines().filter(|l| l.starts_with("Dialogue:")).collect();
+    assert_eq!(events.len(), 3);
+    assert!(events[0].starts_with("Dialogue: 0,0:00:03.20,0:00:07.20,"));
+    assert!(events[0].contains("\\an8\\pos(960,0)\\c&H0000FF&}top & red"));
+    assert!(events[1].contains("\\move(1920,0,"));
+    assert!(events[2].contains("\\an2\\pos(960,1080)\\fs69}<bottom>"));
+}
+
+#[test]
+fn ass_density_limits_lanes() {
+    let items: Vec<Danmaku> = (0..10).map(|i| scrolling(i, 1.0, "同时出现")).collect();
+    let opts = AssOptions { font_size: 54, density: 0.1, opacity: 1.0, ..Default::default() };
+    let ass = to_ass(&items, &opts);
+    assert!(ass.contains("&H00FFFFFF"));
+    // 1080 * 0.1 / 54 = 2 lanes
+    let events: Vec<&str> = ass.lines().filter(|l| l.starts_with("Dialogue:")).collect();
+    assert_eq!(events.len(), 2);
+    assert!(events[0].contains("\\move(1920,0,"));
+    assert!(events[1].contains("\\move(1920,54,"));
+
+    // Once the first comment has scrolled in, its lane is reused.
+    let later = [scrolling(1, 1.0, "a"), scrolling(2, 4.0, "b")];
+    let ass = to_ass(&later, &AssOptions { density: 0.01, ..Default::default() });
+    assert_eq!(ass.lines().filter(|l| l.contains("\\move(1920,0,")).count(), 2);
+}
@@ -0,0 +1,295 @@
+use bilibili_dl::downloader::{
+    download_mirrors, download_tracks, ffmetadata, ffmpeg_mux_args, is_pcdn, rank_mirrors, Chapter, DownloadOptions, MuxOptions,
+    MuxSubtitle, TrackJob,
+    save_state, url_expired, DownloadState, Refresher, StreamSource,
+};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+mod common;
+use common::{serve, temp_path};
+
+fn opts() -> MuxOptions {
+    MuxOptions {
+        subtitles: vec![MuxSubtitle { path: "x.zh-Hans.srt".into(), language: "chi".into(), title: "中文".into() }],
+        cover: Some("x.cover.jpg".into()),
+        metadata: vec![("title".into(), "T".into())],
+        chapters: vec![],
+    }
+}
+
+#[test]
+fn plain_mux_is_stream_copy() {
+    let args = ffmpeg_mux_args("v.m4s", "a.m4s", "out.mp4", &MuxOptions::default(), None);
+    assert_eq!(args, ["-y", "-i", "v.m4s", "-i", "a.m4s", "-c", "copy", "out.mp4"]);
+}
+
+#[test]
+fn mp4_uses_mov_text_and_attached_pic() {
+    let args = ffmpeg_mux_args("v.m4s", "a.m4s", "out.mp4", &opts(), Some("out.mp4.ffmeta.txt")).join(" ");
+    assert_eq!(
+        args,
+        "-y -i v.m4s -i a.m4s -i x.zh-Hans.srt -i x.cover.jpg -i out.mp4.ffmeta.txt \
+         -map 0 -map 1 -map 2 -map 3 -map_metadata 4 -map_chapters 4 -c copy -c:s mov_text \
+         -metadata:s:s:0 language=chi -metadata:s:s:0 title=中文 -disposition:v:1 attached_pic out.mp4"
+    );
+}
+
+#[test]
+fn mkv_copies_subs_and_attaches_cover() {
+    let args = ffmpeg_mux_args("v.m4s", "a.m4s", "out.mkv", &opts(), Some("m.txt")).join(" ");
+    assert!(args.contains("-i x.zh-Hans.srt -i m.txt -map 0 -map 1 -map 2 -map_metadata 3"));
+    assert!(args.contains("-c:s copy"));
+    assert!(args.ends_with("-attach x.cover.jpg -metadata:s:t mimetype=image/jpeg -metadata:s:t filename=cover.jpg out.mkv"));
+    assert!(!args.contains("attached_pic"));
+}
+
+#[test]
+fn ffmetadata_escapes_and_lists_chapters() {
+    let meta = vec![("title".to_string(), "a=b;c#d\\e".to_string()), ("description".to_string(), "line1\nline2".to_string())];
+    let chapters = vec![Chapter { start_ms: 0, end_ms: 90_000, title: "开场".into() }];
+    assert_eq!(
+        ffmetadata(&meta, &chapters),
+        ";FFMETADATA1\ntitle=a\\=b\\;c\\#d\\\\e\ndescription=line1\\\nline2\n\n[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=90000\ntitle=开场\n"
+    );
+}
+
+// ---- mirrors ----
+
+fn strings(v: &[&str]) -> Vec<String> {
+    v.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn detects_pcdn_hosts() {
+    assert!(is_pcdn("https://xy1x2x3x4xy.mcdn.bilivideo.cn:4483/upgcxcode/x.m4s"));
+    assert!(is_pcdn("https://abc.szbdyd.com/upgcxcode/x.m4s"));
+    assert!(is_pcdn("http://117.1.2.3:8082/v1/x.m4s"));
+    assert!(!is_pcdn("https://upos-sz-mirrorcos.bilivideo.com/upgcxcode/x.m4s"));
+    assert!(!is_pcdn("https://cn-gdfs-ct-01-13.bilivideo.com/upgcxcode/x.m4s"));
+}
+
+#[test]
+fn ranks_pcdn_last_and_cdn_hosts_first() {
+    let urls = strings(&[
+        "https://xy1.mcdn.bilivideo.cn:4483/upgcxcode/12/34/x.m4s?deadline=1",
+        "https://upos-sz-mirrorcos.bilivideo.com/upgcxcode/12/34/x.m4s?deadline=1",
+        "https://upos-sz-mirrorhw.bilivideo.com/upgcxcode/12/34/x.m4s?deadline=1",
+    ]);
+    assert_eq!(rank_mirrors(&urls, &[]), vec![urls[1].clone(), urls[2].clone(), urls[0].clone()]);
+
+    // A returned host is moved up; an unknown one gets the upos path with the same query
+    let ranked = rank_mirrors(&urls, &strings(&["upos-sz-mirrorhw.bilivideo.com", "https://upos-sz-mirrorali.bilivideo.com/"]));
+    assert_eq!(
+        ranked,
+        vec![
+            urls[2].clone(),
+            "https://upos-sz-mirrorali.bilivideo.com/upgcxcode/12/34/x.m4s?deadline=1".to_string(),
+            urls[1].clone(),
+            urls[0].clone(),
+        ]
+    );
+}
+
+// ---- failover against a local server ----
+
+const BODY: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+#[tokio::test]
+async fn fails_over_and_resumes_at_offset() {
+    let (base, log) = serve(BODY.to_vec());
+    let out = temp_path("failover");
+    let urls = vec![format!("{}/forbidden", base), format!("{}/cut", base), format!("{}/ok", base)];
+    download_mirrors(&urls, &out, &DownloadOptions::default()).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+    let expected = [("/forbidden", 0), ("/cut", 0), ("/ok", 10)].map(|(p, s)| (p.to_string(), s));
+    assert_eq!(*log.lock().unwrap(), expected);
+    let _ = std::fs::remove_dir_all(std::path::Path::new(&out).parent().unwrap());
+}
+
+#[tokio::test]
+async fn reports_the_last_mirror_error() {
+    let (base, _) = serve(BODY.to_vec());
+    let out = temp_path("all-fail");
+    let urls = vec![format!("{}/forbidden", base), format!("{}/forbidden", base)];
+    let err = download_mirrors(&urls, &out, &DownloadOptions::default()).await.unwrap_err();
+    assert!(err.to_string().contains("403"));
+    let _ = std::fs::remove_dir_all(std::path::Path::new(&out).parent().unwrap());
+}
+
+// ---- concurrent tracks ----
+
+fn job(url: String, out: &str, name: &str, label: &str) -> TrackJob {
+    let path = std::path::Path::new(out).with_file_name(name).to_string_lossy().into_owned();
+    TrackJob { urls: vec![url], path, label: label.into(), source: StreamSource::default(), refresh: None }
+}
+
+#[tokio::test]
+async fn downloads_tracks_together() {
+    let (base, log) = serve(BODY.to_vec());
+    let out = temp_path("tracks");
+    let jobs = [job(format!("{}/slow", base), &out, "v.m4s", "video"), job(format!("{}/slow", base), &out, "a.m4s", "audio")];
+    download_tracks(&jobs, &DownloadOptions::default()).await.unwrap();
+    // Both requests start before either transfer finishes
+    let paths: Vec<String> = log.lock().unwrap().iter().map(|(p, _)| p.clone()).collect();
+    assert_eq!(paths[..2], ["/slow", "/slow"]);
+    for j in &jobs {
+        assert_eq!(std::fs::read(&j.path).unwrap(), BODY);
+    }
+    let _ = std::fs::remove_dir_all(std::path::Path::new(&out).parent().unwrap());
+}
+
+#[tokio::test]
+async fn a_failed_track_cancels_and_removes_the_other() {
+    let (base, _) = serve(BODY.to_vec());
+    let out = temp_path("tracks-cancel");
+    let jobs = [job(format!("{}/slow", base), &out, "v.m4s", "video"), job(format!("{}/forbidden", base), &out, "a.m4s", "audio")];
+    let err = download_tracks(&jobs, &DownloadOptions::default()).await.unwrap_err();
+    assert!(format!("{err:#}").contains("audio track"));
+    for j in &jobs {
+        assert!(!std::path::Path::new(&j.path).exists());
+        assert!(!std::path::Path::new(&format!("{}.part", j.path)).exists());
+    }
+    let _ = std::fs::remove_dir_all(std::path::Path::new(&out).parent().unwrap());
+}
+
+// ---- resume state ----
+
+fn source(stream_id: i32) -> StreamSource {
+    StreamSource { bvid: "BV1xx411c7mD".into(), cid: 42, stream_id, codecs: "avc1.640032".into() }
+}
+
+/// An output path whose `.part` holds `content`, with `state` saved next to it.
+fn partial(name: &str, content: &[u8], state: &DownloadState) -> String {
+    let out = temp_path(name);
+    std::fs::create_dir_all(std::path::Path::new(&out).parent().unwrap()).unwrap();
+    let part = format!("{}.part", out);
+    std::fs::write(&part, content).unwrap();
+    save_state(&part, state).unwrap();
+    out
+}
+
+fn resume_opts(stream_id: i32) -> DownloadOptions {
+    DownloadOptions { resume: true, source: source(stream_id), ..Default::default() }
+}
+
+#[tokio::test]
+async fn resumes_a_matching_part_and_renames_it() {
+    let (base, log) = serve(BODY.to_vec());
+    let out = partial("resume", &BODY[..12], &DownloadState { source: source(80), size: BODY.len() as u64, ranges: None });
+    download_mirrors(&[format!("{}/ok", base)], &out, &resume_opts(80)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+    assert_eq!(*log.lock().unwrap(), [("/ok".to_string(), 12)]);
+    assert!(!std::path::Path::new(&format!("{}.part", out)).exists());
+    assert!(!std::path::Path::new(&format!("{}.part.json", out)).exists());
+
+    // A finished file is only probed, not fetched again
+    download_mirrors(&[format!("{}/ok", base)], &out, &resume_opts(80)).await.unwrap();
+    assert_eq!(log.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn an_unverified_final_file_is_downloaded_again() {
+    let (base, _) = serve(BODY.to_vec());
+    // Truncated by an earlier crash
+    let out = temp_path("resume-short");
+    std::fs::create_dir_all(std::path::Path::new(&out).parent().unwrap()).unwrap();
+    std::fs::write(&out, &BODY[..12]).unwrap();
+    download_mirrors(&[format!("{}/ok", base)], &out, &resume_opts(80)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+
+    // Right size, but a partial download is still pending
+    let out = partial("resume-pending", &BODY[..12], &DownloadState { source: source(80), size: BODY.len() as u64, ranges: None });
+    std::fs::write(&out, vec![b'X'; BODY.len()]).unwrap();
+    download_mirrors(&[format!("{}/ok", base)], &out, &resume_opts(80)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+}
+
+#[tokio::test]
+async fn restarts_a_part_of_another_stream_or_size() {
+    let (base, log) = serve(BODY.to_vec());
+    let stale = DownloadState { source: source(64), size: BODY.len() as u64, ranges: None };
+    let out = partial("resume-other", b"XXXXXXXXXXXX", &stale);
+    download_mirrors(&[format!("{}/ok", base)], &out, &resume_opts(80)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+
+    let resized = DownloadState { source: source(80), size: 1000, ranges: None };
+    let out = partial("resume-size", b"XXXXXXXXXXXX", &resized);
+    download_mirrors(&[format!("{}/ok", base)], &out, &resume_opts(80)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+    // A part longer than the file now served: 416, then a plain request
+    let longer = [BODY, b"XX"].concat();
+    let out = partial("resume-416", &longer, &resized);
+    download_mirrors(&[format!("{}/ok", base)], &out, &resume_opts(80)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+    // Stale state: a plain request. Size change: a ranged request, then a plain one
+    let starts: Vec<u64> = log.lock().unwrap().iter().map(|(_, s)| *s).collect();
+    assert_eq!(starts, [0, 12, 0, BODY.len() as u64 + 2, 0]);
+}
+
+#[tokio::test]
+async fn a_server_ignoring_range_restarts_the_part() {
+    let (base, _) = serve(BODY.to_vec());
+    let out = partial("resume-norange", b"XXXXXXXXXXXX", &DownloadState { source: source(80), size: BODY.len() as u64, ranges: None });
+    download_mirrors(&[format!("{}/norange", base)], &out, &resume_opts(80)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+}
+
+// ---- expired URLs ----
+
+#[test]
+fn reads_the_url_deadline() {
+    let url = "https://upos-sz-mirrorcos.bilivideo.com/upgcxcode/x.m4s?e=ig8&deadline=1700000000&gen=playurlv2";
+    assert!(!url_expired(url, 1_699_999_999));
+    assert!(url_expired(url, 1_700_000_000));
+    assert!(!url_expired("https://cdn/x.m4s", u64::MAX));
+}
+
+/// A refresher returning `urls` and counting its calls.
+fn refresher(urls: Vec<String>) -> (Refresher, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let refresh: Refresher = Arc::new(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+        let urls = urls.clone();
+        Box::pin(async move { Ok(urls) })
+    });
+    (refresh, calls)
+}
+
+#[tokio::test]
+async fn refreshes_expired_urls_and_continues() {
+    let (base, log) = serve(BODY.to_vec());
+    let out = temp_path("refresh");
+    let (refresh, calls) = refresher(vec![format!("{}/ok", base)]);
+    let opts = DownloadOptions { refresh: Some(refresh), ..Default::default() };
+    // The transfer breaks off and the backup mirror has expired as well
+    download_mirrors(&[format!("{}/cut", base), format!("{}/forbidden", base)], &out, &opts).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), BODY);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    let expected = [("/cut", 0), ("/forbidden", 10), ("/ok", 10)].map(|(p, s)| (p.to_string(), s));
+    assert_eq!(*log.lock().unwrap(), expected);
+}
+
+#[tokio::test]
+async fn refreshes_urls_past_their_deadline_up_front() {
+    let (base, log) = serve(BODY.to_vec());
+    let out = temp_path("refresh-deadline");
+    let (refresh, calls) = refresher(vec![format!("{}/ok", base)]);
+    let opts = DownloadOptions { refresh: Some(refresh), ..Default::default() };
+    download_mirrors(&[format!("{}/ok?deadline=1", base)], &out, &opts).await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(*log.lock().unwrap(), [("/ok".to_string(), 0)]);
+}
+
+#[tokio::test]
+async fn gives_up_when_refreshed_urls_keep_failing() {
+    let (base, _) = serve(BODY.to_vec());
+    let out = temp_path("refresh-fail");
+    let (refresh, calls) = refresher(vec![format!("{}/forbidden", base)]);
+    let opts = DownloadOptions { refresh: Some(refresh), ..Default::default() };
+    let err = download_mirrors(&[format!("{}/forbidden", base)], &out, &opts).await.unwrap_err();
+    assert!(err.to_string().contains("403"));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
@@ -1,17 +1,19 @@
-use bilibili_dl::util::{parse_format, expand_template, sanitize_filename};
+use bilibili_dl::util::{parse_batch_lines, parse_format, parse_page_spec, expand_template, expand_template_fields, sanitize_filename, TemplateFields};
 use bilibili_dl::bilibili::{select_streams_with_format, Dash, DashVideo, DashAudio};
 
 fn sample_dash() -> Dash {
     Dash {
         video: vec![
-            DashVideo { id: 120, base_url: "v2160_avc1".into(), codecs: "avc1.640032".into(), height: Some(2160), bandwidth: Some(12_000_000) },
-            DashVideo { id: 80, base_url: "v1080_av01".into(), codecs: "av01.0.08M.10".into(), height: Some(1080), bandwidth: Some(5_000_000) },
-            DashVideo { id: 64, base_url: "v720_hev1".into(), codecs: "hev1.1.6.L123".into(), height: Some(720), bandwidth: Some(3_000_000) },
+            DashVideo { id: 120, base_url: "v2160_avc1".into(), codecs: "avc1.640032".into(), height: Some(2160), bandwidth: Some(12_000_000), ..Default::default() },
+            DashVideo { id: 80, base_url: "v1080_av01".into(), codecs: "av01.0.08M.10".into(), height: Some(1080), bandwidth: Some(5_000_000), ..Default::default() },
+            DashVideo { id: 64, base_url: "v720_hev1".into(), codecs: "hev1.1.6.L123".into(), height: Some(720), bandwidth: Some(3_000_000), ..Default::default() },
         ],
         audio: Some(vec![
-            DashAudio { id: 30216, base_url: "a128".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(128_000) },
-            DashAudio { id: 30232, base_url: "a320".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(320_000) },
+            DashAudio { id: 30216, base_url: "a128".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(128_000), ..Default::default() },
+            DashAudio { id: 30232, base_url: "a320".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(320_000), ..Default::default() },
         ]),
+        dolby: None,
+        flac: None,
     }
 }
 
@@ -50,3 +52,59 @@ fn template_and_sanitize() {
     assert_eq!(stem, "A_B___bad__name__-BVabc");
     assert_eq!(sanitize_filename("  .x.  "), "x");
 }
+
+#[test]
+fn page_spec_lists_and_ranges() {
+    assert_eq!(parse_page_spec("3", 40).unwrap(), vec![3]);
+    assert_eq!(parse_page_spec("1-5,8,10-", 12).unwrap(), vec![1, 2, 3, 4, 5, 8, 10, 11, 12]);
+    // overlapping entries are merged and sorted
+    assert_eq!(parse_page_spec("4,2-4, 1", 5).unwrap(), vec![1, 2, 3, 4]);
+    // open range clamps to the last page
+    assert_eq!(parse_page_spec("38-50", 40).unwrap(), vec![38, 39, 40]);
+}
+
+#[test]
+fn page_spec_rejects_invalid() {
+    assert!(parse_page_spec("0", 3).is_err());
+    assert!(parse_page_spec("7", 3).is_err());
+    assert!(parse_page_spec("3-1", 5).is_err());
+    assert!(parse_page_spec("a-b", 5).is_err());
+    assert!(parse_page_spec("5-", 3).is_err());
+}
+
+#[test]
+fn template_page_fields() {
+    let fields = TemplateFields {
+        title: "Lecture".into(),
+        id: "BVabc".into(),
+        aid: 7,
+        cid: 42,
+        page: 3,
+        page_title: "Part: three".into(),
+        ..Default::default()
+    };
+    let stem = expand_template_fields("%(title)s/%(page)s - %(page_title)s.%(ext)s", &fields, "mp4");
+    assert_eq!(stem, "Lecture/3 - Part_ three");
+}
+
+#[test]
+fn template_episode_fields() {
+    let fields = TemplateFields {
+        title: "Show".into(),
+        id: "BV1xx411c7mQ".into(),
+        season: "Show S2".into(),
+        season_id: 456,
+        episode: "3 The Return".into(),
+        episode_id: 123,
+        episode_number: 3,
+        ..Default::default()
+    };
+    let stem = expand_template_fields("%(season)s E%(episode_number)s %(episode)s [ss%(season_id)s ep%(episode_id)s]", &fields, "mp4");
+    assert_eq!(stem, "Show S2 E3 3 The Return [ss456 ep123]");
+}
+
+#[test]
+fn batch_lines_skip_comments_and_blanks() {
+    let text = "# nightly list\nBV17x411w7KC\n\n  av170001  \n# https://space.bilibili.com/2\nhttps://www.bilibili.com/bangumi/play/ep123\n";
+    assert_eq!(parse_batch_lines(text), vec!["BV17x411w7KC", "av170001", "https://www.bilibili.com/bangumi/play/ep123"]);
+}
@@ -0,0 +1,94 @@
+use bilibili_dl::bilibili::{DashAudio, VideoInfo, VideoPage, ViewPoint};
+use bilibili_dl::metadata::{chapters_from_view_points, mux_tags, InfoJson};
+use bilibili_dl::subtitles::iso639_2;
+
+const VIEW_JSON: &str = r#"{
+    "bvid": "BV17x411w7KC", "aid": 170001, "title": "Sample", "desc": "a description",
+    "pic": "http://i0.hdslb.com/bfs/archive/cover.jpg", "tname": "Music", "copyright": 1,
+    "pubdate": 1300000000, "ctime": 1299999000, "duration": 300, "dynamic": "",
+    "owner": {"mid": 2, "name": "uploader", "face": "http://i0.hdslb.com/face.jpg"},
+    "stat": {"view": 10, "danmaku": 2, "reply": 3, "favorite": 4, "coin": 5, "share": 6, "like": 7, "his_rank": 0},
+    "pages": [{"cid": 279786, "page": 1, "part": "P1", "duration": 300, "dimension": {"width": 1920, "height": 1080, "rotate": 0}}],
+    "rights": {"download": 1}
+}"#;
+
+#[test]
+fn view_response_keeps_metadata() {
+    let info: VideoInfo = serde_json::from_str(VIEW_JSON).expect("parse view data");
+    assert_eq!(info.desc, "a description");
+    assert_eq!(info.owner.name, "uploader");
+    assert_eq!(info.stat.like, 7);
+    assert_eq!(info.pages[0].duration, 300);
+    assert!(info.ugc_season.is_none());
+    assert!(info.tags.is_empty());
+}
+
+#[test]
+fn info_json_includes_selected_formats() {
+    let info: VideoInfo = serde_json::from_str(VIEW_JSON).unwrap();
+    let page = VideoPage {
+        bvid: info.bvid.clone(),
+        aid: info.aid,
+        cid: 279786,
+        page: 1,
+        title: info.title.clone(),
+        page_title: "P1".into(),
+        page_count: 1,
+        duration: 300,
+        episode: None,
+    };
+    let audio = DashAudio { id: 30280, base_url: "a320".into(), codecs: "mp4a.40.2".into(), bandwidth: Some(320_000), ..Default::default() };
+    let json = serde_json::to_value(InfoJson::new(&page, &info, None, Some(&audio))).unwrap();
+    assert_eq!(json["id"], "BV17x411w7KC");
+    assert_eq!(json["info"]["owner"]["mid"], 2);
+    assert_eq!(json["info"]["pubdate"], 1300000000);
+    assert_eq!(json["audio_format"]["id"], 30280);
+    assert!(json["video_format"].is_null());
+    assert!(json.get("episode").is_none());
+}
+
+#[test]
+fn mux_tags_from_view_data() {
+    let info: VideoInfo = serde_json::from_str(VIEW_JSON).unwrap();
+    let page = VideoPage {
+        bvid: info.bvid.clone(),
+        aid: info.aid,
+        cid: 279786,
+        page: 2,
+        title: info.title.clone(),
+        page_title: "Part two".into(),
+        page_count: 2,
+        duration: 300,
+        episode: None,
+    };
+    let tags = mux_tags(&info, &page);
+    let get = |k: &str| tags.iter().find(|(key, _)| key == k).map(|(_, v)| v.as_str());
+    assert_eq!(get("title"), Some("Sample - Part two"));
+    assert_eq!(get("artist"), Some("uploader"));
+    assert_eq!(get("date"), Some("2011-03-13"));
+    assert_eq!(get("comment"), Some("https://www.bilibili.com/video/BV17x411w7KC?p=2"));
+    assert_eq!(get("album"), None);
+}
+
+#[test]
+fn chapters_keep_view_point_chapters_only() {
+    let points: Vec<ViewPoint> = serde_json::from_str(
+        r#"[{"type":2,"from":0,"to":60,"content":"Intro"},
+            {"type":1,"from":30,"to":40,"content":"highlight"},
+            {"type":2,"from":60,"to":400,"content":"Main"},
+            {"type":2,"from":300,"to":420,"content":"Past the end"}]"#,
+    )
+    .unwrap();
+    let chapters = chapters_from_view_points(&points, 300);
+    assert_eq!(chapters.len(), 2);
+    assert_eq!((chapters[0].start_ms, chapters[0].end_ms, chapters[0].title.as_str()), (0, 60_000, "Intro"));
+    assert_eq!((chapters[1].start_ms, chapters[1].end_ms), (60_000, 300_000));
+}
+
+#[test]
+fn subtitle_languages_map_to_iso639_2() {
+    assert_eq!(iso639_2("zh-Hans"), "chi");
+    assert_eq!(iso639_2("ai-zh"), "chi");
+    assert_eq!(iso639_2("en-US"), "eng");
+    assert_eq!(iso639_2("xx"), "und");
+}
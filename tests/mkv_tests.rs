@@ -0,0 +1,195 @@
+use bilibili_dl::downloader::Chapter;
+use bilibili_dl::mkv::{parse_ass, parse_srt, write_mkv, MkvOptions, MkvSubtitle, SubtitleEvent};
+use std::io::Cursor;
+
+#[path = "common/mp4.rs"]
+mod mp4;
+use mp4::{bx, fragment, full, init_segment};
+
+// ---- fixture builders: minimal fragmented MP4 tracks with a sample entry ----
+
+fn avc1() -> Vec<u8> {
+    let mut p = vec![0u8; 78];
+    p[24..26].copy_from_slice(&1920u16.to_be_bytes());
+    p[26..28].copy_from_slice(&1080u16.to_be_bytes());
+    p.extend(bx(b"avcC", &[1, 0x64, 0, 0x28, 0xff]));
+    bx(b"avc1", &p)
+}
+
+/// A Dolby Vision profile 8 HEVC entry with its `dvcC` record.
+fn dvh1() -> Vec<u8> {
+    let mut p = vec![0u8; 78];
+    p[24..26].copy_from_slice(&3840u16.to_be_bytes());
+    p[26..28].copy_from_slice(&2160u16.to_be_bytes());
+    p.extend(bx(b"hvcC", &[1, 2, 0x20, 0, 0]));
+    p.extend(bx(b"dvcC", &[1, 0, 0x10, 0x35, 0x10, 0, 0, 0]));
+    bx(b"dvh1", &p)
+}
+
+fn mp4a() -> Vec<u8> {
+    let mut p = vec![0u8; 28];
+    p[16..18].copy_from_slice(&2u16.to_be_bytes());
+    p[24..28].copy_from_slice(&(48000u32 << 16).to_be_bytes());
+    // ES_Descriptor > DecoderConfigDescriptor > DecoderSpecificInfo
+    let dsi = [0x05, 2, 0x11, 0x90];
+    let dcd = [&[0x04, 13 + dsi.len() as u8, 0x40, 0x15][..], &[0; 11], &dsi].concat();
+    let es = [&[0x03, 3 + dcd.len() as u8, 0, 1, 0][..], &dcd].concat();
+    p.extend(bx(b"esds", &full(0, 0, &es)));
+    bx(b"mp4a", &p)
+}
+
+fn video_track() -> Vec<u8> {
+    [
+        init_segment(b"vide", 1000, avc1(), None),
+        fragment(1, Some(0), &[(1000, b"V0"), (1000, b"V1")]),
+        fragment(2, Some(2000), &[(1000, b"V2")]),
+    ]
+    .concat()
+}
+
+fn audio_track() -> Vec<u8> {
+    [init_segment(b"soun", 48000, mp4a(), None), fragment(1, Some(0), &[(72000, b"A0"), (72000, b"A1")])].concat()
+}
+
+// ---- a small EBML reader ----
+
+fn vint(b: &[u8], pos: usize, keep_marker: bool) -> (u64, usize) {
+    let len = b[pos].leading_zeros() as usize + 1;
+    let mut v = if keep_marker { b[pos] as u64 } else { (b[pos] as u64) & (0xff >> len) };
+    for i in 1..len {
+        v = (v << 8) | b[pos + i] as u64;
+    }
+    (v, len)
+}
+
+fn elements(b: &[u8]) -> Vec<(u64, &[u8])> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < b.len() {
+        let (id, n) = vint(b, pos, true);
+        let (size, m) = vint(b, pos + n, false);
+        let start = pos + n + m;
+        out.push((id, &b[start..start + size as usize]));
+        pos = start + size as usize;
+    }
+    out
+}
+
+fn child(b: &[u8], id: u64) -> &[u8] {
+    elements(b).into_iter().find(|(i, _)| *i == id).map(|(_, d)| d).unwrap()
+}
+
+fn all(b: &[u8], id: u64) -> Vec<&[u8]> {
+    elements(b).into_iter().filter(|(i, _)| *i == id).map(|(_, d)| d).collect()
+}
+
+fn mux(opts: &MkvOptions) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    let (mut v, mut a) = (Cursor::new(video_track()), Cursor::new(audio_track()));
+    write_mkv(&mut [&mut v, &mut a], &mut out, opts).unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn writes_header_tracks_and_blocks() {
+    let out = mux(&MkvOptions::default());
+    let top = elements(&out);
+    assert_eq!(top.len(), 2);
+    assert_eq!(top[0].0, 0x1A45_DFA3);
+    assert_eq!(std::str::from_utf8(child(top[0].1, 0x4282)).unwrap(), "matroska");
+    let segment = top[1].1;
+
+    let tracks: Vec<&[u8]> = all(child(segment, 0x1654_AE6B), 0xAE);
+    let codecs: Vec<&str> = tracks.iter().map(|t| std::str::from_utf8(child(t, 0x86)).unwrap()).collect();
+    assert_eq!(codecs, ["V_MPEG4/ISO/AVC", "A_AAC"]);
+    assert_eq!(child(tracks[0], 0x63A2), &[1, 0x64, 0, 0x28, 0xff]);
+    assert_eq!(child(tracks[1], 0x63A2), &[0x11, 0x90]);
+    assert_eq!(child(child(tracks[0], 0xE0), 0xB0), &1920u16.to_be_bytes());
+
+    // Every sample lands in a SimpleBlock: (track, timecode relative to the cluster, data)
+    let mut blocks = Vec::new();
+    for cluster in all(segment, 0x1F43_B675) {
+        let base = child(cluster, 0xE7).iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        for block in all(cluster, 0xA3) {
+            let rel = i16::from_be_bytes([block[1], block[2]]) as u64;
+            blocks.push((block[0] & 0x7f, base + rel, std::str::from_utf8(&block[4..]).unwrap()));
+        }
+    }
+    let expected = [(1, 0, "V0"), (2, 0, "A0"), (1, 1000, "V1"), (2, 1500, "A1"), (1, 2000, "V2")];
+    assert_eq!(blocks, expected);
+
+    // SeekHead points at Cues, written last
+    let cues = elements(segment).last().unwrap().0;
+    assert_eq!(cues, 0x1C53_BB6B);
+}
+
+#[test]
+fn keeps_the_dolby_vision_configuration() {
+    let video = [init_segment(b"vide", 1000, dvh1(), None), fragment(1, Some(0), &[(1000, b"V0")])].concat();
+    let mut out = Cursor::new(Vec::new());
+    let (mut v, mut a) = (Cursor::new(video), Cursor::new(audio_track()));
+    write_mkv(&mut [&mut v, &mut a], &mut out, &MkvOptions::default()).unwrap();
+    let out = out.into_inner();
+    let segment = elements(&out)[1].1;
+    let track = all(child(segment, 0x1654_AE6B), 0xAE)[0];
+    assert_eq!(child(track, 0x86), b"V_MPEGH/ISO/HEVC");
+    let mapping = child(track, 0x41E4);
+    assert_eq!(child(mapping, 0x41E7), b"dvcC");
+    assert_eq!(child(mapping, 0x41ED), &[1, 0, 0x10, 0x35, 0x10, 0, 0, 0]);
+}
+
+#[test]
+fn writes_subtitle_tracks_and_chapters() {
+    let opts = MkvOptions {
+        title: Some("Title".into()),
+        subtitles: vec![MkvSubtitle {
+            language: "chi".into(),
+            name: "Danmaku".into(),
+            ass_header: Some("[Script Info]\n".into()),
+            events: vec![SubtitleEvent { start_ms: 500, end_ms: 1500, text: "0,0,Default,,0,0,0,,hi".into() }],
+        }],
+        chapters: vec![Chapter { start_ms: 0, end_ms: 1000, title: "Intro".into() }],
+        cover: None,
+    };
+    let out = mux(&opts);
+    let segment = elements(&out)[1].1;
+    let tracks = all(child(segment, 0x1654_AE6B), 0xAE);
+    assert_eq!(tracks.len(), 3);
+    assert_eq!(child(tracks[2], 0x86), b"S_TEXT/ASS");
+    assert_eq!(child(tracks[2], 0x22B59C), b"chi");
+    assert_eq!(child(tracks[2], 0x63A2), b"[Script Info]\n");
+
+    let groups: Vec<&[u8]> = all(segment, 0x1F43_B675).into_iter().flat_map(|c| all(c, 0xA0)).collect();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(&child(groups[0], 0xA1)[4..], b"0,0,Default,,0,0,0,,hi");
+    assert_eq!(child(groups[0], 0x9B), &[0x03, 0xE8]);
+
+    let edition = child(child(segment, 0x1043_A770), 0x45B9);
+    let atom = child(edition, 0xB6);
+    assert_eq!(child(child(atom, 0x80), 0x85), b"Intro");
+}
+
+#[test]
+fn parses_srt_and_vtt_cues() {
+    let srt = "1\r\n00:00:01,000 --> 00:00:02,500\r\nHello\r\nworld\r\n\r\n2\r\n00:01:00,000 --> 00:01:01,000\r\nBye\r\n";
+    let events = parse_srt(srt);
+    assert_eq!(events.len(), 2);
+    assert_eq!((events[0].start_ms, events[0].end_ms, events[0].text.as_str()), (1000, 2500, "Hello\nworld"));
+    assert_eq!(events[1].start_ms, 60_000);
+
+    let vtt = "WEBVTT\n\n00:05.250 --> 00:06.000 align:start\nHi\n";
+    let events = parse_srt(vtt);
+    assert_eq!((events[0].start_ms, events[0].end_ms, events[0].text.as_str()), (5250, 6000, "Hi"));
+}
+
+#[test]
+fn splits_ass_into_header_and_events() {
+    let ass = "[Script Info]\nTitle: x\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue: 1,0:00:01.50,0:00:03.00,R2L,,0,0,0,,{\\move(1,2,3,4)}a, b\n";
+    let (header, events) = parse_ass(ass);
+    let header = header.unwrap();
+    assert!(header.contains("[Events]\nFormat:"));
+    assert!(!header.contains("Dialogue"));
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].start_ms, events[0].end_ms), (1500, 3000));
+    assert_eq!(events[0].text, "0,1,R2L,,0,0,0,,{\\move(1,2,3,4)}a, b");
+}
@@ -0,0 +1,91 @@
+use bilibili_dl::bilibili::BiliClient;
+use bilibili_dl::playlist::{Playlist, PlaylistEntry, PlaylistQueue, PlaylistSelection, extract_collection, extract_favlist_id, extract_series, extract_space_mid, is_watch_later};
+
+#[test]
+fn space_mid_from_links() {
+    assert_eq!(extract_space_mid("https://space.bilibili.com/2"), Some(2));
+    assert_eq!(extract_space_mid("https://space.bilibili.com/546195/video"), Some(546195));
+    assert_eq!(extract_space_mid("https://space.bilibili.com/546195/upload/video?spm_id_from=333"), Some(546195));
+    assert_eq!(extract_space_mid("https://www.bilibili.com/video/BV17x411w7KC"), None);
+}
+
+#[test]
+fn favlist_id_from_links() {
+    assert_eq!(extract_favlist_id("https://space.bilibili.com/546195/favlist?fid=1052622027&ftype=create"), Some(1052622027));
+    assert_eq!(extract_favlist_id("https://space.bilibili.com/546195/favlist?ftype=create&fid=77"), Some(77));
+    assert_eq!(extract_favlist_id("https://www.bilibili.com/medialist/detail/ml1052622027"), Some(1052622027));
+    assert_eq!(extract_favlist_id("https://space.bilibili.com/546195/favlist"), None);
+    // favlist links are not mistaken for the uploader's video list
+    assert_eq!(extract_space_mid("https://space.bilibili.com/546195/favlist?fid=77"), None);
+}
+
+#[test]
+fn watch_later_links() {
+    assert!(is_watch_later("https://www.bilibili.com/watchlater/#/list"));
+    assert!(is_watch_later("https://www.bilibili.com/list/watchlater?bvid=BV17x411w7KC"));
+    assert!(!is_watch_later("https://www.bilibili.com/video/BV17x411w7KC"));
+}
+
+#[test]
+fn collection_and_series_links() {
+    assert_eq!(extract_collection("https://space.bilibili.com/546195/channel/collectiondetail?sid=1023"), Some((546195, 1023)));
+    assert_eq!(extract_collection("https://space.bilibili.com/546195/lists/1023?type=season"), Some((546195, 1023)));
+    assert_eq!(extract_series("https://space.bilibili.com/546195/channel/seriesdetail?sid=88&ctype=0"), Some((546195, 88)));
+    assert_eq!(extract_series("https://space.bilibili.com/546195/lists/88?type=series"), Some((546195, 88)));
+    assert_eq!(extract_collection("https://space.bilibili.com/546195/channel/seriesdetail?sid=88"), None);
+    assert_eq!(extract_space_mid("https://space.bilibili.com/546195/channel/collectiondetail?sid=1023"), None);
+}
+
+fn sample_playlist(n: usize) -> Playlist {
+    Playlist {
+        title: "list".into(),
+        entries: (1..=n).map(|i| PlaylistEntry::Video { bvid: format!("BV{:010}", i), title: format!("v{}", i) }).collect(),
+        pending: None,
+    }
+}
+
+fn client() -> BiliClient {
+    BiliClient::new("bilibili-dl-test".into(), "https://www.bilibili.com".into(), None, None).unwrap()
+}
+
+#[test]
+fn selection_items_start_end_reverse() {
+    let sel = PlaylistSelection { items: Some("1-3,15,7,20-".into()), ..Default::default() };
+    assert_eq!(sel.indices(16).unwrap(), vec![1, 2, 3, 7, 15]);
+
+    let sel = PlaylistSelection { start: Some(3), end: Some(5), reverse: true, ..Default::default() };
+    assert_eq!(sel.indices(10).unwrap(), vec![5, 4, 3]);
+
+    let sel = PlaylistSelection { end: Some(50), ..Default::default() };
+    assert_eq!(sel.indices(2).unwrap(), vec![1, 2]);
+
+    assert!(PlaylistSelection { items: Some("x".into()), ..Default::default() }.indices(3).is_err());
+}
+
+#[tokio::test]
+async fn queue_yields_selected_entries_with_index() {
+    let client = client();
+    let sel = PlaylistSelection { items: Some("2,4,9".into()), reverse: true, ..Default::default() };
+    let mut queue = PlaylistQueue::new(sample_playlist(5), &sel).unwrap();
+    assert_eq!(queue.len(), 2);
+    let mut got = Vec::new();
+    while let Some((i, e)) = queue.next_entry(&client).await.unwrap() {
+        got.push((i, e.title().to_string()));
+    }
+    assert_eq!(got, vec![(4, "v4".to_string()), (2, "v2".to_string())]);
+}
+
+#[tokio::test]
+async fn queue_stops_at_max_downloads() {
+    let client = client();
+    let sel = PlaylistSelection { max_downloads: Some(2), ..Default::default() };
+    let mut queue = PlaylistQueue::new(sample_playlist(5), &sel).unwrap();
+    let mut seen = 0;
+    while let Some((_, _entry)) = queue.next_entry(&client).await.unwrap() {
+        seen += 1;
+        // the first entry "fails", the rest succeed
+        if seen > 1 { queue.record_download(); }
+    }
+    assert_eq!(seen, 3);
+    assert!(queue.max_reached());
+}
@@ -0,0 +1,101 @@
+use bilibili_dl::downloader::MuxOptions;
+use bilibili_dl::postprocess::{extract_audio_args, itunes_tags, write_flac, AudioFormat, AudioQuality};
+use bilibili_dl::remux::parse_boxes;
+use std::io::Cursor;
+
+#[path = "common/mp4.rs"]
+mod mp4;
+use mp4::{bx, fragment, full, init_segment};
+
+#[test]
+fn resolves_best_and_copyable_formats() {
+    let best = AudioFormat::parse("best").unwrap();
+    assert_eq!(best.resolve("mp4a.40.2"), AudioFormat::M4a);
+    assert_eq!(best.resolve("fLaC"), AudioFormat::Flac);
+    assert_eq!(best.resolve("ec-3").ext(), "m4a");
+    assert!(best.can_copy("ec-3"));
+    assert!(AudioFormat::M4a.can_copy("mp4a.40.2"));
+    assert!(!AudioFormat::Mp3.can_copy("mp4a.40.2"));
+    assert!(!AudioFormat::Flac.can_copy("mp4a.40.2"));
+    assert!(AudioFormat::parse("wav").is_err());
+}
+
+#[test]
+fn parses_audio_quality() {
+    assert_eq!(AudioQuality::parse("0").unwrap(), AudioQuality::Vbr(0));
+    assert_eq!(AudioQuality::parse("192K").unwrap(), AudioQuality::Kbps(192));
+    assert_eq!(AudioQuality::parse("128k").unwrap(), AudioQuality::Kbps(128));
+    assert!(AudioQuality::parse("11").is_err());
+    assert!(AudioQuality::parse("fast").is_err());
+}
+
+#[test]
+fn builds_ffmpeg_args() {
+    let plain = MuxOptions::default();
+    let args = extract_audio_args("a.m4s", "out.m4a", AudioFormat::Best, "mp4a.40.2", AudioQuality::Vbr(5), &plain, None);
+    assert_eq!(args, ["-y", "-i", "a.m4s", "-map", "0:a", "-c:a", "copy", "out.m4a"]);
+
+    let opts = MuxOptions { cover: Some("c.jpg".into()), ..Default::default() };
+    let args = extract_audio_args("a.m4s", "out.mp3", AudioFormat::Mp3, "mp4a.40.2", AudioQuality::Vbr(2), &opts, Some("m.txt"));
+    let expected = [
+        "-y", "-i", "a.m4s", "-i", "c.jpg", "-i", "m.txt", "-map", "0:a", "-map", "1", "-map_metadata", "2", "-map_chapters", "2",
+        "-c:a", "libmp3lame", "-q:a", "2", "-c:v", "copy", "-disposition:v:0", "attached_pic", "-id3v2_version", "3", "out.mp3",
+    ];
+    assert_eq!(args, expected);
+
+    // Opus gets a bitrate and no cover
+    let args = extract_audio_args("a.m4s", "out.opus", AudioFormat::Opus, "mp4a.40.2", AudioQuality::Kbps(96), &opts, None);
+    assert_eq!(args, ["-y", "-i", "a.m4s", "-map", "0:a", "-c:a", "libopus", "-b:a", "96k", "out.opus"]);
+}
+
+// ---- a fragmented MP4 FLAC track ----
+
+const STREAMINFO: [u8; 34] = [0x11; 34];
+
+fn flac_track(frames: &[&[u8]]) -> Vec<u8> {
+    let mut entry = vec![0u8; 28];
+    entry[16..18].copy_from_slice(&2u16.to_be_bytes());
+    entry[24..28].copy_from_slice(&(48000u32 << 16).to_be_bytes());
+    // dfLa: a single, last STREAMINFO block
+    let dfla = full(0, 0, &[&[0x80, 0, 0, 34][..], &STREAMINFO].concat());
+    entry.extend(bx(b"dfLa", &dfla));
+    let samples: Vec<(u32, &[u8])> = frames.iter().map(|f| (4096, *f)).collect();
+    [init_segment(b"soun", 48000, bx(b"fLaC", &entry), None), fragment(1, None, &samples)].concat()
+}
+
+#[test]
+fn unwraps_flac_frames_and_adds_tags() {
+    let mut out = Vec::new();
+    let tags = vec![("title".to_string(), "Song".to_string()), ("track".to_string(), "3".to_string())];
+    write_flac(&mut Cursor::new(flac_track(&[b"F0", b"F1"])), &mut out, &tags).unwrap();
+
+    assert_eq!(&out[..4], b"fLaC");
+    // STREAMINFO is no longer the last block
+    assert_eq!(&out[4..8], &[0x00, 0, 0, 34]);
+    assert_eq!(&out[8..42], &STREAMINFO);
+    let comment_len = u32::from_be_bytes([0, out[43], out[44], out[45]]) as usize;
+    assert_eq!(out[42], 0x84);
+    let comment = String::from_utf8_lossy(&out[46..46 + comment_len]);
+    assert!(comment.contains("TITLE=Song"));
+    assert!(comment.contains("TRACKNUMBER=3"));
+    assert_eq!(&out[46 + comment_len..], b"F0F1");
+}
+
+#[test]
+fn builds_itunes_tags() {
+    let tags = vec![("title".to_string(), "Song".to_string()), ("track".to_string(), "3".to_string()), ("x".to_string(), "y".to_string())];
+    let udta = parse_boxes(&itunes_tags(&tags)).unwrap();
+    assert_eq!(&udta[0].kind, b"udta");
+    let meta = udta[0].children().unwrap();
+    // meta is a full box
+    let meta = parse_boxes(&meta[0].payload[4..]).unwrap();
+    assert_eq!(&meta[0].payload[8..12], b"mdir");
+    let ilst = meta[1].children().unwrap();
+    let items: Vec<(&[u8; 4], Vec<u8>)> = ilst.iter().map(|b| (&b.kind, b.children().unwrap()[0].payload.clone())).collect();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].0, b"\xa9nam");
+    assert_eq!(items[0].1, [&[0, 0, 0, 1, 0, 0, 0, 0][..], b"Song"].concat());
+    assert_eq!(items[1].0, b"trkn");
+    assert_eq!(items[1].1, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0]);
+    assert!(itunes_tags(&[]).is_empty());
+}
@@ -0,0 +1,158 @@
+use bilibili_dl::downloader::{parse_rate, Clock, RateLimiter, RateSchedule, RateWindow};
+use futures_util::future::BoxFuture;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Time only moves when someone sleeps.
+#[derive(Default)]
+struct MockClock {
+    now: Mutex<Duration>,
+    minute: Mutex<u32>,
+}
+
+impl MockClock {
+    fn at(minute: u32) -> Arc<Self> {
+        Arc::new(Self { minute: Mutex::new(minute), ..Default::default() })
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn minute_of_day(&self) -> u32 {
+        *self.minute.lock().unwrap()
+    }
+
+    fn sleep(&self, d: Duration) -> BoxFuture<'static, ()> {
+        *self.now.lock().unwrap() += d;
+        Box::pin(async {})
+    }
+}
+
+fn limiter(limit: &str, schedule: Option<&str>, clock: &Arc<MockClock>) -> RateLimiter {
+    RateLimiter::with_clock(RateSchedule::parse(Some(limit), schedule).unwrap(), clock.clone())
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs_f64()
+}
+
+#[test]
+fn parses_rates() {
+    assert_eq!(parse_rate("2M").unwrap(), 2 * 1024 * 1024);
+    assert_eq!(parse_rate("500k").unwrap(), 500 * 1024);
+    assert_eq!(parse_rate("1.5M").unwrap(), 1536 * 1024);
+    assert_eq!(parse_rate("4096").unwrap(), 4096);
+    assert!(parse_rate("fast").is_err());
+    assert!(parse_rate("2X").is_err());
+    assert!(parse_rate("-1M").is_err());
+}
+
+#[test]
+fn parses_schedules_and_wraps_midnight() {
+    let s = RateSchedule::parse(Some("1M"), Some("01:00-07:00=0, 22:30-00:30=256K")).unwrap();
+    assert_eq!(s.windows[0], RateWindow { start: 60, end: 420, rate: None });
+    assert_eq!(s.rate_at(0), Some(256 * 1024));
+    assert_eq!(s.rate_at(30), Some(1024 * 1024));
+    assert_eq!(s.rate_at(60), None);
+    assert_eq!(s.rate_at(419), None);
+    assert_eq!(s.rate_at(420), Some(1024 * 1024));
+    assert_eq!(s.rate_at(23 * 60), Some(256 * 1024));
+    assert!(!s.is_unlimited());
+    assert!(RateSchedule::parse(None, Some("01:00-07:00=0")).unwrap().is_unlimited());
+    assert!(RateSchedule::parse(None, Some("1-7=0")).is_err());
+    assert!(RateSchedule::parse(None, Some("01:00-25:00=1M")).is_err());
+    assert!(RateSchedule::parse(None, Some("01:00-07:00")).is_err());
+}
+
+#[tokio::test]
+async fn holds_the_rate_after_a_one_second_burst() {
+    let clock = MockClock::at(12 * 60);
+    let l = limiter("1000", None, &clock);
+    for _ in 0..100 {
+        l.acquire(100).await;
+    }
+    // 10 000 bytes at 1000 B/s, the first second's worth without waiting
+    assert!((secs(clock.elapsed()) - 9.0).abs() < 1e-6);
+}
+
+#[tokio::test]
+async fn reads_larger_than_the_bucket_wait_their_share() {
+    let clock = MockClock::at(0);
+    let l = limiter("1000", None, &clock);
+    l.acquire(1000).await;
+    l.acquire(5000).await;
+    assert!((secs(clock.elapsed()) - 5.0).abs() < 1e-6);
+}
+
+/// Tokio's clock, so the sleeps of concurrent downloads overlap under paused time.
+struct TokioClock(tokio::time::Instant);
+
+impl Clock for TokioClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    fn minute_of_day(&self) -> u32 {
+        0
+    }
+
+    fn sleep(&self, d: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(d))
+    }
+}
+
+/// Runs two downloads of 10 000 bytes each, read in 200-byte chunks, as separate tasks.
+async fn two_downloads(a: Arc<RateLimiter>, b: Arc<RateLimiter>) -> Duration {
+    let start = tokio::time::Instant::now();
+    let track = |l: Arc<RateLimiter>| {
+        tokio::spawn(async move {
+            for _ in 0..50 {
+                l.acquire(200).await;
+            }
+        })
+    };
+    let (a, b) = (track(a), track(b));
+    a.await.unwrap();
+    b.await.unwrap();
+    start.elapsed()
+}
+
+fn tokio_limiter(limit: &str) -> Arc<RateLimiter> {
+    let clock = Arc::new(TokioClock(tokio::time::Instant::now()));
+    Arc::new(RateLimiter::with_clock(RateSchedule::parse(Some(limit), None).unwrap(), clock))
+}
+
+#[tokio::test(start_paused = true)]
+async fn concurrent_downloads_share_the_bucket() {
+    // 20 000 bytes in total through one bucket
+    let shared = tokio_limiter("1000");
+    let elapsed = two_downloads(shared.clone(), shared).await;
+    assert!((secs(elapsed) - 19.0).abs() < 0.01, "{elapsed:?}");
+
+    // With a bucket each, both get the full rate
+    let elapsed = two_downloads(tokio_limiter("1000"), tokio_limiter("1000")).await;
+    assert!((secs(elapsed) - 9.0).abs() < 0.01, "{elapsed:?}");
+}
+
+#[tokio::test]
+async fn follows_the_schedule() {
+    let clock = MockClock::at(3 * 60);
+    let l = limiter("1000", Some("01:00-07:00=0"), &clock);
+    for _ in 0..100 {
+        l.acquire(10_000).await;
+    }
+    assert_eq!(clock.elapsed(), Duration::ZERO);
+
+    // Past 07:00 the limit applies again, starting from an empty bucket
+    *clock.minute.lock().unwrap() = 7 * 60;
+    l.acquire(1000).await;
+    l.acquire(1000).await;
+    assert!((secs(clock.elapsed()) - 2.0).abs() < 1e-6);
+}
@@ -0,0 +1,127 @@
+use bilibili_dl::remux::{find_box, parse_boxes, remux_fmp4, Mp4Box};
+use std::io::Cursor;
+
+#[path = "common/mp4.rs"]
+mod mp4;
+use mp4::{bx, fragment, init_segment};
+
+// ---- fixtures: minimal fragmented MP4 tracks ----
+
+fn video_track() -> Vec<u8> {
+    [
+        init_segment(b"vide", 16000, bx(b"avc1", &[0; 28]), Some((4000, 0))),
+        fragment(1, Some(0), &[(16000, b"V0a"), (16000, b"V0b")]),
+        fragment(2, Some(32000), &[(32000, b"V1")]),
+    ]
+    .concat()
+}
+
+fn audio_track() -> Vec<u8> {
+    // No tfdt: decode times come from the running sample durations (0s, 1.5s, 3s).
+    [
+        init_segment(b"soun", 48000, bx(b"mp4a", &[0; 28]), Some((4000, 0))),
+        fragment(1, None, &[(72000, b"A0")]),
+        fragment(2, None, &[(72000, b"A1")]),
+        fragment(3, None, &[(48000, b"A2")]),
+    ]
+    .concat()
+}
+
+fn u32_at(b: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(b[pos..pos + 4].try_into().unwrap())
+}
+
+fn children(b: &Mp4Box) -> Vec<Mp4Box> {
+    b.children().unwrap()
+}
+
+fn remux(video: Vec<u8>, audio: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::new();
+    remux_fmp4(&mut Cursor::new(video), &mut Cursor::new(audio), &mut out).unwrap();
+    out
+}
+
+/// The `mdat` bytes of track `index` (0-based) in sample order, read through its `stco`/`stsc`/`stsz`.
+fn track_samples(out: &[u8], traks: &[&Mp4Box], index: usize) -> Vec<String> {
+    let mdia = children(find_box(&children(traks[index]), b"mdia").unwrap());
+    let stbl = children(find_box(&children(find_box(&mdia, b"minf").unwrap()), b"stbl").unwrap());
+    let payload = |kind: &[u8; 4]| find_box(&stbl, kind).unwrap().payload.clone();
+    let (stco, stsc, stsz) = (payload(b"stco"), payload(b"stsc"), payload(b"stsz"));
+    let sizes: Vec<usize> = (0..u32_at(&stsz, 8) as usize).map(|i| u32_at(&stsz, 12 + i * 4) as usize).collect();
+    let runs: Vec<(u32, u32)> = (0..u32_at(&stsc, 4) as usize).map(|i| (u32_at(&stsc, 8 + i * 12), u32_at(&stsc, 12 + i * 12))).collect();
+    let mut samples = Vec::new();
+    for chunk in 0..u32_at(&stco, 4) as usize {
+        let per_chunk = runs.iter().rev().find(|(first, _)| *first as usize <= chunk + 1).unwrap().1;
+        let mut pos = u32_at(&stco, 8 + chunk * 4) as usize;
+        for _ in 0..per_chunk {
+            let size = sizes[samples.len()];
+            samples.push(String::from_utf8(out[pos..pos + size].to_vec()).unwrap());
+            pos += size;
+        }
+    }
+    samples
+}
+
+#[test]
+fn writes_a_progressive_mp4_with_both_tracks() {
+    let out = remux(video_track(), audio_track());
+    let top = parse_boxes(&out).unwrap();
+    let kinds: Vec<&[u8; 4]> = top.iter().map(|b| &b.kind).collect();
+    assert_eq!(kinds, [b"ftyp", b"moov", b"mdat"]);
+
+    let moov = children(&top[1]);
+    assert!(find_box(&moov, b"mvex").is_none());
+    let mvhd = find_box(&moov, b"mvhd").unwrap();
+    assert_eq!(u32_at(&mvhd.payload, 12), 1000);
+    assert_eq!(u32_at(&mvhd.payload, 16), 4000);
+    assert_eq!(u32_at(&mvhd.payload, mvhd.payload.len() - 4), 3);
+
+    let traks: Vec<&Mp4Box> = moov.iter().filter(|b| &b.kind == b"trak").collect();
+    assert_eq!(traks.len(), 2);
+    let tkhd_id = |t: &Mp4Box| u32_at(&find_box(&children(t), b"tkhd").unwrap().payload, 12);
+    assert_eq!((tkhd_id(traks[0]), tkhd_id(traks[1])), (1, 2));
+    let timescale = |t: &Mp4Box| {
+        let mdia = children(find_box(&children(t), b"mdia").unwrap());
+        u32_at(&find_box(&mdia, b"mdhd").unwrap().payload, 12)
+    };
+    assert_eq!((timescale(traks[0]), timescale(traks[1])), (16000, 48000));
+}
+
+#[test]
+fn samples_are_interleaved_and_offsets_stay_valid() {
+    let out = remux(video_track(), audio_track());
+    let moov = children(&parse_boxes(&out).unwrap()[1]);
+    let traks: Vec<&Mp4Box> = moov.iter().filter(|b| &b.kind == b"trak").collect();
+    assert_eq!(track_samples(&out, &traks, 0), ["V0a", "V0b", "V1"]);
+    assert_eq!(track_samples(&out, &traks, 1), ["A0", "A1", "A2"]);
+
+    // Half-second buckets by decode time, video first on ties
+    let mdat = &out[out.len() - 14..];
+    assert_eq!(mdat, b"V0aA0V0bA1V1A2");
+}
+
+#[test]
+fn rejects_non_fragmented_input() {
+    let plain = init_segment(b"vide", 16000, bx(b"avc1", &[0; 28]), Some((4000, 0)));
+    let mut out = Vec::new();
+    let err = remux_fmp4(&mut Cursor::new(plain), &mut Cursor::new(audio_track()), &mut out).unwrap_err();
+    assert!(err.to_string().contains("not a fragmented MP4"));
+}
+
+#[test]
+fn keeps_the_edit_list_start() {
+    // 1024 samples of AAC priming at 48 kHz
+    let audio = [
+        init_segment(b"soun", 48000, bx(b"mp4a", &[0; 28]), Some((3979, 1024))),
+        fragment(1, Some(0), &[(72000, b"A0"), (120000, b"A1")]),
+    ]
+    .concat();
+    let out = remux(video_track(), audio);
+    let moov = children(&parse_boxes(&out).unwrap()[1]);
+    let traks: Vec<&Mp4Box> = moov.iter().filter(|b| &b.kind == b"trak").collect();
+    assert!(find_box(&children(traks[0]), b"edts").is_none());
+    let edts = children(find_box(&children(traks[1]), b"edts").unwrap());
+    let elst = &find_box(&edts, b"elst").unwrap().payload;
+    // one entry: 4 s minus the priming in ms, media_time 1024, rate 1.0
+    assert_eq!((u32_at(elst, 4), u32_at(elst, 8), u32_at(elst, 12), u32_at(elst, 16)), (1, 3978, 1024, 0x0001_0000));
+}
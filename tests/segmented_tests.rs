@@ -0,0 +1,138 @@
+use bilibili_dl::downloader::{download_mirrors, save_state, DownloadOptions, DownloadState, Refresher, StreamSource};
+use bilibili_dl::segmented::{plan_fragments, ByteRanges, MIN_FRAGMENT};
+
+use std::sync::Arc;
+
+mod common;
+use common::{serve, temp_path};
+
+#[test]
+fn merges_ranges_and_finds_gaps() {
+    let mut r = ByteRanges::default();
+    r.insert(10, 20);
+    r.insert(30, 40);
+    r.insert(20, 25);
+    r.insert(5, 5);
+    assert_eq!(r.ranges(), &[(10, 25), (30, 40)]);
+    assert_eq!(r.covered(), 25);
+    assert_eq!(r.missing(50), vec![(0, 10), (25, 30), (40, 50)]);
+    r.insert(0, 50);
+    assert!(r.missing(50).is_empty());
+}
+
+#[test]
+fn state_round_trips_as_json() {
+    let mut r = ByteRanges::default();
+    r.insert(0, 100);
+    r.insert(200, 300);
+    let state = DownloadState { source: StreamSource { bvid: "BV1xx".into(), cid: 7, stream_id: 80, codecs: "hev1".into() }, size: 1000, ranges: Some(r) };
+    let json = serde_json::to_string(&state).unwrap();
+    assert_eq!(json, r#"{"source":{"bvid":"BV1xx","cid":7,"stream_id":80,"codecs":"hev1"},"size":1000,"ranges":[[0,100],[200,300]]}"#);
+    assert_eq!(serde_json::from_str::<DownloadState>(&json).unwrap(), state);
+}
+
+#[test]
+fn plans_fragments_per_worker() {
+    let total = 16 * MIN_FRAGMENT;
+    let plan = plan_fragments(&[(0, total)], total, 2);
+    assert_eq!(plan.len(), 8);
+    assert_eq!(plan[1], (2 * MIN_FRAGMENT, 4 * MIN_FRAGMENT));
+    // Never below the minimum, and gaps are kept apart
+    let plan = plan_fragments(&[(0, 100), (MIN_FRAGMENT, 3 * MIN_FRAGMENT + 5)], 4 * MIN_FRAGMENT, 8);
+    assert_eq!(plan, vec![(0, 100), (MIN_FRAGMENT, 2 * MIN_FRAGMENT), (2 * MIN_FRAGMENT, 3 * MIN_FRAGMENT), (3 * MIN_FRAGMENT, 3 * MIN_FRAGMENT + 5)]);
+}
+
+fn body() -> Vec<u8> {
+    (0..3 * MIN_FRAGMENT + 1234).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+fn options(n: usize, resume: bool) -> DownloadOptions {
+    DownloadOptions { concurrent_fragments: n, resume, ..Default::default() }
+}
+
+/// Range starts of the requests to `path`, skipping the size probe.
+fn starts(log: &common::RequestLog, path: &str) -> Vec<u64> {
+    let mut s: Vec<u64> = log.lock().unwrap().iter().filter(|(p, _)| p == path).skip(1).map(|(_, s)| *s).collect();
+    s.sort_unstable();
+    s
+}
+
+#[tokio::test]
+async fn downloads_ranges_concurrently() {
+    let (base, log) = serve(body());
+    let out = temp_path("segmented");
+    download_mirrors(&[format!("{}/ok", base)], &out, &options(4, false)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), body());
+    assert_eq!(starts(&log, "/ok"), vec![0, MIN_FRAGMENT, 2 * MIN_FRAGMENT, 3 * MIN_FRAGMENT]);
+    assert!(!std::path::Path::new(&format!("{}.part", out)).exists());
+    assert!(!std::path::Path::new(&format!("{}.part.json", out)).exists());
+}
+
+#[tokio::test]
+async fn continues_from_completed_ranges() {
+    let (base, log) = serve(body());
+    let out = temp_path("segmented-resume");
+    std::fs::create_dir_all(std::path::Path::new(&out).parent().unwrap()).unwrap();
+    let total = body().len() as u64;
+    let mut partial = body();
+    partial[MIN_FRAGMENT as usize..].fill(0);
+    let part = format!("{}.part", out);
+    std::fs::write(&part, &partial).unwrap();
+    let mut done = ByteRanges::default();
+    done.insert(0, MIN_FRAGMENT);
+    save_state(&part, &DownloadState { source: StreamSource::default(), size: total, ranges: Some(done) }).unwrap();
+
+    download_mirrors(&[format!("{}/ok", base)], &out, &options(2, true)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), body());
+    assert!(starts(&log, "/ok").iter().all(|&s| s >= MIN_FRAGMENT));
+}
+
+#[tokio::test]
+async fn continues_a_single_stream_part() {
+    let (base, log) = serve(body());
+    let out = temp_path("segmented-resume-single");
+    std::fs::create_dir_all(std::path::Path::new(&out).parent().unwrap()).unwrap();
+    let total = body().len() as u64;
+    let part = format!("{}.part", out);
+    std::fs::write(&part, &body()[..MIN_FRAGMENT as usize]).unwrap();
+    save_state(&part, &DownloadState { source: StreamSource::default(), size: total, ranges: None }).unwrap();
+
+    download_mirrors(&[format!("{}/ok", base)], &out, &options(2, true)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), body());
+    assert!(starts(&log, "/ok").iter().all(|&s| s >= MIN_FRAGMENT));
+}
+
+#[tokio::test]
+async fn fragments_fail_over_at_their_offset() {
+    let (base, log) = serve(body());
+    let out = temp_path("segmented-failover");
+    download_mirrors(&[format!("{}/cut", base), format!("{}/ok", base)], &out, &options(2, false)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), body());
+    // Every fragment got 10 bytes from the first mirror
+    let resumed: Vec<u64> = log.lock().unwrap().iter().filter(|(p, _)| p == "/ok").map(|(_, s)| *s).collect();
+    assert!(!resumed.is_empty());
+    assert!(resumed.iter().all(|s| s % MIN_FRAGMENT == 10));
+}
+
+#[tokio::test]
+async fn falls_back_to_one_stream_without_range_support() {
+    let (base, _) = serve(body());
+    let out = temp_path("segmented-norange");
+    download_mirrors(&[format!("{}/norange", base)], &out, &options(4, false)).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), body());
+}
+
+#[tokio::test]
+async fn refreshes_expired_urls_before_splitting() {
+    let (base, log) = serve(body());
+    let out = temp_path("segmented-refresh");
+    let fresh = vec![format!("{}/ok", base)];
+    let refresh: Refresher = Arc::new(move || {
+        let urls = fresh.clone();
+        Box::pin(async move { Ok(urls) })
+    });
+    let opts = DownloadOptions { refresh: Some(refresh), ..options(4, false) };
+    download_mirrors(&[format!("{}/forbidden", base)], &out, &opts).await.unwrap();
+    assert_eq!(std::fs::read(&out).unwrap(), body());
+    assert_eq!(starts(&log, "/ok").len(), 4);
+}
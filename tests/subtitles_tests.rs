@@ -0,0 +1,51 @@
+use bilibili_dl::subtitles::{select_tracks, to_ass, to_srt, to_vtt, SubFormat, SubtitleBody, SubtitleTrack};
+
+const BODY: &str = r##"{"font_size":0.4,"font_color":"#FFFFFF","background_alpha":0.5,"body":[
+    {"from":0.5,"to":2.25,"sid":1,"location":2,"content":"第一句"},
+    {"from":3661.0,"to":3662.999,"sid":2,"location":2,"content":"second {line}\nwrapped"}
+]}"##;
+
+const TRACKS: &str = r#"[
+    {"id":1,"lan":"zh-Hans","lan_doc":"中文（简体）","subtitle_url":"//aisubtitle.hdslb.com/bfs/subtitle/a.json","type":0},
+    {"id":2,"lan":"en","lan_doc":"English","subtitle_url":"//aisubtitle.hdslb.com/bfs/subtitle/b.json","type":0},
+    {"id":3,"lan":"ai-zh","lan_doc":"中文（自动生成）","subtitle_url":"","type":1}
+]"#;
+
+#[test]
+fn srt_and_vtt_timestamps() {
+    let sub: SubtitleBody = serde_json::from_str(BODY).unwrap();
+    let srt = to_srt(&sub);
+    assert!(srt.starts_with("1\n00:00:00,500 --> 00:00:02,250\n第一句\n\n2\n01:01:01,000 --> 01:01:02,999\n"));
+    let vtt = to_vtt(&sub);
+    assert!(vtt.starts_with("WEBVTT\n\n00:00:00.500 --> 00:00:02.250\n第一句\n"));
+}
+
+#[test]
+fn ass_escapes_overrides_and_newlines() {
+    let sub: SubtitleBody = serde_json::from_str(BODY).unwrap();
+    let ass = to_ass(&sub);
+    assert!(ass.contains("Dialogue: 0,0:00:00.50,0:00:02.25,Default,,0,0,0,,第一句"));
+    assert!(ass.contains("1:01:01.00,1:01:03.00,Default,,0,0,0,,second \\{line\\}\\Nwrapped"));
+}
+
+#[test]
+fn select_tracks_by_language() {
+    let tracks: Vec<SubtitleTrack> = serde_json::from_str(TRACKS).unwrap();
+    assert!(tracks[2].is_ai() && !tracks[0].is_ai());
+    let langs = |s: &str| s.split(',').map(String::from).collect::<Vec<_>>();
+    let pick = |l: &[String]| select_tracks(&tracks, l).iter().map(|t| t.id).collect::<Vec<_>>();
+    assert_eq!(pick(&[]), vec![1, 2, 3]);
+    assert_eq!(pick(&langs("all")), vec![1, 2, 3]);
+    assert_eq!(pick(&langs("EN,ai-zh,fr")), vec![2, 3]);
+    assert!(pick(&langs("ja")).is_empty());
+}
+
+#[test]
+fn sub_format_parse() {
+    assert_eq!(SubFormat::parse("SRT").unwrap(), SubFormat::Srt);
+    assert_eq!(SubFormat::parse("vtt").unwrap().ext(), "vtt");
+    assert!(SubFormat::parse("lrc").is_err());
+    let sub: SubtitleBody = serde_json::from_str(BODY).unwrap();
+    let json = SubFormat::Json.render(&sub).unwrap();
+    assert!(json.contains("\"content\": \"第一句\""));
+}